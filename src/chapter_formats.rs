@@ -0,0 +1,489 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use id3::TagLike;
+use id3::frame::{Chapter, Content, ExtendedLink, Frame, TableOfContents};
+
+use crate::chapters;
+use crate::input::ChapterFormat;
+
+// Chapter files from other tools, converted to and from CHAP frames with a single top-level CTOC
+// frame that lists them in order:
+//
+// - ffmpeg's FFMETADATA, with a [CHAPTER] section for each one, with its own time base.
+// - Podlove Simple Chapters, as JSON, like the Podlove Web Player uses them: an array of objects
+//   with a "start" time, a "title", and an "href".
+// - Plain lists of "HH:MM:SS Title" lines, like the ones in video descriptions.
+//
+// The last two only have start times, so each chapter ends where the next one starts, and the
+// last one ends with the file.
+
+const TABLE_OF_CONTENTS_ID: &str = "toc";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChapterEntry {
+    // In milliseconds
+    pub start: u32,
+    pub end: Option<u32>,
+    pub title: String,
+    pub url: Option<String>,
+}
+
+// Only FFMETADATA has a header, and only JSON starts with a bracket
+pub fn detect_format(contents: &str) -> ChapterFormat {
+    let contents = contents.trim_start_matches('\u{feff}').trim_start();
+
+    if contents.starts_with(";FFMETADATA") {
+        ChapterFormat::FfMetadata
+    } else if contents.starts_with('[') || contents.starts_with('{') {
+        ChapterFormat::Podlove
+    } else {
+        ChapterFormat::Text
+    }
+}
+
+// ffmpeg's own docs name FFMETADATA files "*.txt", but so does everyone else for plain lists, so
+// that's left to --chapters-format.
+pub fn format_from_extension(path: &Path) -> ChapterFormat {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("json") => ChapterFormat::Podlove,
+        Some("txt")  => ChapterFormat::Text,
+        _            => ChapterFormat::FfMetadata,
+    }
+}
+
+pub fn parse(contents: &str, format: ChapterFormat) -> anyhow::Result<Vec<ChapterEntry>> {
+    let contents = contents.trim_start_matches('\u{feff}');
+
+    match format {
+        ChapterFormat::FfMetadata => parse_ffmetadata(contents),
+        ChapterFormat::Podlove    => parse_podlove(contents),
+        ChapterFormat::Text       => parse_text(contents),
+    }
+}
+
+pub fn serialize(entries: &[ChapterEntry], format: ChapterFormat) -> String {
+    match format {
+        ChapterFormat::FfMetadata => serialize_ffmetadata(entries),
+        ChapterFormat::Podlove    => serialize_podlove(entries),
+        ChapterFormat::Text       => serialize_text(entries),
+    }
+}
+
+// Sorts the chapters, fills in missing end times, and checks that they don't overlap or go past
+// the end of the file. The duration is in milliseconds, if it's known.
+pub fn resolve(mut entries: Vec<ChapterEntry>, duration: Option<u32>) -> anyhow::Result<Vec<ChapterEntry>> {
+    entries.sort_by_key(|e| e.start);
+
+    if let (Some(duration), Some(last)) = (duration, entries.last()) {
+        if last.start >= duration {
+            return Err(anyhow!(
+                "Chapter {:?} starts at {}, after the end of the file at {}",
+                last.title, format_time(last.start), format_time(duration),
+            ));
+        }
+    }
+
+    let next_starts = entries.iter().skip(1).map(|e| Some(e.start)).chain([duration]).collect::<Vec<_>>();
+
+    for (entry, next_start) in entries.iter_mut().zip(next_starts) {
+        if entry.end.is_none() {
+            entry.end = Some(next_start.ok_or_else(|| {
+                anyhow!("Can't tell where chapter {:?} ends, since the file's duration is unknown", entry.title)
+            })?);
+        }
+    }
+
+    for (index, entry) in entries.iter().enumerate() {
+        // Unwrap: all end times were just filled in
+        let end = entry.end.unwrap();
+
+        if end <= entry.start {
+            return Err(anyhow!("Chapter {:?} doesn't end after it starts", entry.title));
+        }
+        if let Some(duration) = duration.filter(|d| end > *d) {
+            return Err(anyhow!(
+                "Chapter {:?} ends at {}, after the end of the file at {}",
+                entry.title, format_time(end), format_time(duration),
+            ));
+        }
+        if let Some(next) = entries.get(index + 1).filter(|next| next.start < end) {
+            return Err(anyhow!("Chapters {:?} and {:?} overlap", entry.title, next.title));
+        }
+    }
+
+    Ok(entries)
+}
+
+// Replaces all chapters and tables of contents with the given ones, which need to be resolved
+pub fn write_to_tag(tag: &mut id3::Tag, entries: &[ChapterEntry]) {
+    tag.remove("CHAP");
+    tag.remove("CTOC");
+
+    let mut elements = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let mut frames = vec![Frame::text("TIT2", entry.title.clone())];
+        if let Some(url) = &entry.url {
+            let link = ExtendedLink { description: String::new(), link: url.clone() };
+            frames.push(Frame::with_content("WXXX", Content::ExtendedLink(link)));
+        }
+
+        let element_id = format!("chp{}", index);
+        elements.push(element_id.clone());
+
+        tag.add_frame(Chapter {
+            element_id,
+            start_time:   entry.start,
+            end_time:     entry.end.unwrap_or(entry.start),
+            start_offset: u32::MAX,
+            end_offset:   u32::MAX,
+            frames,
+        });
+    }
+
+    if !elements.is_empty() {
+        tag.add_frame(TableOfContents {
+            element_id: String::from(TABLE_OF_CONTENTS_ID),
+            top_level:  true,
+            ordered:    true,
+            elements,
+            frames:     Vec::new(),
+        });
+    }
+}
+
+// All chapters in the tag, in order of their start times
+pub fn read_from_tag(tag: &id3::Tag) -> Vec<ChapterEntry> {
+    let mut entries = tag.chapters().
+        map(|chapter| ChapterEntry {
+            start: chapter.start_time,
+            end:   Some(chapter.end_time),
+            title: chapters::read_title(&chapter.frames).unwrap_or_default().to_string(),
+            url:   chapter.get("WXXX").and_then(|f| f.content().extended_link()).map(|l| l.link.clone()),
+        }).
+        collect::<Vec<_>>();
+
+    entries.sort_by_key(|e| e.start);
+    entries
+}
+
+// A [CHAPTER] section of an FFMETADATA file, with times in units of its own time base
+struct FfMetadataChapter {
+    time_base: (u64, u64),
+    start: Option<u64>,
+    end: Option<u64>,
+    title: String,
+}
+
+impl FfMetadataChapter {
+    fn finish(self) -> anyhow::Result<ChapterEntry> {
+        let (numerator, denominator) = self.time_base;
+        let to_millis = |value: u64| {
+            numerator.checked_mul(1000).
+                and_then(|n| value.checked_mul(n)).
+                and_then(|v| u32::try_from(v / denominator).ok()).
+                ok_or_else(|| anyhow!("Chapter {:?} is too long", self.title))
+        };
+
+        let start = self.start.ok_or_else(|| anyhow!("Chapter {:?} needs a START", self.title))?;
+        let end = self.end.ok_or_else(|| anyhow!("Chapter {:?} needs an END", self.title))?;
+        let (start, end) = (to_millis(start)?, to_millis(end)?);
+
+        Ok(ChapterEntry { start, end: Some(end), title: self.title, url: None })
+    }
+}
+
+// Key-value lines in sections. Special characters in keys and values are escaped with a
+// backslash, including newlines, and lines that start with ";" or "#" are comments.
+fn parse_ffmetadata(contents: &str) -> anyhow::Result<Vec<ChapterEntry>> {
+    if !contents.starts_with(";FFMETADATA") {
+        return Err(anyhow!("FFMETADATA files need to start with \";FFMETADATA1\""));
+    }
+
+    let mut entries = Vec::new();
+    let mut chapter: Option<FfMetadataChapter> = None;
+
+    for line in split_ffmetadata_lines(contents) {
+        if line.starts_with(';') || line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if let Some(finished) = chapter.take() {
+                entries.push(finished.finish()?);
+            }
+            if line.trim_end() == "[CHAPTER]" {
+                chapter = Some(FfMetadataChapter { time_base: (1, 1000), start: None, end: None, title: String::new() });
+            }
+            continue;
+        }
+
+        // Global metadata and [STREAM] sections are skipped
+        let Some(chapter) = chapter.as_mut() else { continue };
+        let (key, value) = split_ffmetadata_pair(line);
+        let invalid = || anyhow!("Invalid {} in FFMETADATA chapter: {:?}", key, value);
+
+        match key.to_ascii_uppercase().as_str() {
+            "TIMEBASE" => {
+                chapter.time_base = value.split_once('/').
+                    and_then(|(n, d)| Some((n.trim().parse().ok()?, d.trim().parse().ok()?))).
+                    filter(|(n, d)| *n > 0 && *d > 0).
+                    ok_or_else(invalid)?;
+            },
+            "START" => chapter.start = Some(value.trim().parse().map_err(|_| invalid())?),
+            "END"   => chapter.end = Some(value.trim().parse().map_err(|_| invalid())?),
+            "TITLE" => chapter.title = unescape_ffmetadata(value),
+            _       => (),
+        }
+    }
+
+    if let Some(finished) = chapter {
+        entries.push(finished.finish()?);
+    }
+    Ok(entries)
+}
+
+fn serialize_ffmetadata(entries: &[ChapterEntry]) -> String {
+    let mut output = String::from(";FFMETADATA1\n");
+
+    for entry in entries {
+        output.push_str("\n[CHAPTER]\nTIMEBASE=1/1000\n");
+        output.push_str(&format!("START={}\n", entry.start));
+        output.push_str(&format!("END={}\n", entry.end.unwrap_or(entry.start)));
+        output.push_str(&format!("title={}\n", escape_ffmetadata(&entry.title)));
+    }
+
+    output
+}
+
+// Lines end on newlines that aren't escaped. The escapes themselves are left for later, so that
+// the key and value can be split on the first unescaped "=".
+fn split_ffmetadata_lines(contents: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (index, c) in contents.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\'         => escaped = true,
+            '\n'         => {
+                lines.push(contents[start..index].trim_end_matches('\r'));
+                start = index + 1;
+            },
+            _            => (),
+        }
+    }
+    lines.push(&contents[start..]);
+
+    lines
+}
+
+fn split_ffmetadata_pair(line: &str) -> (String, &str) {
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\'         => escaped = true,
+            '='          => return (unescape_ffmetadata(&line[..index]), &line[(index + 1)..]),
+            _            => (),
+        }
+    }
+
+    (unescape_ffmetadata(line), "")
+}
+
+fn unescape_ffmetadata(text: &str) -> String {
+    let mut output = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            output.extend(chars.next());
+        } else {
+            output.push(c);
+        }
+    }
+
+    output
+}
+
+fn escape_ffmetadata(text: &str) -> String {
+    let mut output = String::new();
+
+    for c in text.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+
+    output
+}
+
+// Either an array of chapters, or an object with a "chapters" key, like the Web Player's config.
+// Start times are "normal play time" strings, but seconds as numbers are accepted, too.
+fn parse_podlove(contents: &str) -> anyhow::Result<Vec<ChapterEntry>> {
+    let json: serde_json::Value = serde_json::from_str(contents)?;
+
+    let chapters = match &json {
+        serde_json::Value::Array(chapters) => chapters,
+        serde_json::Value::Object(config) => config.get("chapters").
+            and_then(serde_json::Value::as_array).
+            ok_or_else(|| anyhow!("Podlove chapters need to be an array, or an object with a `chapters` array"))?,
+        _ => return Err(anyhow!("Podlove chapters need to be an array, or an object with a `chapters` array")),
+    };
+
+    chapters.iter().
+        map(|chapter| {
+            let start = match chapter.get("start") {
+                Some(serde_json::Value::String(start)) => parse_time(start),
+                Some(serde_json::Value::Number(seconds)) => seconds.as_f64().
+                    filter(|s| *s >= 0.0 && *s * 1000.0 <= f64::from(u32::MAX)).
+                    map(|s| (s * 1000.0).round() as u32),
+                _ => None,
+            };
+            let start = start.ok_or_else(|| anyhow!("Invalid start time of Podlove chapter: {}", chapter))?;
+
+            let text = |key: &str| chapter.get(key).and_then(serde_json::Value::as_str).map(String::from);
+            let title = text("title").unwrap_or_default();
+            let url = text("href").filter(|href| !href.is_empty());
+
+            Ok(ChapterEntry { start, end: None, title, url })
+        }).
+        collect()
+}
+
+fn serialize_podlove(entries: &[ChapterEntry]) -> String {
+    let chapters = entries.iter().
+        map(|entry| serde_json::json!({
+            "start": format_time_with_millis(entry.start),
+            "title": entry.title,
+            "href":  entry.url.as_deref().unwrap_or_default(),
+            "image": "",
+        })).
+        collect::<Vec<_>>();
+
+    // Unwrap: serializing a JSON value can't fail
+    serde_json::to_string_pretty(&chapters).unwrap() + "\n"
+}
+
+// Empty lines and lines that start with "#" are skipped. The title can be separated from the time
+// with a dash, too, like "00:05 - Intro".
+fn parse_text(contents: &str) -> anyhow::Result<Vec<ChapterEntry>> {
+    contents.lines().
+        map(str::trim).
+        filter(|line| !line.is_empty() && !line.starts_with('#')).
+        map(|line| {
+            let (time, title) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let start = parse_time(time).ok_or_else(|| anyhow!("Invalid chapter line: {:?}", line))?;
+            let title = title.trim_start().trim_start_matches(['-', '–']).trim();
+
+            Ok(ChapterEntry { start, end: None, title: title.to_string(), url: None })
+        }).
+        collect()
+}
+
+fn serialize_text(entries: &[ChapterEntry]) -> String {
+    entries.iter().
+        map(|entry| format!("{} {}\n", format_time(entry.start), entry.title)).
+        collect()
+}
+
+// "HH:MM:SS", "MM:SS", or "SS", with optional fractions of a second
+fn parse_time(text: &str) -> Option<u32> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+
+    let mut seconds = 0_u64;
+    let parts = whole.split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return None;
+    }
+    for (index, part) in parts.iter().enumerate() {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        // Only the first field can go past 59, so "90" is fine, but "1:90" isn't
+        let value = part.parse::<u64>().ok()?;
+        if index > 0 && value >= 60 {
+            return None;
+        }
+        seconds = seconds.checked_mul(60)?.checked_add(value)?;
+    }
+
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse::<u64>().ok()?;
+
+    u32::try_from(seconds.checked_mul(1000)?.checked_add(millis)?).ok()
+}
+
+// Milliseconds are only shown if there are any
+fn format_time(millis: u32) -> String {
+    if millis.is_multiple_of(1000) {
+        let seconds = millis / 1000;
+        format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format_time_with_millis(millis)
+    }
+}
+
+fn format_time_with_millis(millis: u32) -> String {
+    let seconds = millis / 1000;
+    format!("{:02}:{:02}:{:02}.{:03}", seconds / 3600, seconds / 60 % 60, seconds % 60, millis % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("00:00:00"), Some(0));
+        assert_eq!(parse_time("01:02:03"), Some(3_723_000));
+        assert_eq!(parse_time("2:03.5"), Some(123_500));
+        assert_eq!(parse_time("45"), Some(45_000));
+        assert_eq!(parse_time("00:00:01.2345"), Some(1_234));
+
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("1:2:3:4"), None);
+        assert_eq!(parse_time("01:-2"), None);
+        assert_eq!(parse_time("Intro"), None);
+        assert_eq!(parse_time("1:75"), None);
+        assert_eq!(parse_time("00:99:99"), None);
+        assert_eq!(parse_time("00:59:59"), Some(3_599_000));
+        assert_eq!(parse_time("75:00"), Some(4_500_000));
+
+        // Too long for milliseconds in a u32, or even for seconds in a u64
+        assert_eq!(parse_time("4294967296"), None);
+        assert_eq!(parse_time("99999999999999999"), None);
+        assert_eq!(parse_time("99999999999999999:00:00"), None);
+        assert_eq!(parse_time("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "00:00:00");
+        assert_eq!(format_time(3_723_000), "01:02:03");
+        assert_eq!(format_time(123_500), "00:02:03.500");
+        assert_eq!(format_time_with_millis(3_723_000), "01:02:03.000");
+    }
+
+    #[test]
+    fn test_ffmetadata_escapes() {
+        let line = r"title=One \= Two\; \#3 \\ and\
+four";
+        let lines = split_ffmetadata_lines(line);
+        assert_eq!(lines.len(), 1);
+
+        let (key, value) = split_ffmetadata_pair(lines[0]);
+        assert_eq!(key, "title");
+        assert_eq!(unescape_ffmetadata(value), "One = Two; #3 \\ and\nfour");
+        assert_eq!(escape_ffmetadata("One = Two; #3 \\ and\nfour"), r"One \= Two\; \#3 \\ and\
+four");
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use id3::TagLike;
+use id3::frame::{Chapter, Content, ExtendedLink, Frame, TableOfContents};
+
+use crate::input::Args;
+use crate::json::{picture_from_json, picture_to_json};
+
+// CHAP frames mark a section of the audio by start and end time in milliseconds, and optionally
+// by byte offset from the start of the audio. An offset of 0xFFFFFFFF means it's not set, which is
+// what most podcast tools write.
+//
+// CTOC frames group chapters, and other tables of contents, by their element IDs. A tag should
+// have a single top-level one, which is what players use to list chapters.
+const NO_OFFSET: u32 = u32::MAX;
+
+// Sub-frames that are read into separate keys. Others are kept as they are on writing.
+const TITLE_FRAME: &str = "TIT2";
+const URL_FRAME: &str = "WXXX";
+const IMAGE_FRAME: &str = "APIC";
+
+pub fn read_chapters(tag: &id3::Tag, args: &Args) -> serde_json::Value {
+    tag.chapters().
+        map(|chapter| serde_json::json!({
+            "id":           chapter.element_id,
+            "start_time":   chapter.start_time,
+            "end_time":     chapter.end_time,
+            "start_offset": read_offset(chapter.start_offset),
+            "end_offset":   read_offset(chapter.end_offset),
+            "title":        read_title(&chapter.frames),
+            "url":          chapter.get(URL_FRAME).and_then(|f| f.content().extended_link()).map(|l| &l.link),
+            "image":        chapter.frames.iter().find_map(|f| f.content().picture()).map(|p| picture_to_json(p, args)),
+        })).
+        collect()
+}
+
+pub fn read_tables_of_contents(tag: &id3::Tag) -> serde_json::Value {
+    tag.tables_of_contents().
+        map(|toc| serde_json::json!({
+            "id":        toc.element_id,
+            "top_level": toc.top_level,
+            "ordered":   toc.ordered,
+            "elements":  toc.elements,
+            "title":     read_title(&toc.frames),
+        })).
+        collect()
+}
+
+// Replaces all chapters. Sub-frames of an existing chapter with the same ID are kept, unless
+// they're given: a `null` title, url, or image removes it, and a missing one is left alone. An
+// image without `data`, like one that was read without --with-covers, keeps the existing image.
+//
+// Tables of contents that refer to removed chapters are updated to leave them out. The IDs of the
+// removed chapters are returned, to leave them out of the `tables_of_contents` that are written
+// next, which still have them if they come from the same read.
+pub fn write_chapters(tag: &mut id3::Tag, json_value: &serde_json::Value) -> anyhow::Result<Vec<String>> {
+    let entries = json_value.as_array().
+        ok_or_else(|| anyhow!("The `chapters` key needs to be an array of entries"))?;
+
+    let existing = tag.chapters().cloned().collect::<Vec<_>>();
+    let mut chapters = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let chapter = chapter_from_json(index, entry, &existing)?;
+
+        if chapters.iter().any(|c: &Chapter| c.element_id == chapter.element_id) {
+            return Err(anyhow!("Duplicate chapter ID: {:?}", chapter.element_id));
+        }
+        chapters.push(chapter);
+    }
+
+    let removed_ids = existing.into_iter().
+        map(|c| c.element_id).
+        filter(|id| !chapters.iter().any(|c| c.element_id == *id)).
+        collect();
+
+    tag.remove("CHAP");
+    for chapter in chapters {
+        tag.add_frame(chapter);
+    }
+
+    remove_missing_elements(tag);
+    Ok(removed_ids)
+}
+
+// Replaces all tables of contents. Their elements need to be chapters that are in the tag, or
+// other tables of contents in the list. Since the JSON keys are processed in alphabetical order,
+// the `chapters` are written first, so they can be changed together, and elements that refer to
+// the `removed_chapters` are left out.
+pub fn write_tables_of_contents(
+    tag: &mut id3::Tag,
+    json_value: &serde_json::Value,
+    removed_chapters: &[String],
+) -> anyhow::Result<()> {
+    let entries = json_value.as_array().
+        ok_or_else(|| anyhow!("The `tables_of_contents` key needs to be an array of entries"))?;
+
+    let existing = tag.tables_of_contents().cloned().collect::<Vec<_>>();
+    let mut tables = entries.iter().
+        enumerate().
+        map(|(index, entry)| table_of_contents_from_json(index, entry, &existing)).
+        collect::<anyhow::Result<Vec<_>>>()?;
+
+    for table in &mut tables {
+        table.elements.retain(|e| !removed_chapters.contains(e));
+    }
+
+    let chapter_ids = tag.chapters().map(|c| c.element_id.as_str()).collect::<HashSet<_>>();
+    let mut table_ids = HashSet::new();
+
+    for table in &tables {
+        if chapter_ids.contains(table.element_id.as_str()) || !table_ids.insert(table.element_id.as_str()) {
+            return Err(anyhow!("Duplicate table of contents ID: {:?}", table.element_id));
+        }
+    }
+
+    for table in &tables {
+        for element in &table.elements {
+            if *element == table.element_id {
+                return Err(anyhow!("Table of contents {:?} contains itself", table.element_id));
+            }
+            if !chapter_ids.contains(element.as_str()) && !table_ids.contains(element.as_str()) {
+                return Err(anyhow!("Table of contents {:?} refers to a missing element: {:?}", table.element_id, element));
+            }
+        }
+    }
+
+    if tables.iter().filter(|t| t.top_level).count() > 1 {
+        return Err(anyhow!("Only one table of contents can be top-level"));
+    }
+
+    tag.remove("CTOC");
+    for table in tables {
+        tag.add_frame(table);
+    }
+
+    Ok(())
+}
+
+fn chapter_from_json(index: usize, entry: &serde_json::Value, existing: &[Chapter]) -> anyhow::Result<Chapter> {
+    let entry = entry.as_object().
+        ok_or_else(|| anyhow!("Entries in the `chapters` array need to be objects"))?;
+
+    let element_id = read_element_id(entry, "chapters", || format!("chp{}", index))?;
+
+    let time = |key: &str| {
+        entry.get(key).
+            and_then(serde_json::Value::as_u64).
+            and_then(|t| u32::try_from(t).ok()).
+            ok_or_else(|| anyhow!("Chapter {:?} needs to have a `{}` in milliseconds", element_id, key))
+    };
+    let offset = |key: &str| match entry.get(key) {
+        None | Some(serde_json::Value::Null) => Ok(NO_OFFSET),
+        Some(value) => value.as_u64().
+            and_then(|o| u32::try_from(o).ok()).
+            ok_or_else(|| anyhow!("Invalid `{}` for chapter {:?}: {}", key, element_id, value)),
+    };
+
+    let (start_time, end_time) = (time("start_time")?, time("end_time")?);
+    let (start_offset, end_offset) = (offset("start_offset")?, offset("end_offset")?);
+    if end_time < start_time {
+        return Err(anyhow!("Chapter {:?} ends before it starts", element_id));
+    }
+
+    let mut frames = existing.iter().
+        find(|c| c.element_id == element_id).
+        map(|c| c.frames.clone()).
+        unwrap_or_default();
+
+    if let Some(title) = entry.get("title") {
+        let title = read_optional_string(title, "title", &element_id)?;
+        set_sub_frame(&mut frames, TITLE_FRAME, title.map(|t| Frame::text(TITLE_FRAME, t)));
+    }
+
+    if let Some(url) = entry.get("url") {
+        let link = read_optional_string(url, "url", &element_id)?.map(|link| {
+            let link = ExtendedLink { description: String::new(), link };
+            Frame::with_content(URL_FRAME, Content::ExtendedLink(link))
+        });
+        set_sub_frame(&mut frames, URL_FRAME, link);
+    }
+
+    match entry.get("image") {
+        None => (),
+        Some(serde_json::Value::Null) => set_sub_frame(&mut frames, IMAGE_FRAME, None),
+        Some(image) if image.get("data").is_none() && frames.iter().any(|f| f.id() == IMAGE_FRAME) => (),
+        Some(image) => {
+            let picture = picture_from_json("Chapter images", image)?;
+            set_sub_frame(&mut frames, IMAGE_FRAME, Some(Frame::with_content(IMAGE_FRAME, Content::Picture(picture))));
+        },
+    }
+
+    Ok(Chapter { element_id, start_time, end_time, start_offset, end_offset, frames })
+}
+
+fn table_of_contents_from_json(
+    index: usize,
+    entry: &serde_json::Value,
+    existing: &[TableOfContents],
+) -> anyhow::Result<TableOfContents> {
+    let entry = entry.as_object().
+        ok_or_else(|| anyhow!("Entries in the `tables_of_contents` array need to be objects"))?;
+
+    let element_id = read_element_id(entry, "tables_of_contents", || format!("toc{}", index))?;
+
+    let flag = |key: &str| match entry.get(key) {
+        None | Some(serde_json::Value::Null) => Ok(false),
+        Some(serde_json::Value::Bool(value)) => Ok(*value),
+        Some(value) => Err(anyhow!("Invalid `{}` for table of contents {:?}: {}", key, element_id, value)),
+    };
+
+    let elements = entry.get("elements").
+        and_then(serde_json::Value::as_array).
+        ok_or_else(|| anyhow!("Table of contents {:?} needs to have an `elements` array", element_id))?.
+        iter().
+        map(|e| {
+            e.as_str().
+                map(String::from).
+                ok_or_else(|| anyhow!("Elements of table of contents {:?} need to be IDs", element_id))
+        }).
+        collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut frames = existing.iter().
+        find(|t| t.element_id == element_id).
+        map(|t| t.frames.clone()).
+        unwrap_or_default();
+
+    if let Some(title) = entry.get("title") {
+        let title = read_optional_string(title, "title", &element_id)?;
+        set_sub_frame(&mut frames, TITLE_FRAME, title.map(|t| Frame::text(TITLE_FRAME, t)));
+    }
+
+    Ok(TableOfContents {
+        top_level: flag("top_level")?,
+        ordered:   flag("ordered")?,
+        element_id,
+        elements,
+        frames,
+    })
+}
+
+// Drops elements of tables of contents that aren't in the tag anymore
+fn remove_missing_elements(tag: &mut id3::Tag) {
+    let mut ids = tag.chapters().map(|c| c.element_id.clone()).collect::<HashSet<_>>();
+    ids.extend(tag.tables_of_contents().map(|t| t.element_id.clone()));
+
+    let tables = tag.tables_of_contents().cloned().collect::<Vec<_>>();
+    if tables.iter().all(|t| t.elements.iter().all(|e| ids.contains(e))) {
+        return;
+    }
+
+    tag.remove("CTOC");
+    for mut table in tables {
+        table.elements.retain(|e| ids.contains(e));
+        tag.add_frame(table);
+    }
+}
+
+fn read_element_id(
+    entry: &serde_json::Map<String, serde_json::Value>,
+    label: &str,
+    default: impl FnOnce() -> String,
+) -> anyhow::Result<String> {
+    match entry.get("id") {
+        None | Some(serde_json::Value::Null) => Ok(default()),
+        Some(serde_json::Value::String(id)) if !id.is_empty() => Ok(id.clone()),
+        Some(other) => Err(anyhow!("Invalid ID in `{}`: {}", label, other)),
+    }
+}
+
+fn read_optional_string(value: &serde_json::Value, key: &str, element_id: &str) -> anyhow::Result<Option<String>> {
+    match value {
+        serde_json::Value::Null          => Ok(None),
+        serde_json::Value::String(value) => Ok(Some(value.clone())),
+        _ => Err(anyhow!("Invalid `{}` for {:?}: {}", key, element_id, value)),
+    }
+}
+
+fn read_offset(offset: u32) -> Option<u32> {
+    (offset != NO_OFFSET).then_some(offset)
+}
+
+pub(crate) fn read_title(frames: &[Frame]) -> Option<&str> {
+    frames.iter().
+        find(|f| f.id() == TITLE_FRAME).
+        and_then(|f| f.content().text()).
+        map(|t| t.trim_end_matches('\u{0000}'))
+}
+
+// Replaces all sub-frames with the given ID, keeping the position of the first one
+fn set_sub_frame(frames: &mut Vec<Frame>, frame_id: &str, frame: Option<Frame>) {
+    let position = frames.iter().position(|f| f.id() == frame_id).unwrap_or(frames.len());
+    frames.retain(|f| f.id() != frame_id);
+
+    if let Some(frame) = frame {
+        frames.insert(position.min(frames.len()), frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_sub_frame() {
+        let mut frames = vec![
+            Frame::text("TIT2", "Old"),
+            Frame::text("TIT3", "Subtitle"),
+            Frame::text("TIT2", "Duplicate"),
+        ];
+
+        set_sub_frame(&mut frames, "TIT2", Some(Frame::text("TIT2", "New")));
+        assert_eq!(frames.iter().map(|f| f.content().text().unwrap()).collect::<Vec<_>>(), vec!["New", "Subtitle"]);
+
+        set_sub_frame(&mut frames, "TIT2", None);
+        assert_eq!(frames.iter().map(|f| f.id()).collect::<Vec<_>>(), vec!["TIT3"]);
+
+        set_sub_frame(&mut frames, "WXXX", Some(Frame::link("WXXX", "https://example.com")));
+        assert_eq!(frames.iter().map(|f| f.id()).collect::<Vec<_>>(), vec!["TIT3", "WXXX"]);
+    }
+
+    #[test]
+    fn test_remove_missing_elements() {
+        let mut tag = id3::Tag::new();
+        for id in ["chp0", "chp1"] {
+            tag.add_frame(Chapter {
+                element_id: id.to_string(), start_time: 0, end_time: 1000,
+                start_offset: NO_OFFSET, end_offset: NO_OFFSET, frames: Vec::new(),
+            });
+        }
+        tag.add_frame(TableOfContents {
+            element_id: String::from("toc"), top_level: true, ordered: true,
+            elements: vec![String::from("chp0"), String::from("chp1"), String::from("chp2")],
+            frames: Vec::new(),
+        });
+
+        remove_missing_elements(&mut tag);
+        assert_eq!(tag.tables_of_contents().next().unwrap().elements, vec!["chp0", "chp1"]);
+    }
+}
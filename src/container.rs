@@ -0,0 +1,146 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::dsf;
+use crate::flac;
+use crate::mp4;
+use crate::ogg;
+
+// The kind of file the tags are stored in, detected from the first few bytes rather than the file
+// extension. MPEG files have the ID3 tag at the start (and maybe an ID3v1 one at the end), WAV and
+// AIFF files have it in an "ID3 " chunk, and DSF files have it at the end, after the audio. FLAC
+// and Ogg files have Vorbis comments instead, and MP4 files have iTunes metadata, which are
+// converted to and from an ID3 tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mpeg,
+    Wav,
+    Aiff,
+    Dsf,
+    Flac,
+    Mp4,
+    Ogg,
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Container::Mpeg => write!(f, "MPEG"),
+            Container::Wav  => write!(f, "WAV"),
+            Container::Aiff => write!(f, "AIFF"),
+            Container::Dsf  => write!(f, "DSF"),
+            Container::Flac => write!(f, "FLAC"),
+            Container::Mp4  => write!(f, "MP4"),
+            Container::Ogg  => write!(f, "Ogg"),
+        }
+    }
+}
+
+impl Container {
+    // ID3v1, APEv2 and Lyrics3v2 tags are appended to the raw audio. In a chunk-based container,
+    // that would put garbage after the last chunk.
+    pub fn supports_trailing_tags(self) -> bool {
+        self == Container::Mpeg
+    }
+
+    // The kind of tag, for containers that don't store ID3 tags natively
+    pub fn tag_format(self) -> Option<&'static str> {
+        match self {
+            Container::Mpeg | Container::Wav | Container::Aiff | Container::Dsf => None,
+            Container::Flac | Container::Ogg => Some("Vorbis comment"),
+            Container::Mp4  => Some("iTunes metadata"),
+        }
+    }
+}
+
+pub fn read_tag(path: &Path, container: Container) -> anyhow::Result<id3::Tag> {
+    match container {
+        Container::Dsf  => dsf::read_tag(path),
+        Container::Flac => flac::read_tag(path),
+        Container::Mp4  => mp4::read_tag(path),
+        Container::Ogg  => ogg::read_tag(path),
+        // The id3 crate finds the tag in WAV and AIFF chunks by itself, using the same magic bytes
+        _ => match id3::Tag::read_from_path(path) {
+            Ok(tag) => Ok(tag),
+            Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) => Ok(id3::Tag::new()),
+            Err(e) => Err(e.into()),
+        },
+    }
+}
+
+// Returns the IDs of frames that couldn't be written, for containers that don't store ID3 tags.
+pub fn write_tag(path: &Path, container: Container, tag: &id3::Tag, version: id3::Version) -> anyhow::Result<Vec<String>> {
+    match container {
+        Container::Dsf => {
+            dsf::write_tag(path, tag, version)?;
+            Ok(Vec::new())
+        },
+        Container::Flac => flac::write_tag(path, tag),
+        Container::Mp4  => mp4::write_tag(path, tag),
+        Container::Ogg  => ogg::write_tag(path, tag),
+        _ => {
+            tag.write_to_path(path, version)?;
+            Ok(Vec::new())
+        },
+    }
+}
+
+pub fn detect_from_path(path: &Path) -> anyhow::Result<Container> {
+    let mut header = Vec::with_capacity(12);
+    File::open(path)?.take(12).read_to_end(&mut header)?;
+
+    detect(&header)
+}
+
+// Anything that isn't a known container is treated as MPEG, since raw MPEG audio doesn't
+// necessarily start with anything recognizable, and a file without tags might not have any audio.
+pub fn detect(header: &[u8]) -> anyhow::Result<Container> {
+    let magic = header.get(0..4).unwrap_or_default();
+    let form_type = header.get(8..12).unwrap_or_default();
+
+    let unsupported = match magic {
+        b"RIFF" if form_type == b"WAVE" => return Ok(Container::Wav),
+        b"FORM" if form_type == b"AIFF" || form_type == b"AIFC" => return Ok(Container::Aiff),
+        b"fLaC" => return Ok(Container::Flac),
+        b"OggS" => return Ok(Container::Ogg),
+        b"DSD " => return Ok(Container::Dsf),
+        _ if header.get(4..8) == Some(b"ftyp") => return Ok(Container::Mp4),
+
+        b"RIFF"       => format!("RIFF ({})", String::from_utf8_lossy(form_type)),
+        b"FORM"       => format!("IFF ({})", String::from_utf8_lossy(form_type)),
+        b"MAC "       => String::from("Monkey's Audio"),
+        b"wvpk"       => String::from("WavPack"),
+        b"MThd"       => String::from("MIDI"),
+        _ => return Ok(Container::Mpeg),
+    };
+
+    Err(anyhow!("Unsupported container: {}", unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"ID3\x04\x00\x00\x00\x00\x00\x00").unwrap(), Container::Mpeg);
+        assert_eq!(detect(b"\xFF\xFB\x90\x00").unwrap(), Container::Mpeg);
+        assert_eq!(detect(b"").unwrap(), Container::Mpeg);
+        assert_eq!(detect(b"RIFF\x24\x00\x00\x00WAVE").unwrap(), Container::Wav);
+        assert_eq!(detect(b"FORM\x00\x00\x00\x24AIFF").unwrap(), Container::Aiff);
+        assert_eq!(detect(b"FORM\x00\x00\x00\x24AIFC").unwrap(), Container::Aiff);
+        assert_eq!(detect(b"fLaC\x00\x00\x00\x22").unwrap(), Container::Flac);
+        assert_eq!(detect(b"\x00\x00\x00\x20ftypM4A ").unwrap(), Container::Mp4);
+        assert_eq!(detect(b"OggS\x00\x02\x00\x00").unwrap(), Container::Ogg);
+        assert_eq!(detect(b"DSD \x1c\x00\x00\x00").unwrap(), Container::Dsf);
+
+        let error = |header: &[u8]| detect(header).unwrap_err().to_string();
+
+        assert_eq!(error(b"wvpk\x00\x00\x00\x00"), "Unsupported container: WavPack");
+        assert_eq!(error(b"RIFF\x24\x00\x00\x00AVI "), "Unsupported container: RIFF (AVI )");
+    }
+}
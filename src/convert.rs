@@ -0,0 +1,276 @@
+use id3::{Frame, TagLike, Version};
+use id3::frame::{Content, InvolvedPeopleList};
+
+use crate::date;
+
+// Frames that only exist in ID3v2.4 and have no ID3v2.3 equivalent. Players that only know
+// ID3v2.3 wouldn't understand them, so they're removed.
+const ID3V24_ONLY_FRAMES: &[&str] = &["ASPI", "EQU2", "RVA2", "SEEK", "SIGN", "TDEN", "TPRO", "TSST"];
+
+// Frames that were removed in ID3v2.4 without a replacement, or with an incompatible one.
+const ID3V23_ONLY_FRAMES: &[&str] = &["EQUA", "RVAD", "TRDA", "TSIZ"];
+
+// Frames that aren't a part of ID3v2.3, but are written by iTunes and understood by most players,
+// so they're kept as-is. These are also fields that are read and written by this tool.
+const ID3V23_NONSTANDARD_FRAMES: &[&str] = &[
+    "TSOP", "TSOA", "TSOT", "TSO2", "TSOC", "TMOO", "TDRL", "TDTG",
+];
+
+// iTunes-specific frames don't have standard ID3v2.2 IDs, but iTunes uses these.
+const ITUNES_ID3V22_FRAMES: &[(&str, &str)] = &[
+    ("TSOP", "TSP"),
+    ("TSOA", "TSA"),
+    ("TSOT", "TST"),
+    ("TSO2", "TS2"),
+    ("TSOC", "TSC"),
+    ("TCMP", "TCP"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Converted,
+    Merged,
+    Kept,
+    Dropped,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Converted => "converted",
+            Action::Merged    => "merged",
+            Action::Kept      => "kept",
+            Action::Dropped   => "dropped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub frame_id: String,
+    pub action: Action,
+    pub into: Vec<String>,
+    pub lossy: bool,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub from: Version,
+    pub to: Version,
+    pub entries: Vec<Entry>,
+}
+
+impl Report {
+    fn new(from: Version, to: Version) -> Self {
+        Report { from, to, entries: Vec::new() }
+    }
+
+    fn add(&mut self, frame_id: &str, action: Action, into: &[&str], lossy: bool, note: Option<String>) {
+        let frame_id = frame_id.to_string();
+        let into = into.iter().map(|id| id.to_string()).collect();
+
+        self.entries.push(Entry { frame_id, action, into, lossy, note });
+    }
+
+    pub fn is_lossy(&self) -> bool {
+        self.entries.iter().any(|e| e.lossy)
+    }
+
+    pub fn lossy_entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|e| e.lossy)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let frames = self.entries.iter().
+            map(|e| serde_json::json!({
+                "frame":  e.frame_id,
+                "action": e.action.as_str(),
+                "into":   e.into,
+                "lossy":  e.lossy,
+                "note":   e.note,
+            })).
+            collect::<Vec<_>>();
+
+        serde_json::json!({
+            "from":   format!("{}", self.from),
+            "to":     format!("{}", self.to),
+            "lossy":  self.is_lossy(),
+            "frames": frames,
+        })
+    }
+}
+
+// Converts the frames in the tag to the ones that make sense for the given version, so that
+// writing the tag in that version doesn't lose data silently. Everything that was changed is
+// recorded in the report. If the versions are the same, nothing happens.
+pub fn convert_tag(tag: &mut id3::Tag, to: Version, separator: &str) -> Report {
+    let from = tag.version();
+    let mut report = Report::new(from, to);
+
+    if from == to {
+        return report;
+    }
+
+    let mut converted = id3::Tag::with_version(to);
+    for frame in tag.frames() {
+        converted.add_frame(frame.clone());
+    }
+
+    convert_itunes_frames(&mut converted, to, &mut report);
+    convert_dates(&mut converted, to, &mut report);
+    convert_involved_people(&mut converted, to, &mut report);
+
+    if to < Version::Id3v24 {
+        merge_text_values(&mut converted, separator, &mut report);
+
+        for frame_id in ID3V24_ONLY_FRAMES {
+            if !converted.remove(frame_id).is_empty() {
+                let note = format!("No equivalent in {}", to);
+                report.add(frame_id, Action::Dropped, &[], true, Some(note));
+            }
+        }
+
+        if to == Version::Id3v23 {
+            for frame_id in ID3V23_NONSTANDARD_FRAMES {
+                if converted.get(frame_id).is_some() {
+                    let note = String::from("Not a part of ID3v2.3, but supported by most players");
+                    report.add(frame_id, Action::Kept, &[], false, Some(note));
+                }
+            }
+        }
+    } else {
+        for frame_id in ID3V23_ONLY_FRAMES {
+            if !converted.remove(frame_id).is_empty() {
+                let note = format!("No equivalent in {}", to);
+                report.add(frame_id, Action::Dropped, &[], true, Some(note));
+            }
+        }
+    }
+
+    // Anything that still doesn't have an ID for the target version can't be written at all
+    let unwritable = converted.frames().
+        filter(|f| f.id_for_version(to).is_none()).
+        map(|f| f.id().to_string()).
+        collect::<Vec<_>>();
+
+    for frame_id in unwritable {
+        if !converted.remove(&frame_id).is_empty() {
+            let note = format!("No frame ID in {}", to);
+            report.add(&frame_id, Action::Dropped, &[], true, Some(note));
+        }
+    }
+
+    *tag = converted;
+    report
+}
+
+fn convert_itunes_frames(tag: &mut id3::Tag, to: Version, report: &mut Report) {
+    for (frame_id, v22_frame_id) in ITUNES_ID3V22_FRAMES {
+        let (old_id, new_id) = if to == Version::Id3v22 {
+            (frame_id, v22_frame_id)
+        } else {
+            (v22_frame_id, frame_id)
+        };
+
+        for frame in tag.remove(old_id) {
+            tag.add_frame(Frame::with_content(*new_id, frame.content().clone()));
+            report.add(old_id, Action::Converted, &[new_id], false, None);
+        }
+    }
+}
+
+fn convert_dates(tag: &mut id3::Tag, to: Version, report: &mut Report) {
+    if to >= Version::Id3v24 {
+        let old_date_frames = ["TYER", "TDAT", "TIME"].into_iter().
+            filter(|id| tag.get(id).is_some()).
+            collect::<Vec<_>>();
+
+        if !old_date_frames.is_empty() {
+            let date = date::read_recording_date(tag);
+            date::write_recording_date(tag, date, to);
+
+            for frame_id in old_date_frames {
+                report.add(frame_id, Action::Converted, &["TDRC"], false, None);
+            }
+        }
+
+        if tag.get("TORY").is_some() {
+            let date = date::read_original_date(tag);
+            date::write_original_date(tag, date, to);
+            report.add("TORY", Action::Converted, &["TDOR"], false, None);
+        }
+    } else {
+        if let Some(date) = tag.date_recorded() {
+            date::write_recording_date(tag, Some(date), to);
+
+            let lossy = date.second.is_some() || (date.month.is_some() && date.day.is_none());
+            let note = lossy.then(|| format!("{} can only store the year, day, month, hours and minutes", to));
+            let into = ["TYER", "TDAT", "TIME"].into_iter().
+                filter(|id| tag.get(id).is_some()).
+                collect::<Vec<_>>();
+
+            report.add("TDRC", Action::Converted, &into, lossy, note);
+        }
+
+        if let Some(date) = tag.original_date_released() {
+            date::write_original_date(tag, Some(date), to);
+
+            let lossy = date.month.is_some();
+            let note = lossy.then(|| format!("{} can only store the year", to));
+
+            report.add("TDOR", Action::Converted, &["TORY"], lossy, note);
+        }
+    }
+}
+
+// ID3v2.4 split the involved people list into TIPL for general credits and TMCL for musicians.
+// ID3v2.3 only has IPLS, so both are put in there.
+fn convert_involved_people(tag: &mut id3::Tag, to: Version, report: &mut Report) {
+    if to >= Version::Id3v24 {
+        for frame in tag.remove("IPLS") {
+            tag.add_frame(Frame::with_content("TIPL", frame.content().clone()));
+            report.add("IPLS", Action::Converted, &["TIPL"], false, None);
+        }
+        return;
+    }
+
+    let mut items = Vec::new();
+
+    for frame in tag.remove("TIPL") {
+        if let Content::InvolvedPeopleList(list) = frame.content() {
+            items.extend(list.items.iter().cloned());
+        }
+        report.add("TIPL", Action::Converted, &["IPLS"], false, None);
+    }
+
+    for frame in tag.remove("TMCL") {
+        if let Content::InvolvedPeopleList(list) = frame.content() {
+            items.extend(list.items.iter().cloned());
+        }
+        let note = String::from("Musician credits are mixed with the other involved people");
+        report.add("TMCL", Action::Merged, &["IPLS"], true, Some(note));
+    }
+
+    if !items.is_empty() {
+        let content = Content::InvolvedPeopleList(InvolvedPeopleList { items });
+        tag.add_frame(Frame::with_content("IPLS", content));
+    }
+}
+
+// Only ID3v2.4 supports multiple values in text frames, so they're joined into one.
+fn merge_text_values(tag: &mut id3::Tag, separator: &str, report: &mut Report) {
+    let multi_valued = tag.frames().
+        filter_map(|f| {
+            let text = f.content().text()?.trim_end_matches('\u{0000}');
+            text.contains('\u{0000}').then(|| (f.id().to_string(), text.replace('\u{0000}', separator)))
+        }).
+        collect::<Vec<_>>();
+
+    for (frame_id, text) in multi_valued {
+        tag.set_text(&frame_id, text);
+
+        let note = format!("Multiple values joined with {:?}", separator);
+        report.add(&frame_id, Action::Merged, &[&frame_id], true, Some(note));
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::anyhow;
+use id3::{TagLike, Timestamp};
+
+// Parses an ID3v2.4 timestamp, which is a subset of ISO 8601: "yyyy", "yyyy-MM", "yyyy-MM-dd",
+// "yyyy-MM-ddTHH", "yyyy-MM-ddTHH:mm", or "yyyy-MM-ddTHH:mm:ss". The id3 crate's parser ignores
+// anything it doesn't understand, so the result is checked against the input.
+pub fn parse_timestamp(label: &str, text: &str) -> anyhow::Result<Timestamp> {
+    let invalid_timestamp = || anyhow!("Invalid timestamp for \"{}\": {:?}", label, text);
+
+    let timestamp: Timestamp = text.parse().map_err(|_| invalid_timestamp())?;
+
+    let in_range = |value: Option<u8>, range: std::ops::RangeInclusive<u8>| {
+        value.is_none_or(|v| range.contains(&v))
+    };
+    let valid =
+        in_range(timestamp.month, 1..=12) &&
+        in_range(timestamp.day, 1..=31) &&
+        in_range(timestamp.hour, 0..=23) &&
+        in_range(timestamp.minute, 0..=59) &&
+        in_range(timestamp.second, 0..=59);
+
+    if !valid || timestamp.to_string() != text {
+        return Err(invalid_timestamp());
+    }
+
+    Ok(timestamp)
+}
+
+// ID3v2.4 has a single TDRC frame with a full timestamp for the recording date. ID3v2.3 splits
+// it into TYER for the year, TDAT for the day and month as "DDMM", and TIME for the time as
+// "HHMM". Either set of frames is read, regardless of the tag version, so a tag that was
+// converted without updating its frames still has a date.
+pub fn read_recording_date(tag: &id3::Tag) -> Option<Timestamp> {
+    if let Some(timestamp) = tag.date_recorded() {
+        return Some(timestamp);
+    }
+
+    let year = frame_text(tag, "TYER")?.parse().ok()?;
+    let mut timestamp = Timestamp { year, ..Timestamp::default() };
+
+    if let Some((day, month)) = frame_text(tag, "TDAT").and_then(split_digit_pairs) {
+        timestamp.month = Some(month);
+        timestamp.day = Some(day);
+
+        if let Some((hour, minute)) = frame_text(tag, "TIME").and_then(split_digit_pairs) {
+            timestamp.hour = Some(hour);
+            timestamp.minute = Some(minute);
+        }
+    }
+
+    Some(timestamp)
+}
+
+// Writes the frames for the given version and removes the ones for the other, so there's only a
+// single source of truth for the date. ID3v2.3 can't store seconds or a month without a day, so
+// those get dropped.
+pub fn write_recording_date(tag: &mut id3::Tag, timestamp: Option<Timestamp>, version: id3::Version) {
+    for frame_id in ["TDRC", "TYER", "TDAT", "TIME"] {
+        tag.remove(frame_id);
+    }
+
+    let Some(timestamp) = timestamp else { return };
+
+    if version >= id3::Version::Id3v24 {
+        tag.set_date_recorded(timestamp);
+        return;
+    }
+
+    tag.set_text("TYER", format!("{:04}", timestamp.year));
+
+    if let (Some(month), Some(day)) = (timestamp.month, timestamp.day) {
+        tag.set_text("TDAT", format!("{:02}{:02}", day, month));
+
+        if let (Some(hour), Some(minute)) = (timestamp.hour, timestamp.minute) {
+            tag.set_text("TIME", format!("{:02}{:02}", hour, minute));
+        }
+    }
+}
+
+// ID3v2.4 has a full timestamp in TDOR, but ID3v2.3 only has the year in TORY.
+pub fn read_original_date(tag: &id3::Tag) -> Option<Timestamp> {
+    if let Some(timestamp) = tag.original_date_released() {
+        return Some(timestamp);
+    }
+
+    let year = frame_text(tag, "TORY")?.parse().ok()?;
+    Some(Timestamp { year, ..Timestamp::default() })
+}
+
+pub fn write_original_date(tag: &mut id3::Tag, timestamp: Option<Timestamp>, version: id3::Version) {
+    tag.remove("TDOR");
+    tag.remove("TORY");
+
+    let Some(timestamp) = timestamp else { return };
+
+    if version >= id3::Version::Id3v24 {
+        tag.set_original_date_released(timestamp);
+    } else {
+        tag.set_text("TORY", format!("{:04}", timestamp.year));
+    }
+}
+
+// The release date (TDRL) and tagging time (TDTG) don't have an ID3v2.3 equivalent, so they're
+// stored in the same frames regardless of the version. Players that don't know them will ignore
+// them.
+pub fn read_timestamp(tag: &id3::Tag, frame_id: &str) -> Option<Timestamp> {
+    frame_text(tag, frame_id)?.parse().ok()
+}
+
+pub fn write_timestamp(tag: &mut id3::Tag, frame_id: &str, timestamp: Option<Timestamp>) {
+    match timestamp {
+        Some(timestamp) => tag.set_text(frame_id, timestamp.to_string()),
+        None            => { tag.remove(frame_id); },
+    }
+}
+
+fn frame_text<'a>(tag: &'a id3::Tag, frame_id: &str) -> Option<&'a str> {
+    tag.get(frame_id).
+        and_then(|f| f.content().text()).
+        map(|t| t.trim_end_matches('\u{0000}').trim())
+}
+
+// Splits "DDMM" or "HHMM" into its two numbers.
+fn split_digit_pairs(text: &str) -> Option<(u8, u8)> {
+    if text.len() != 4 || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((text[0..2].parse().ok()?, text[2..4].parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let timestamp = parse_timestamp("_", "2023-06-01T12:30").unwrap();
+        assert_eq!(timestamp.year, 2023);
+        assert_eq!(timestamp.month, Some(6));
+        assert_eq!(timestamp.minute, Some(30));
+        assert_eq!(timestamp.second, None);
+
+        assert!(parse_timestamp("_", "2023").is_ok());
+        assert!(parse_timestamp("_", "2023-06-01T12:30:59").is_ok());
+
+        assert!(parse_timestamp("_", "2023-13").is_err());
+        assert!(parse_timestamp("_", "2023-06-32").is_err());
+        assert!(parse_timestamp("_", "2023-06-01T24").is_err());
+        assert!(parse_timestamp("_", "2023-6-1").is_err());
+        assert!(parse_timestamp("_", "2023 and then some").is_err());
+        assert!(parse_timestamp("_", "June 2023").is_err());
+    }
+
+    #[test]
+    fn test_split_digit_pairs() {
+        assert_eq!(split_digit_pairs("0106"), Some((1, 6)));
+        assert_eq!(split_digit_pairs("2359"), Some((23, 59)));
+        assert_eq!(split_digit_pairs("106"), None);
+        assert_eq!(split_digit_pairs("01-6"), None);
+    }
+}
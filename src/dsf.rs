@@ -0,0 +1,88 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+// A DSF file starts with a "DSD " chunk that has the total file size and a pointer to the ID3v2
+// tag, which is at the very end, after the "fmt " and "data" chunks. All numbers are little-endian.
+const DSD_CHUNK_SIZE: usize = 28;
+const FILE_SIZE_OFFSET: usize = 12;
+const METADATA_POINTER_OFFSET: usize = 20;
+
+struct Layout {
+    audio_end: usize,
+    metadata_pointer: usize,
+}
+
+pub fn read_tag(path: &Path) -> anyhow::Result<id3::Tag> {
+    let bytes = fs::read(path)?;
+    let layout = parse(&bytes)?;
+
+    if layout.metadata_pointer == 0 {
+        return Ok(id3::Tag::new());
+    }
+
+    let Some(tag_bytes) = bytes.get(layout.metadata_pointer..) else {
+        return Err(anyhow!("Invalid DSF file: the metadata pointer is past the end of the file"));
+    };
+
+    match id3::Tag::read_from2(Cursor::new(tag_bytes)) {
+        Ok(tag) => Ok(tag),
+        Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) => Ok(id3::Tag::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Replaces everything after the audio data with the tag, and updates the pointer to it and the
+// file size in the header. An empty tag is removed, with a pointer of 0.
+pub fn write_tag(path: &Path, tag: &id3::Tag, version: id3::Version) -> anyhow::Result<()> {
+    let bytes = fs::read(path)?;
+    let layout = parse(&bytes)?;
+
+    let mut output = bytes[..layout.audio_end].to_vec();
+    let metadata_pointer = if tag.frames().next().is_some() {
+        tag.write_to(&mut output, version)?;
+        layout.audio_end as u64
+    } else {
+        0
+    };
+
+    let file_size = output.len() as u64;
+    output[FILE_SIZE_OFFSET..(FILE_SIZE_OFFSET + 8)].copy_from_slice(&file_size.to_le_bytes());
+    output[METADATA_POINTER_OFFSET..(METADATA_POINTER_OFFSET + 8)].copy_from_slice(&metadata_pointer.to_le_bytes());
+
+    fs::write(path, output)?;
+    Ok(())
+}
+
+fn parse(bytes: &[u8]) -> anyhow::Result<Layout> {
+    let read_u64 = |offset: usize| {
+        bytes.get(offset..(offset + 8)).
+            map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize).
+            ok_or_else(|| anyhow!("Invalid DSF file: the header at byte {} is cut off", offset))
+    };
+
+    if !bytes.starts_with(b"DSD ") || read_u64(4)? != DSD_CHUNK_SIZE {
+        return Err(anyhow!("Invalid DSF file: missing \"DSD \" chunk"));
+    }
+
+    let metadata_pointer = read_u64(METADATA_POINTER_OFFSET)?;
+
+    // The "fmt " chunk, followed by the "data" chunk, both with their sizes after the ID
+    let mut position = DSD_CHUNK_SIZE;
+    for chunk_id in [b"fmt ", b"data"] {
+        if bytes.get(position..(position + 4)) != Some(chunk_id.as_slice()) {
+            return Err(anyhow!("Invalid DSF file: expected a {:?} chunk at byte {}", String::from_utf8_lossy(chunk_id), position));
+        }
+
+        // A size past the end of the file, or even past the end of memory, means it's cut off
+        position = match position.checked_add(read_u64(position + 4)?) {
+            Some(end) if end <= bytes.len() => end,
+            _ if chunk_id == b"data"        => return Err(anyhow!("Invalid DSF file: the audio data is cut off")),
+            _                               => return Err(anyhow!("Invalid DSF file: the \"fmt \" chunk is cut off")),
+        };
+    }
+
+    Ok(Layout { audio_end: position, metadata_pointer })
+}
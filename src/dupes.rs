@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::container::{self, Container};
+use crate::input::{Args, DupesMatch};
+use crate::json;
+use crate::mpeg;
+
+// Encoders and rippers often disagree by a frame or two of padding, so recordings with the same
+// artist and title are the same if their lengths are this close, in seconds.
+const DURATION_TOLERANCE: f64 = 2.0;
+
+// The fields that a well-tagged file is expected to have, for the "tag_completeness" score
+const EXPECTED_FIELDS: &[&str] = &["title", "artist", "album", "track", "year", "genre", "covers"];
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub container: Container,
+    pub size: u64,
+    pub tags: serde_json::Value,
+    pub audio: Option<mpeg::AudioInfo>,
+    pub audio_hash: Option<String>,
+}
+
+impl Entry {
+    // Anything that isn't a known container is detected as MPEG, so a file that has neither an ID3
+    // tag nor MPEG frames, like a cover image next to the music, isn't audio at all, and is skipped.
+    pub fn read(path: &Path) -> anyhow::Result<Option<Self>> {
+        let container = container::detect_from_path(path)?;
+        let tag = container::read_tag(path, container)?;
+
+        let (audio, audio_hash) = if container == Container::Mpeg {
+            let bytes = fs::read(path)?;
+            (mpeg::analyze(&bytes), mpeg::audio_hash(&bytes))
+        } else {
+            (None, None)
+        };
+
+        if container == Container::Mpeg && audio.is_none() && tag.frames().next().is_none() {
+            return Ok(None);
+        }
+
+        let tags = json::read_from_tag(&tag, &Args::default())["data"].take();
+        Ok(Some(Entry { path: path.to_path_buf(), container, size: fs::metadata(path)?.len(), tags, audio, audio_hash }))
+    }
+
+    // Containers that only hold uncompressed or losslessly compressed audio, which has no bitrate
+    pub fn is_lossless(&self) -> bool {
+        matches!(self.container, Container::Wav | Container::Aiff | Container::Dsf | Container::Flac)
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.audio.as_ref().map(|a| a.duration())
+    }
+
+    pub fn bitrate(&self) -> Option<u32> {
+        self.audio.as_ref().map(|a| a.bitrate())
+    }
+
+    pub fn missing_fields(&self) -> Vec<&'static str> {
+        EXPECTED_FIELDS.iter().
+            copied().
+            filter(|field| match &self.tags[field] {
+                serde_json::Value::Null      => true,
+                serde_json::Value::String(s) => s.trim().is_empty(),
+                serde_json::Value::Array(a)  => a.is_empty(),
+                _                            => false,
+            }).
+            collect()
+    }
+
+    pub fn tag_completeness(&self) -> f64 {
+        let present = EXPECTED_FIELDS.len() - self.missing_fields().len();
+        (present as f64 / EXPECTED_FIELDS.len() as f64 * 100.0).round() / 100.0
+    }
+
+    // The normalized artist and title, if the file has both
+    fn tag_key(&self) -> Option<(String, String)> {
+        let artist = normalize(&self.tags["artist"]);
+        let title = normalize(&self.tags["title"]);
+
+        (!artist.is_empty() && !title.is_empty()).then_some((artist, title))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path":             self.path.to_string_lossy(),
+            "size":             self.size,
+            "tags":             self.tags,
+            "audio_hash":       self.audio_hash,
+            "bitrate":          self.bitrate(),
+            "bitrate_mode":     self.audio.as_ref().map(|a| a.bitrate_mode),
+            "sample_rate":      self.audio.as_ref().map(|a| a.first_frame.sample_rate),
+            "duration":         self.duration().map(|d| (d * 1000.0).round() / 1000.0),
+            "tag_completeness": self.tag_completeness(),
+            "missing_tags":     self.missing_fields(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    // Indexes into the entries, the best copy first
+    pub entries: Vec<usize>,
+    pub matched_by: Vec<&'static str>,
+}
+
+// Lists all files in the given paths, recursing into directories, in a stable order
+pub fn collect_files<P: AsRef<Path>>(paths: &[P]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        let path = path.as_ref();
+
+        if path.is_dir() {
+            let mut children = fs::read_dir(path)?.
+                map(|entry| entry.map(|e| e.path())).
+                collect::<Result<Vec<_>, _>>()?;
+            children.sort();
+
+            files.extend(collect_files(&children)?);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+// Groups the entries that are copies of the same recording. Two files match by audio if their
+// audio hashes are the same, and by tags if they have the same artist and title, ignoring case
+// and punctuation, and their durations are within a couple of seconds. Matches are transitive, so
+// with `DupesMatch::Any`, a group could have pairs of files that only match through a third one.
+pub fn find_groups(entries: &[Entry], by: DupesMatch) -> Vec<Group> {
+    let mut parents = (0..entries.len()).collect::<Vec<_>>();
+    let mut matches = Vec::new();
+
+    if by != DupesMatch::Tags {
+        let mut by_hash: HashMap<&str, usize> = HashMap::new();
+
+        for (index, entry) in entries.iter().enumerate() {
+            let Some(hash) = &entry.audio_hash else { continue };
+            if let Some(first) = by_hash.get(hash.as_str()) {
+                matches.push((*first, index, "audio"));
+            } else {
+                by_hash.insert(hash, index);
+            }
+        }
+    }
+
+    if by != DupesMatch::Audio {
+        let mut by_key: HashMap<(String, String), Vec<usize>> = HashMap::new();
+
+        for (index, entry) in entries.iter().enumerate() {
+            if let Some(key) = entry.tag_key() {
+                by_key.entry(key).or_default().push(index);
+            }
+        }
+
+        for indexes in by_key.values() {
+            for (position, &i) in indexes.iter().enumerate() {
+                for &j in &indexes[(position + 1)..] {
+                    let same_length = match (entries[i].duration(), entries[j].duration()) {
+                        (Some(a), Some(b)) => (a - b).abs() <= DURATION_TOLERANCE,
+                        _                  => true,
+                    };
+                    if same_length {
+                        matches.push((i, j, "tags"));
+                    }
+                }
+            }
+        }
+    }
+
+    for (i, j, _) in &matches {
+        let (root_i, root_j) = (find_root(&mut parents, *i), find_root(&mut parents, *j));
+        parents[root_i.max(root_j)] = root_i.min(root_j);
+    }
+
+    let mut groups: Vec<Group> = Vec::new();
+    let mut group_by_root = HashMap::new();
+
+    for index in 0..entries.len() {
+        let root = find_root(&mut parents, index);
+        if root == index && !matches.iter().any(|(i, j, _)| *i == index || *j == index) {
+            continue;
+        }
+
+        let group_index = *group_by_root.entry(root).or_insert_with(|| {
+            groups.push(Group { entries: Vec::new(), matched_by: Vec::new() });
+            groups.len() - 1
+        });
+        groups[group_index].entries.push(index);
+    }
+
+    for (i, _, reason) in &matches {
+        let group = &mut groups[group_by_root[&find_root(&mut parents, *i)]];
+        if !group.matched_by.contains(reason) {
+            group.matched_by.push(reason);
+        }
+    }
+
+    for group in &mut groups {
+        group.matched_by.sort();
+        group.entries.sort_by(|&a, &b| compare_quality(&entries[b], &entries[a]));
+    }
+
+    groups
+}
+
+pub fn groups_to_json(entries: &[Entry], groups: &[Group]) -> serde_json::Value {
+    groups.iter().
+        map(|group| serde_json::json!({
+            "matched_by": group.matched_by,
+            "files":      group.entries.iter().map(|i| entries[*i].to_json()).collect::<Vec<_>>(),
+        })).
+        collect()
+}
+
+// Lossless copies win, then higher bitrate, then more complete tags. Equal copies keep their order.
+fn compare_quality(a: &Entry, b: &Entry) -> std::cmp::Ordering {
+    a.is_lossless().cmp(&b.is_lossless()).
+        then(a.bitrate().cmp(&b.bitrate())).
+        then(a.tag_completeness().total_cmp(&b.tag_completeness())).
+        then(b.path.cmp(&a.path))
+}
+
+fn find_root(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[index] = root;
+    root
+}
+
+// Lowercase words of letters and digits, so "AC/DC" and "ac-dc" are the same. Multiple values are
+// joined, in case one file has them as an array, and another as a single string.
+fn normalize(value: &serde_json::Value) -> String {
+    let text = match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(a)  => a.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>().join(" "),
+        _                            => String::new(),
+    };
+
+    text.to_lowercase().
+        split(|c: char| !c.is_alphanumeric()).
+        filter(|word| !word.is_empty()).
+        collect::<Vec<_>>().
+        join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(&"AC/DC".into()), "ac dc");
+        assert_eq!(normalize(&"  ac-dc ".into()), "ac dc");
+        assert_eq!(normalize(&"Björk".into()), "björk");
+        assert_eq!(normalize(&serde_json::json!(["One", "Two"])), "one two");
+        assert_eq!(normalize(&serde_json::Value::Null), "");
+    }
+}
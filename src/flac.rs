@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::vorbis::{self, PictureBlock};
+
+const MAGIC: &[u8] = b"fLaC";
+
+const STREAMINFO: u8     = 0;
+const PADDING: u8        = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8        = 6;
+
+const LAST_BLOCK_FLAG: u8 = 0x80;
+const BLOCK_HEADER_SIZE: usize = 4;
+const MAX_BLOCK_SIZE: usize = 0xFF_FFFF;
+
+// Extra room for the tags to grow without having to rewrite the audio next time
+const DEFAULT_PADDING: usize = 4096;
+
+// The metadata blocks at the start of a FLAC file, followed by the audio frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+struct FlacFile {
+    blocks: Vec<Block>,
+    audio_offset: usize,
+}
+
+// Reads the Vorbis comments and pictures into an ID3v2.4 tag, so they can be handled with the
+// same JSON fields.
+pub fn read_tag(path: &Path) -> anyhow::Result<id3::Tag> {
+    let file = parse(&fs::read(path)?)?;
+    let (_, comments) = read_comments(&file)?;
+    let pictures = read_pictures(&file)?;
+
+    Ok(vorbis::to_id3_tag(&comments, &pictures))
+}
+
+// Replaces the Vorbis comment and pictures with the ones from the tag. If the new metadata fits in
+// the space of the old one, the difference goes into padding, so the audio stays where it is.
+// Returns the IDs of frames that can't be stored in a Vorbis comment.
+pub fn write_tag(path: &Path, tag: &id3::Tag) -> anyhow::Result<Vec<String>> {
+    let bytes = fs::read(path)?;
+    let file = parse(&bytes)?;
+
+    let (vendor, _) = read_comments(&file)?;
+    let original_pictures = read_pictures(&file)?;
+    let converted = vorbis::from_id3_tag(tag, &original_pictures);
+
+    let mut blocks = file.blocks.iter().
+        filter(|b| ![VORBIS_COMMENT, PICTURE, PADDING].contains(&b.kind)).
+        cloned().
+        collect::<Vec<_>>();
+
+    blocks.push(Block { kind: VORBIS_COMMENT, data: vorbis::encode_comments(&vendor, &converted.comments) });
+    for picture in &converted.pictures {
+        blocks.push(Block { kind: PICTURE, data: picture.encode() });
+    }
+
+    let old_size = file.audio_offset - MAGIC.len();
+    let new_size = blocks.iter().map(|b| BLOCK_HEADER_SIZE + b.data.len()).sum::<usize>();
+
+    // A single padding block can't hold more than the size limit, so if the old metadata was much
+    // bigger, the audio moves after all.
+    let padding = match old_size.checked_sub(new_size + BLOCK_HEADER_SIZE) {
+        Some(padding) if padding <= MAX_BLOCK_SIZE => padding,
+        _                                          => DEFAULT_PADDING,
+    };
+    blocks.push(Block { kind: PADDING, data: vec![0; padding] });
+
+    if let Some(block) = blocks.iter().find(|b| b.data.len() > MAX_BLOCK_SIZE) {
+        return Err(anyhow!("FLAC metadata block is too large: {} bytes", block.data.len()));
+    }
+
+    let mut output = MAGIC.to_vec();
+    let last_index = blocks.len() - 1;
+
+    for (index, block) in blocks.iter().enumerate() {
+        let flag = if index == last_index { LAST_BLOCK_FLAG } else { 0 };
+        let length = (block.data.len() as u32).to_be_bytes();
+
+        output.push(block.kind | flag);
+        output.extend(&length[1..4]);
+        output.extend(&block.data);
+    }
+    output.extend(&bytes[file.audio_offset..]);
+
+    fs::write(path, output)?;
+    Ok(converted.skipped)
+}
+
+fn parse(bytes: &[u8]) -> anyhow::Result<FlacFile> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(anyhow!("Invalid FLAC file: missing \"fLaC\" marker"));
+    }
+
+    let mut blocks = Vec::new();
+    let mut position = MAGIC.len();
+
+    loop {
+        let Some(header) = bytes.get(position..(position + BLOCK_HEADER_SIZE)) else {
+            return Err(anyhow!("Invalid FLAC file: metadata block header at byte {} is cut off", position));
+        };
+
+        let is_last = header[0] & LAST_BLOCK_FLAG != 0;
+        let kind = header[0] & !LAST_BLOCK_FLAG;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let start = position + BLOCK_HEADER_SIZE;
+
+        let Some(data) = bytes.get(start..(start + length)) else {
+            return Err(anyhow!("Invalid FLAC file: metadata block at byte {} is cut off", position));
+        };
+
+        blocks.push(Block { kind, data: data.to_vec() });
+        position = start + length;
+
+        if is_last {
+            break;
+        }
+    }
+
+    if blocks.first().is_none_or(|b| b.kind != STREAMINFO) {
+        return Err(anyhow!("Invalid FLAC file: the first metadata block isn't STREAMINFO"));
+    }
+
+    Ok(FlacFile { blocks, audio_offset: position })
+}
+
+fn read_comments(file: &FlacFile) -> anyhow::Result<(String, Vec<(String, String)>)> {
+    match file.blocks.iter().find(|b| b.kind == VORBIS_COMMENT) {
+        Some(block) => vorbis::decode_comments(&block.data),
+        None        => Ok((format!("id3-json {}", env!("CARGO_PKG_VERSION")), Vec::new())),
+    }
+}
+
+fn read_pictures(file: &FlacFile) -> anyhow::Result<Vec<PictureBlock>> {
+    file.blocks.iter().
+        filter(|b| b.kind == PICTURE).
+        map(|b| PictureBlock::decode(&b.data)).
+        collect()
+}
@@ -0,0 +1,273 @@
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Tags written by other programs at the end of MP3 files, between the audio and the ID3v1 tag.
+// They're not read by most players, and they often duplicate (or contradict) the ID3 data.
+//
+// The layout at the end of the file is usually:
+//
+//     [audio] [APEv2] [Lyrics3v2] [ID3v1]
+//
+// Though any of them could be missing, and the APEv2 and Lyrics3v2 tags might be in either order.
+
+const APE_PREAMBLE: &[u8] = b"APETAGEX";
+const APE_FOOTER_SIZE: usize = 32;
+const APE_HAS_HEADER: u32 = 1 << 31;
+
+const LYRICS3V2_BEGIN: &[u8] = b"LYRICSBEGIN";
+const LYRICS3V2_END: &[u8] = b"LYRICS200";
+// 6 digits for the size, and the end marker
+const LYRICS3V2_FOOTER_SIZE: usize = 15;
+
+const ID3V1_SIZE: usize = 128;
+const ID3V1_EXTENDED_SIZE: usize = 227;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApeValue {
+    Text(Vec<String>),
+    Binary(usize),
+    Locator(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForeignTag {
+    Ape {
+        offset: usize,
+        size: usize,
+        version: u32,
+        items: Vec<(String, ApeValue)>,
+    },
+    Lyrics3v2 {
+        offset: usize,
+        size: usize,
+        fields: Vec<(String, String)>,
+    },
+}
+
+impl ForeignTag {
+    pub fn offset(&self) -> usize {
+        match self {
+            ForeignTag::Ape { offset, .. } | ForeignTag::Lyrics3v2 { offset, .. } => *offset,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            ForeignTag::Ape { size, .. } | ForeignTag::Lyrics3v2 { size, .. } => *size,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            ForeignTag::Ape { offset, size, version, items } => {
+                let items = items.iter().
+                    map(|(key, value)| {
+                        let value = match value {
+                            ApeValue::Text(values) if values.len() == 1 => values[0].clone().into(),
+                            ApeValue::Text(values) => values.clone().into(),
+                            ApeValue::Binary(size) => serde_json::json!({ "binary": true, "size": size }),
+                            ApeValue::Locator(url) => serde_json::json!({ "locator": url }),
+                        };
+                        (key.clone(), value)
+                    }).
+                    collect::<serde_json::Map<_, _>>();
+
+                serde_json::json!({
+                    "type":    "APEv2",
+                    "version": if *version >= 2000 { "2.0" } else { "1.0" },
+                    "offset":  offset,
+                    "size":    size,
+                    "items":   items,
+                })
+            },
+            ForeignTag::Lyrics3v2 { offset, size, fields } => {
+                let fields = fields.iter().
+                    map(|(id, value)| (id.clone(), value.clone().into())).
+                    collect::<serde_json::Map<_, _>>();
+
+                serde_json::json!({
+                    "type":   "Lyrics3v2",
+                    "offset": offset,
+                    "size":   size,
+                    "fields": fields,
+                })
+            },
+        }
+    }
+}
+
+// Finds all APEv2 and Lyrics3v2 tags at the end of the file, in the order they appear in.
+pub fn read_from_path(path: &Path) -> anyhow::Result<Vec<ForeignTag>> {
+    let bytes = fs::read(path)?;
+    Ok(read_from_bytes(&bytes))
+}
+
+pub fn read_from_bytes(bytes: &[u8]) -> Vec<ForeignTag> {
+    let mut tags = Vec::new();
+    let mut end = id3v1_start(bytes);
+
+    while let Some(tag) = parse_ape(bytes, end).or_else(|| parse_lyrics3v2(bytes, end)) {
+        // A broken tag that doesn't take up any space would be found again and again
+        if tag.offset() >= end {
+            break;
+        }
+        end = tag.offset();
+        tags.push(tag);
+    }
+
+    tags.reverse();
+    tags
+}
+
+// Where the audio ends: at the first foreign tag, or at the ID3v1 tag if there aren't any.
+pub fn audio_end(bytes: &[u8]) -> usize {
+    read_from_bytes(bytes).first().map(|tag| tag.offset()).unwrap_or_else(|| id3v1_start(bytes))
+}
+
+// Removes all APEv2 and Lyrics3v2 tags, keeping the ID3v1 tag after them, if there is one.
+// Returns the removed tags.
+pub fn strip_from_path(path: &Path) -> anyhow::Result<Vec<ForeignTag>> {
+    let bytes = fs::read(path)?;
+    let tags = read_from_bytes(&bytes);
+
+    let Some(first_tag) = tags.first() else {
+        return Ok(tags);
+    };
+
+    let tail = &bytes[id3v1_start(&bytes)..];
+
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    file.set_len(first_tag.offset() as u64)?;
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(tail)?;
+
+    Ok(tags)
+}
+
+fn id3v1_start(bytes: &[u8]) -> usize {
+    let len = bytes.len();
+
+    if len < ID3V1_SIZE || &bytes[(len - ID3V1_SIZE)..(len - ID3V1_SIZE + 3)] != b"TAG" {
+        return len;
+    }
+
+    if len >= ID3V1_EXTENDED_SIZE && &bytes[(len - ID3V1_EXTENDED_SIZE)..(len - ID3V1_EXTENDED_SIZE + 4)] == b"TAG+" {
+        len - ID3V1_EXTENDED_SIZE
+    } else {
+        len - ID3V1_SIZE
+    }
+}
+
+// The footer has the preamble, version, size of the items and footer, the item count, and flags,
+// all little-endian. The optional header is the same as the footer, and isn't counted in the size.
+fn parse_ape(bytes: &[u8], end: usize) -> Option<ForeignTag> {
+    let footer = bytes.get(end.checked_sub(APE_FOOTER_SIZE)?..end)?;
+    if &footer[0..8] != APE_PREAMBLE {
+        return None;
+    }
+
+    let version    = read_u32_le(&footer[8..12]);
+    let tag_size   = read_u32_le(&footer[12..16]) as usize;
+    let item_count = read_u32_le(&footer[16..20]);
+    let flags      = read_u32_le(&footer[20..24]);
+
+    // The size includes the footer itself
+    if tag_size < APE_FOOTER_SIZE {
+        return None;
+    }
+
+    let items_start = end.checked_sub(tag_size)?;
+    let offset = if flags & APE_HAS_HEADER != 0 {
+        items_start.checked_sub(APE_FOOTER_SIZE)?
+    } else {
+        items_start
+    };
+
+    let mut items = Vec::new();
+    let mut position = items_start;
+    let items_end = end - APE_FOOTER_SIZE;
+
+    for _ in 0..item_count {
+        let value_size  = read_u32_le(bytes.get(position..(position + 4))?) as usize;
+        let item_flags  = read_u32_le(bytes.get((position + 4)..(position + 8))?);
+        let key_start   = position + 8;
+        let key_length  = bytes.get(key_start..items_end)?.iter().position(|b| *b == 0)?;
+        let key         = String::from_utf8_lossy(&bytes[key_start..(key_start + key_length)]).to_string();
+        let value_start = key_start + key_length + 1;
+        let value       = bytes.get(value_start..(value_start + value_size))?;
+
+        let value = match (item_flags >> 1) & 0b11 {
+            0 => ApeValue::Text(String::from_utf8_lossy(value).split('\u{0000}').map(String::from).collect()),
+            2 => ApeValue::Locator(String::from_utf8_lossy(value).to_string()),
+            _ => ApeValue::Binary(value.len()),
+        };
+
+        items.push((key, value));
+        position = value_start + value_size;
+    }
+
+    Some(ForeignTag::Ape { offset, size: end - offset, version, items })
+}
+
+// Lyrics3v2 ends with a 6-digit size and "LYRICS200". The size covers everything from
+// "LYRICSBEGIN" up to the size itself. In between are fields with a 3-letter ID and a 5-digit size.
+fn parse_lyrics3v2(bytes: &[u8], end: usize) -> Option<ForeignTag> {
+    let footer = bytes.get(end.checked_sub(LYRICS3V2_FOOTER_SIZE)?..end)?;
+    if &footer[6..] != LYRICS3V2_END {
+        return None;
+    }
+
+    let size = parse_digits(&footer[0..6])?;
+    let offset = (end - LYRICS3V2_FOOTER_SIZE).checked_sub(size)?;
+    let content = &bytes[offset..(end - LYRICS3V2_FOOTER_SIZE)];
+
+    if !content.starts_with(LYRICS3V2_BEGIN) {
+        return None;
+    }
+
+    let mut fields = Vec::new();
+    let mut rest = &content[LYRICS3V2_BEGIN.len()..];
+
+    while rest.len() >= 8 {
+        let id = String::from_utf8_lossy(&rest[0..3]).to_string();
+        let Some(field_size) = parse_digits(&rest[3..8]) else { break };
+        let Some(value) = rest.get(8..(8 + field_size)) else { break };
+
+        // Lyrics3 is ISO-8859-1, which maps directly to the first 256 unicode code points
+        fields.push((id, value.iter().map(|b| char::from(*b)).collect()));
+        rest = &rest[(8 + field_size)..];
+    }
+
+    Some(ForeignTag::Lyrics3v2 { offset, size: end - offset, fields })
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn parse_digits(bytes: &[u8]) -> Option<usize> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id3v1_start() {
+        assert_eq!(id3v1_start(b"short"), 5);
+
+        let mut bytes = vec![0; 200];
+        assert_eq!(id3v1_start(&bytes), 200);
+
+        bytes[72..75].copy_from_slice(b"TAG");
+        assert_eq!(id3v1_start(&bytes), 72);
+    }
+
+    #[test]
+    fn test_parse_digits() {
+        assert_eq!(parse_digits(b"000123"), Some(123));
+        assert_eq!(parse_digits(b"00012a"), None);
+    }
+}
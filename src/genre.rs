@@ -0,0 +1,135 @@
+// The ID3v1 genre list, with the Winamp extensions. The first 80 are a part of the ID3v1 spec,
+// but most players support the rest as well. It's still used in ID3v2 tags as references like
+// "(17)" in ID3v2.3 or just "17" in ID3v2.4.
+pub const GENRES: &[&str] = &[
+    "Blues", "Classic Rock", "Country", "Dance", "Disco", "Funk", "Grunge", "Hip-Hop",
+    "Jazz", "Metal", "New Age", "Oldies", "Other", "Pop", "R&B", "Rap",
+    "Reggae", "Rock", "Techno", "Industrial", "Alternative", "Ska", "Death Metal", "Pranks",
+    "Soundtrack", "Euro-Techno", "Ambient", "Trip-Hop", "Vocal", "Jazz+Funk", "Fusion", "Trance",
+    "Classical", "Instrumental", "Acid", "House", "Game", "Sound Clip", "Gospel", "Noise",
+    "Alternative Rock", "Bass", "Soul", "Punk", "Space", "Meditative", "Instrumental Pop", "Instrumental Rock",
+    "Ethnic", "Gothic", "Darkwave", "Techno-Industrial", "Electronic", "Pop-Folk", "Eurodance", "Dream",
+    "Southern Rock", "Comedy", "Cult", "Gangsta", "Top 40", "Christian Rap", "Pop/Funk", "Jungle",
+    "Native US", "Cabaret", "New Wave", "Psychadelic", "Rave", "Showtunes", "Trailer", "Lo-Fi",
+    "Tribal", "Acid Punk", "Acid Jazz", "Polka", "Retro", "Musical", "Rock & Roll", "Hard Rock",
+    "Folk", "Folk-Rock", "National Folk", "Swing", "Fast Fusion", "Bebop", "Latin", "Revival",
+    "Celtic", "Bluegrass", "Avantgarde", "Gothic Rock", "Progressive Rock", "Psychedelic Rock", "Symphonic Rock", "Slow Rock",
+    "Big Band", "Chorus", "Easy Listening", "Acoustic", "Humour", "Speech", "Chanson", "Opera",
+    "Chamber Music", "Sonata", "Symphony", "Booty Bass", "Primus", "Porn Groove", "Satire", "Slow Jam",
+    "Club", "Tango", "Samba", "Folklore", "Ballad", "Power Ballad", "Rhythmic Soul", "Freestyle",
+    "Duet", "Punk Rock", "Drum Solo", "A capella", "Euro-House", "Dance Hall", "Goa", "Drum & Bass",
+    "Club-House", "Hardcore Techno", "Terror", "Indie", "BritPop", "Negerpunk", "Polsk Punk", "Beat",
+    "Christian Gangsta Rap", "Heavy Metal", "Black Metal", "Crossover", "Contemporary Christian", "Christian Rock", "Merengue", "Salsa",
+    "Thrash Metal", "Anime", "JPop", "Synthpop", "Abstract", "Art Rock", "Baroque", "Bhangra",
+    "Big Beat", "Breakbeat", "Chillout", "Downtempo", "Dub", "EBM", "Eclectic", "Electro",
+    "Electroclash", "Emo", "Experimental", "Garage", "Global", "IDM", "Illbient", "Industro-Goth",
+    "Jam Band", "Krautrock", "Leftfield", "Lounge", "Math Rock", "New Romantic", "Nu-Breakz", "Post-Punk",
+    "Post-Rock", "Psytrance", "Shoegaze", "Space Rock", "Trop Rock", "World Music", "Neoclassical", "Audiobook",
+    "Audio Theatre", "Neue Deutsche Welle", "Podcast", "Indie Rock", "G-Funk", "Dubstep", "Garage Rock", "Psybient",
+];
+
+// In ID3v1, this is used for "no genre".
+pub const NO_GENRE: u8 = 255;
+
+pub fn genre_name(id: u32) -> Option<&'static str> {
+    GENRES.get(usize::try_from(id).ok()?).copied()
+}
+
+pub fn genre_id(name: &str) -> Option<u8> {
+    let index = GENRES.iter().position(|g| g.eq_ignore_ascii_case(name.trim()))?;
+    // Unwrap: the list is shorter than 255 entries
+    Some(u8::try_from(index).unwrap())
+}
+
+// Resolves a single TCON value into genre names. It can be:
+//
+// - A plain genre name: "Rock"
+// - An ID3v2.4 numeric reference: "17"
+// - One or more ID3v2.3 references, optionally followed by a refinement: "(17)", "(4)Eurodisco"
+// - The special references "(RX)" for "Remix" and "(CR)" for "Cover"
+// - A name that starts with a parenthesis, escaped with another one: "((Unknown)"
+//
+// A refinement is kept alongside the references, since it's often just the name of the referenced
+// genre, which gets deduplicated later, and sometimes a more specific one, which shouldn't replace
+// it. Unknown numeric references are left as-is.
+pub fn resolve(value: &str) -> Vec<String> {
+    let value = value.trim();
+
+    if value.is_empty() {
+        return Vec::new();
+    }
+
+    if let Ok(id) = value.parse::<u32>() {
+        return match genre_name(id) {
+            Some(name)                        => vec![name.to_string()],
+            None if id == u32::from(NO_GENRE) => Vec::new(),
+            None                              => vec![value.to_string()],
+        };
+    }
+
+    let mut genres = Vec::new();
+    let mut rest = value;
+
+    while rest.starts_with('(') && !rest.starts_with("((") {
+        let Some(end) = rest.find(')') else { break };
+
+        match &rest[1..end] {
+            "RX" => genres.push(String::from("Remix")),
+            "CR" => genres.push(String::from("Cover")),
+            reference => {
+                let Ok(id) = reference.parse::<u32>() else { break };
+
+                match genre_name(id) {
+                    Some(name) => genres.push(name.to_string()),
+                    None if id == u32::from(NO_GENRE) => (),
+                    None => genres.push(rest[..=end].to_string()),
+                }
+            },
+        }
+
+        rest = &rest[(end + 1)..];
+    }
+
+    let refinement = rest.strip_prefix('(').filter(|r| r.starts_with('(')).unwrap_or(rest).trim();
+
+    if !refinement.is_empty() {
+        genres.push(refinement.to_string());
+    }
+
+    genres
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_genre_lookup() {
+        assert_eq!(genre_name(17), Some("Rock"));
+        assert_eq!(genre_name(191), Some("Psybient"));
+        assert_eq!(genre_name(192), None);
+
+        assert_eq!(genre_id("Rock"), Some(17));
+        assert_eq!(genre_id("hip-hop"), Some(7));
+        assert_eq!(genre_id("Not a genre"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("Rock"), vec!["Rock"]);
+        assert_eq!(resolve("17"), vec!["Rock"]);
+        assert_eq!(resolve("(17)"), vec!["Rock"]);
+        assert_eq!(resolve("(17)Rock"), vec!["Rock", "Rock"]);
+        assert_eq!(resolve("(4)Eurodisco"), vec!["Disco", "Eurodisco"]);
+        assert_eq!(resolve("(51)(39)"), vec!["Techno-Industrial", "Noise"]);
+        assert_eq!(resolve("(RX)(CR)"), vec!["Remix", "Cover"]);
+        assert_eq!(resolve("((Unknown)"), vec!["(Unknown)"]);
+        assert_eq!(resolve("(Live)"), vec!["(Live)"]);
+
+        // Unknown references are kept, "no genre" is removed
+        assert_eq!(resolve("(254)"), vec!["(254)"]);
+        assert_eq!(resolve("(255)"), Vec::<String>::new());
+        assert_eq!(resolve("255"), Vec::<String>::new());
+        assert_eq!(resolve(""), Vec::<String>::new());
+    }
+}
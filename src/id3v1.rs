@@ -0,0 +1,152 @@
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use id3::TagLike;
+
+use crate::date;
+use crate::genre;
+
+const TEXT_FIELD_SIZE: usize = 30;
+const COMMENT_SIZE_WITH_TRACK: usize = 28;
+
+// Builds an ID3v1.1 tag from the ID3v2 data. The fields are limited to 30 bytes of ISO-8859-1,
+// so anything longer gets truncated, and characters outside of it are replaced with "?". The
+// genre can only be one of the ID3v1 list. Returns any warnings about data that didn't fit.
+pub fn from_v2_tag(tag: &id3::Tag) -> (id3::v1::Tag, Vec<String>) {
+    let mut truncated = Vec::new();
+    let mut warnings = Vec::new();
+
+    let track = tag.track().and_then(|t| u8::try_from(t).ok()).filter(|t| *t > 0);
+    if track.is_none() && tag.track().is_some_and(|t| t > 0) {
+        warnings.push(String::from("The track number doesn't fit in ID3v1, it needs to be under 256"));
+    }
+
+    let comment_size = if track.is_some() { COMMENT_SIZE_WITH_TRACK } else { TEXT_FIELD_SIZE };
+    let comment = tag.comments().
+        find(|c| c.description.is_empty()).
+        map(|c| c.text.trim_end_matches('\u{0000}').to_string()).
+        unwrap_or_default();
+
+    let mut field = |label: &'static str, value: &str, size: usize| {
+        let (value, was_truncated) = truncate(value, size);
+        if was_truncated {
+            truncated.push(label);
+        }
+        value
+    };
+
+    let title   = field("title", first_value(tag.title()), TEXT_FIELD_SIZE);
+    let artist  = field("artist", first_value(tag.artist()), TEXT_FIELD_SIZE);
+    let album   = field("album", first_value(tag.album()), TEXT_FIELD_SIZE);
+    let comment = field("comment", &comment, comment_size);
+
+    let year = date::read_recording_date(tag).
+        filter(|ts| (0..=9999).contains(&ts.year)).
+        map(|ts| format!("{:04}", ts.year)).
+        unwrap_or_default();
+
+    let genres = tag.genre().map(|g| {
+        g.split('\u{0000}').flat_map(genre::resolve).collect::<Vec<_>>()
+    }).unwrap_or_default();
+
+    let genre_id = match genres.iter().find_map(|g| genre::genre_id(g)) {
+        Some(id) => id,
+        None => {
+            if let Some(name) = genres.first() {
+                warnings.push(format!("The ID3v1 genre list has no entry for {:?}", name));
+            }
+            genre::NO_GENRE
+        },
+    };
+
+    if !truncated.is_empty() {
+        warnings.insert(0, format!("ID3v1 fields were truncated: {}", truncated.join(", ")));
+    }
+
+    let v1_tag = id3::v1::Tag {
+        title, artist, album, year, comment, track, genre_id,
+        ..id3::v1::Tag::default()
+    };
+
+    (v1_tag, warnings)
+}
+
+// Encodes the tag into the 128 bytes that go at the end of the file. The extended "TAG+" block
+// isn't written, since hardly anything supports it.
+pub fn encode(tag: &id3::v1::Tag) -> [u8; 128] {
+    let mut bytes = [0; 128];
+
+    bytes[0..3].copy_from_slice(b"TAG");
+    encode_str(&tag.title, &mut bytes[3..33]);
+    encode_str(&tag.artist, &mut bytes[33..63]);
+    encode_str(&tag.album, &mut bytes[63..93]);
+    encode_str(&tag.year, &mut bytes[93..97]);
+
+    if let Some(track) = tag.track {
+        encode_str(&tag.comment, &mut bytes[97..125]);
+        bytes[125] = 0;
+        bytes[126] = track;
+    } else {
+        encode_str(&tag.comment, &mut bytes[97..127]);
+    }
+
+    bytes[127] = tag.genre_id;
+    bytes
+}
+
+// Replaces any existing ID3v1 tag at the end of the file with the given one.
+pub fn write_to_path(path: &Path, tag: &id3::v1::Tag) -> anyhow::Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+
+    id3::v1::Tag::remove_from_file(&mut file)?;
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(&encode(tag))?;
+
+    Ok(())
+}
+
+pub fn is_present(path: &Path) -> anyhow::Result<bool> {
+    Ok(id3::v1::Tag::is_candidate(fs::File::open(path)?)?)
+}
+
+pub fn strip_from_path(path: &Path) -> anyhow::Result<bool> {
+    Ok(id3::v1::Tag::remove_from_path(path)?)
+}
+
+fn first_value(text: Option<&str>) -> &str {
+    text.unwrap_or_default().split('\u{0000}').next().unwrap_or_default()
+}
+
+// ISO-8859-1 code points match the first 256 unicode ones, so anything above that is replaced.
+fn truncate(value: &str, size: usize) -> (String, bool) {
+    let value = value.chars().
+        map(|c| if u32::from(c) <= 0xFF { c } else { '?' }).
+        collect::<String>();
+
+    if value.chars().count() > size {
+        (value.chars().take(size).collect(), true)
+    } else {
+        (value, false)
+    }
+}
+
+fn encode_str(value: &str, target: &mut [u8]) {
+    for (byte, c) in target.iter_mut().zip(value.chars()) {
+        *byte = u8::try_from(u32::from(c)).unwrap_or(b'?');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("Short", 30), (String::from("Short"), false));
+        assert_eq!(truncate("Exactly five", 12), (String::from("Exactly five"), false));
+        assert_eq!(truncate("Too long", 3), (String::from("Too"), true));
+        assert_eq!(truncate("Café", 30), (String::from("Café"), false));
+        assert_eq!(truncate("Кафе", 30), (String::from("????"), false));
+    }
+}
@@ -3,13 +3,67 @@ use std::ffi::OsString;
 
 use lexopt::prelude::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum V1Mode {
+    Write,
+    Sync,
+    Strip,
+}
+
+// How POPM ratings map to stars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RatingScale {
+    #[default]
+    Wmp,
+    Foobar,
+}
+
+// Files for --import-chapters and --export-chapters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChapterFormat {
+    FfMetadata,
+    Podlove,
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    Track,
+    Album,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DupesMatch {
+    Tags,
+    Audio,
+    #[default]
+    Any,
+}
+
 #[derive(Debug, PartialEq, Eq, Default)]
 pub struct Args {
     pub filename: PathBuf,
     pub read: bool,
     pub write: bool,
     pub with_covers: bool,
+    pub join_values: bool,
+    pub raw_genres: bool,
+    pub numeric_genres: bool,
+    pub strict_conversion: bool,
+    pub strip_foreign_tags: bool,
+    pub audio: bool,
+    pub verify_audio: bool,
+    pub value_separator: Option<String>,
     pub tag_version: Option<id3::Version>,
+    pub v1: Option<V1Mode>,
+    pub rating_scale: RatingScale,
+    pub replaygain: Option<ReplayGainMode>,
+    // The rest of the album, with `--replaygain album`
+    pub album_filenames: Vec<PathBuf>,
+    pub import_chapters: Option<PathBuf>,
+    pub export_chapters: Option<PathBuf>,
+    // Detected from the contents or the extension if not given
+    pub chapters_format: Option<ChapterFormat>,
     pub in_json: Option<PathBuf>,
     pub out_json: Option<PathBuf>,
 }
@@ -19,22 +73,44 @@ where
     I: IntoIterator + 'static,
     I::Item: Into<OsString>,
 {
-    let mut read        = false;
-    let mut write       = false;
-    let mut with_covers = false;
+    let mut read               = false;
+    let mut write              = false;
+    let mut with_covers        = false;
+    let mut join_values        = false;
+    let mut raw_genres         = false;
+    let mut numeric_genres     = false;
+    let mut strict_conversion  = false;
+    let mut strip_foreign_tags = false;
+    let mut audio              = false;
+    let mut verify_audio       = false;
 
-    let mut filename_input = None;
-    let mut tag_version    = None;
-    let mut in_json        = None;
-    let mut out_json       = None;
+    let mut filename_input  = None;
+    let mut tag_version     = None;
+    let mut value_separator = None;
+    let mut v1              = None;
+    let mut rating_scale    = RatingScale::default();
+    let mut replaygain      = None;
+    let mut album_filenames = Vec::new();
+    let mut import_chapters = None;
+    let mut export_chapters = None;
+    let mut chapters_format = None;
+    let mut in_json         = None;
+    let mut out_json        = None;
 
     let mut parser = lexopt::Parser::from_iter(args);
 
     while let Some(arg) = parser.next()? {
         match arg {
-            Short('r') | Long("read")  => read        = true,
-            Short('w') | Long("write") => write       = true,
-            Long("with-covers")        => with_covers = true,
+            Short('r') | Long("read")  => read               = true,
+            Short('w') | Long("write") => write              = true,
+            Long("with-covers")        => with_covers        = true,
+            Long("join-values")        => join_values        = true,
+            Long("raw-genres")         => raw_genres         = true,
+            Long("numeric-genres")     => numeric_genres     = true,
+            Long("strict-conversion")  => strict_conversion  = true,
+            Long("strip-foreign-tags") => strip_foreign_tags = true,
+            Long("audio")              => audio              = true,
+            Long("verify-audio")       => verify_audio       = true,
 
             Long("tag-version") => {
                 let mut input = parser.value()?;
@@ -51,9 +127,68 @@ where
                     return Err(lexopt::Error::Custom(error.into()));
                 }
             },
+            Long("v1") => {
+                let mut input = parser.value()?;
+                input.make_ascii_lowercase();
+
+                if input == "write" {
+                    v1 = Some(V1Mode::Write);
+                } else if input == "sync" {
+                    v1 = Some(V1Mode::Sync);
+                } else if input == "strip" {
+                    v1 = Some(V1Mode::Strip);
+                } else {
+                    let error = format!("Unsupported ID3v1 mode: {:?}. Expected write, sync, or strip", input);
+                    return Err(lexopt::Error::Custom(error.into()));
+                }
+            },
+            Long("rating-scale") => {
+                let mut input = parser.value()?;
+                input.make_ascii_lowercase();
+
+                if input == "wmp" {
+                    rating_scale = RatingScale::Wmp;
+                } else if input == "foobar" {
+                    rating_scale = RatingScale::Foobar;
+                } else {
+                    let error = format!("Unsupported rating scale: {:?}. Expected wmp or foobar", input);
+                    return Err(lexopt::Error::Custom(error.into()));
+                }
+            },
+            Long("replaygain") => {
+                let mut input = parser.value()?;
+                input.make_ascii_lowercase();
+
+                if input == "track" {
+                    replaygain = Some(ReplayGainMode::Track);
+                } else if input == "album" {
+                    replaygain = Some(ReplayGainMode::Album);
+                } else {
+                    let error = format!("Unsupported ReplayGain mode: {:?}. Expected track or album", input);
+                    return Err(lexopt::Error::Custom(error.into()));
+                }
+            },
+            Long("chapters-format") => {
+                let mut input = parser.value()?;
+                input.make_ascii_lowercase();
+
+                if input == "ffmetadata" {
+                    chapters_format = Some(ChapterFormat::FfMetadata);
+                } else if input == "podlove" {
+                    chapters_format = Some(ChapterFormat::Podlove);
+                } else if input == "text" {
+                    chapters_format = Some(ChapterFormat::Text);
+                } else {
+                    let error = format!("Unsupported chapter format: {:?}. Expected ffmetadata, podlove, or text", input);
+                    return Err(lexopt::Error::Custom(error.into()));
+                }
+            },
             Value(val) if filename_input.is_none() => {
                 filename_input = Some(PathBuf::from(val));
             },
+            Value(val) => {
+                album_filenames.push(PathBuf::from(val));
+            },
 
             Short('i') | Long("in-json") => {
                 let input = parser.value()?.into();
@@ -63,6 +198,19 @@ where
                 let input = parser.value()?.into();
                 out_json = Some(input);
             },
+            Long("import-chapters") => {
+                let input = parser.value()?.into();
+                import_chapters = Some(input);
+            },
+            Long("export-chapters") => {
+                let input = parser.value()?.into();
+                export_chapters = Some(input);
+            },
+
+            Long("value-separator") => {
+                let input = parser.value()?.string()?;
+                value_separator = Some(input);
+            },
 
             Short('V') | Long("version") => {
                 println!("id3-json {}", env!("CARGO_PKG_VERSION"));
@@ -81,11 +229,81 @@ where
         return Err(lexopt::Error::Custom(error.into()));
     };
 
+    if !album_filenames.is_empty() && replaygain != Some(ReplayGainMode::Album) {
+        let error = String::from("Multiple files are only supported with --replaygain album");
+        return Err(lexopt::Error::Custom(error.into()));
+    }
+
     if !read && !write {
         read = true;
     }
 
-    Ok(Args { filename, read, write, with_covers, tag_version, in_json, out_json })
+    Ok(Args {
+        filename, read, write, with_covers, join_values, value_separator,
+        raw_genres, numeric_genres, strict_conversion, strip_foreign_tags,
+        audio, verify_audio, tag_version, v1, rating_scale, replaygain, album_filenames,
+        import_chapters, export_chapters, chapters_format, in_json, out_json,
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct DupesArgs {
+    pub paths: Vec<PathBuf>,
+    pub by: DupesMatch,
+    pub out_json: Option<PathBuf>,
+}
+
+// The arguments of `id3-json dupes`, without the "dupes" itself
+pub fn parse_dupes_args<I>(args: I) -> Result<DupesArgs, lexopt::Error>
+where
+    I: IntoIterator + 'static,
+    I::Item: Into<OsString>,
+{
+    let mut paths    = Vec::new();
+    let mut by       = DupesMatch::default();
+    let mut out_json = None;
+
+    let mut parser = lexopt::Parser::from_iter(args);
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("by") => {
+                let mut input = parser.value()?;
+                input.make_ascii_lowercase();
+
+                if input == "tags" {
+                    by = DupesMatch::Tags;
+                } else if input == "audio" {
+                    by = DupesMatch::Audio;
+                } else if input == "any" {
+                    by = DupesMatch::Any;
+                } else {
+                    let error = format!("Unsupported match: {:?}. Expected tags, audio, or any", input);
+                    return Err(lexopt::Error::Custom(error.into()));
+                }
+            },
+            Short('o') | Long("out-json") => {
+                let input = parser.value()?.into();
+                out_json = Some(input);
+            },
+            Value(val) => {
+                paths.push(PathBuf::from(val));
+            },
+
+            Long("help") => {
+                print_dupes_help();
+                std::process::exit(0);
+            },
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    if paths.is_empty() {
+        let error = String::from("Missing <path>");
+        return Err(lexopt::Error::Custom(error.into()));
+    }
+
+    Ok(DupesArgs { paths, by, out_json })
 }
 
 fn print_help() {
@@ -93,6 +311,8 @@ fn print_help() {
     println!();
     println!("USAGE:");
     println!("    id3-json [FLAGS] <music-file.mp3>");
+    println!("    id3-json --replaygain album [FLAGS] <music-file.mp3> <other-tracks.mp3>...");
+    println!("    id3-json dupes [FLAGS] <path>...");
     println!();
     println!("FLAGS:");
     println!("    -r, --read       Reads tags from the file and outputs them to STDOUT as JSON,");
@@ -106,6 +326,29 @@ fn print_help() {
     println!("    --with-covers    Also output cover images as base64-encoded data.");
     println!("                     If not set, only cover metadata will be returned.");
     println!();
+    println!("    --join-values    Text frames with multiple values are returned as arrays by default.");
+    println!("                     With this flag, they're joined into a single string using the");
+    println!("                     --value-separator instead.");
+    println!();
+    println!("        --value-separator <separator>");
+    println!("                     On write, joins arrays of values for tags older than ID3v2.4,");
+    println!("                     which can't store multiple values. Defaults to \"/\".");
+    println!();
+    println!("    --raw-genres     Output genres as they're stored in the tag. By default, ID3v1 genre");
+    println!("                     references like \"(17)\" are resolved to names like \"Rock\".");
+    println!();
+    println!("    --audio          Also output an \"audio\" section with the duration, bitrate, and other");
+    println!("                     stream properties of MPEG files, from their frame headers, and an");
+    println!("                     \"audio_hash\" of the audio frames, without the tags. The hash needs");
+    println!("                     the whole file, so it isn't part of a plain read.");
+    println!();
+    println!("    --verify-audio   Walk every MPEG frame and report sync errors, garbage between frames,");
+    println!("                     truncated frames, and frame counts that don't match the VBR header");
+    println!("                     as \"audio_verification\".");
+    println!();
+    println!("    --numeric-genres On write, store genres from the ID3v1 list as numeric references,");
+    println!("                     for legacy players that don't understand genre names.");
+    println!();
     println!("    -i, --in-json <path/to.json>");
     println!("                     File to read tags from. If not given, uses STDIN");
     println!();
@@ -114,9 +357,70 @@ fn print_help() {
     println!();
     println!("        --tag-version <ID3v2.{{2,3,4}}>");
     println!("                     On write, sets the tags' version to 2.2, 2.3, or 2.4.");
+    println!("                     Frames are converted to the ones used by that version, and a");
+    println!("                     report of the changes is added to the output as \"conversion\".");
+    println!();
+    println!("        --strict-conversion");
+    println!("                     Refuse to write the file if converting the tag's version would");
+    println!("                     drop frames or lose information.");
+    println!();
+    println!("        --v1 <write|sync|strip>");
+    println!("                     write: Creates an ID3v1.1 tag at the end of the file from the ID3v2 data.");
+    println!("                     sync:  Updates the ID3v1 tag from the ID3v2 data, only if there is one.");
+    println!("                     strip: Removes the ID3v1 tag.");
+    println!();
+    println!("        --rating-scale <wmp|foobar>");
+    println!("                     How POPM ratings from 1 to 255 map to 1 to 5 stars.");
+    println!("                     wmp:    1, 64, 128, 196, 255, like Windows Media Player. The default.");
+    println!("                     foobar: 51, 102, 153, 204, 255, like foobar2000.");
+    println!();
+    println!("        --replaygain <track|album>");
+    println!("                     Decodes the audio, calculates ReplayGain 2.0 values, and writes them");
+    println!("                     as TXXX REPLAYGAIN_* frames, and as RVA2 frames for ID3v2.4 tags.");
+    println!("                     album: Also calculates album values from all the given files, and");
+    println!("                     writes them to each one.");
+    println!();
+    println!("        --import-chapters <path>");
+    println!("                     Replaces the file's chapters with the ones in an FFMETADATA,");
+    println!("                     Podlove JSON, or \"HH:MM:SS Title\" text file. They can't overlap,");
+    println!("                     or go past the end of the audio, so this only works for MPEG files.");
+    println!();
+    println!("        --export-chapters <path>");
+    println!("                     Writes the file's chapters to a file in one of these formats.");
+    println!();
+    println!("        --chapters-format <ffmetadata|podlove|text>");
+    println!("                     The format of the chapter files. By default, detected from the");
+    println!("                     contents on import, and from the extension on export: .json is");
+    println!("                     podlove, .txt is text, and anything else is ffmetadata.");
+    println!();
+    println!("        --strip-foreign-tags");
+    println!("                     Removes APEv2 and Lyrics3v2 tags from the end of the file,");
+    println!("                     keeping the ID3v1 tag, if there is one.");
     println!();
     println!("    -V, --version    Print version information");
     println!();
     println!("ARGS:");
     println!("    <music-file.mp3>    Music file to read tags from or write tags to");
 }
+
+fn print_dupes_help() {
+    println!("id3-json {}", env!("CARGO_PKG_VERSION"));
+    println!();
+    println!("Finds copies of the same recording in the given files and directories, and prints");
+    println!("them as JSON groups, the best copy first.");
+    println!();
+    println!("USAGE:");
+    println!("    id3-json dupes [FLAGS] <path>...");
+    println!();
+    println!("FLAGS:");
+    println!("        --by <tags|audio|any>");
+    println!("                     tags:  Same artist and title, and about the same duration.");
+    println!("                     audio: Same audio_hash, regardless of tags.");
+    println!("                     any:   Either one of these. The default.");
+    println!();
+    println!("    -o, --out-json <path/to.json>");
+    println!("                     File to write the groups to. If not given, uses STDOUT");
+    println!();
+    println!("ARGS:");
+    println!("    <path>...           Music files, or directories to search recursively");
+}
@@ -4,6 +4,44 @@ use id3::frame::{Frame, Content, Picture, PictureType};
 use base64::prelude::*;
 
 use crate::input::Args;
+use crate::chapters;
+use crate::date;
+use crate::genre;
+use crate::rating;
+use crate::replaygain;
+use crate::volume::{self, Equalization, VolumeAdjustment};
+
+// Used to join multiple values in text frames for tags older than ID3v2.4, which don't support
+// NUL-separated values. The ID3v2.3 spec uses "/" for artists, composers, and lyricists.
+pub const DEFAULT_VALUE_SEPARATOR: &str = "/";
+
+// Plain text frames that are read and written as-is, without any special processing.
+const TEXT_FIELDS: &[(&str, &str)] = &[
+    ("album_artist", "TPE2"),
+    ("composer",     "TCOM"),
+    ("conductor",    "TPE3"),
+    ("remixer",      "TPE4"),
+    ("lyricist",     "TEXT"),
+    ("grouping",     "TIT1"),
+    ("subtitle",     "TIT3"),
+    ("publisher",    "TPUB"),
+    ("copyright",    "TCOP"),
+    ("key",          "TKEY"),
+    ("language",     "TLAN"),
+    ("mood",         "TMOO"),
+    ("isrc",         "TSRC"),
+];
+
+// Sort-order frames, with the ID3v2.2 IDs that iTunes uses for them. TSO2 and TSOC aren't a part
+// of any ID3 standard, but they're what iTunes and most other players use for album artist and
+// composer sorting.
+const SORT_FIELDS: &[(&str, &str, &str)] = &[
+    ("artist_sort",       "TSOP", "TSP"),
+    ("album_sort",        "TSOA", "TSA"),
+    ("title_sort",        "TSOT", "TST"),
+    ("album_artist_sort", "TSO2", "TS2"),
+    ("composer_sort",     "TSOC", "TSC"),
+];
 
 pub fn read_from_tag(tag: &id3::Tag, args: &Args) -> serde_json::Value {
     // There could be many comments, but in my music library, it seems like it's common to just
@@ -15,116 +53,183 @@ pub fn read_from_tag(tag: &id3::Tag, args: &Args) -> serde_json::Value {
 
     let covers = tag.pictures().
         filter(|p| is_cover(p)).
-        map(|p| if args.with_covers {
-            serde_json::json!({
-                "mime_type":   p.mime_type,
-                "type":        cover_type(p),
-                "description": p.description,
-                "size":        p.data.len(),
-                "data":        BASE64_STANDARD.encode(&p.data),
-            })
-        } else {
-            serde_json::json!({
-                "mime_type":   p.mime_type,
-                "type":        cover_type(p),
-                "description": p.description,
-                "size":        p.data.len(),
-            })
-        }).
+        map(|p| picture_to_json(p, args)).
         collect::<Vec<_>>();
 
-    if tag.version() == id3::Version::Id3v24 {
-        serde_json::json!({
-            "version": format!("{}", tag.version()),
-            "data": {
-                "title": tag.title().map(remove_nul_byte),
-                "artist": tag.artist().map(remove_nul_byte),
-                "album": tag.album().map(remove_nul_byte),
-                "track": tag.track(),
-                "date": tag.date_recorded().map(|ts| format!("{}", ts)),
-                "genre": tag.genre().map(remove_nul_byte),
-                "comment": comment,
-                "covers": covers,
-            },
-        })
-    } else {
-        serde_json::json!({
-            "version": format!("{}", tag.version()),
-            "data": {
-                "title": tag.title().map(remove_nul_byte),
-                "artist": tag.artist().map(remove_nul_byte),
-                "album": tag.album().map(remove_nul_byte),
-                "track": tag.track(),
-                "year": tag.year(),
-                "genre": tag.genre().map(remove_nul_byte),
-                "comment": comment,
-                "covers": covers,
-            },
-        })
+    let date = date::read_recording_date(tag);
+
+    let mut json = serde_json::json!({
+        "version": format!("{}", tag.version()),
+        "data": {
+            "title": read_text(tag.get("TIT2"), args),
+            "artist": read_text(tag.get("TPE1"), args),
+            "album": read_text(tag.get("TALB"), args),
+            "track": tag.track(),
+            "track_total": read_number_pair(tag, "TRCK").1,
+            "disc": read_number_pair(tag, "TPOS").0,
+            "disc_total": read_number_pair(tag, "TPOS").1,
+            "date": date.map(|ts| ts.to_string()),
+            "year": date.map(|ts| ts.year),
+            "recording_date": date.map(|ts| ts.to_string()),
+            "release_date": date::read_timestamp(tag, "TDRL").map(|ts| ts.to_string()),
+            "original_date": date::read_original_date(tag).map(|ts| ts.to_string()),
+            "tagging_date": date::read_timestamp(tag, "TDTG").map(|ts| ts.to_string()),
+            "genre": read_genre(tag, args),
+            "comment": comment,
+            "covers": covers,
+        },
+    });
+
+    for (key, frame_id) in TEXT_FIELDS {
+        json["data"][key] = read_text(tag.get(frame_id), args);
     }
+
+    for (key, frame_id, v22_frame_id) in SORT_FIELDS {
+        json["data"][key] = read_text(tag.get(frame_id).or_else(|| tag.get(v22_frame_id)), args);
+    }
+
+    // The BPM is supposed to be an integer, but a non-numeric one shouldn't get lost on reading
+    json["data"]["bpm"] = match tag.get("TBPM").and_then(|f| f.content().text()).map(remove_nul_byte) {
+        Some(bpm) => bpm.parse::<u32>().map(Into::into).unwrap_or_else(|_| bpm.into()),
+        None      => serde_json::Value::Null,
+    };
+
+    json["data"]["ratings"] = rating::read_ratings(tag, args.rating_scale);
+    json["data"]["play_count"] = rating::read_play_count(tag).into();
+    json["data"]["replaygain"] = replaygain::read_from_tag(tag);
+
+    let (volume_adjustments, equalizations) = volume::read_from_tag(tag);
+    json["data"]["volume_adjustments"] = volume_adjustments.iter().map(|v| v.to_json()).collect();
+    json["data"]["equalizations"] = equalizations.iter().map(|e| e.to_json()).collect();
+
+    json["data"]["chapters"] = chapters::read_chapters(tag, args);
+    json["data"]["tables_of_contents"] = chapters::read_tables_of_contents(tag);
+
+    // Set to "1" by iTunes for compilation albums, anything else is treated as "not a compilation"
+    json["data"]["compilation"] = tag.get("TCMP").
+        and_then(|f| f.content().text()).
+        map(|c| remove_nul_byte(c) == "1").
+        into();
+
+    json
+}
+
+// ID3v1 tags have fixed-size fields padded with NUL bytes, so empty ones are returned as null.
+// ID3v1.1 is the same, except it steals two bytes from the comment for a track number.
+pub fn read_from_v1_tag(tag: &id3::v1::Tag) -> serde_json::Value {
+    let non_empty = |value: &str| {
+        let value = value.trim_end_matches(['\u{0000}', ' ']);
+        (!value.is_empty()).then(|| value.to_string())
+    };
+
+    let genre = tag.genre_str.as_deref().
+        and_then(non_empty).
+        or_else(|| genre::genre_name(u32::from(tag.genre_id)).map(String::from));
+
+    serde_json::json!({
+        "version": if tag.track.is_some() { "ID3v1.1" } else { "ID3v1" },
+        "title": non_empty(&tag.title),
+        "artist": non_empty(&tag.artist),
+        "album": non_empty(&tag.album),
+        "year": tag.year.trim().parse::<u32>().ok(),
+        "comment": non_empty(&tag.comment),
+        "track": tag.track,
+        "genre": genre,
+    })
 }
 
 pub fn write_to_tag(
     json_map: &serde_json::Map<String, serde_json::Value>,
     tag: &mut id3::Tag,
-    version: Option<id3::Version>,
+    args: &Args,
 ) -> anyhow::Result<()> {
     // Check for a nested "data" key to read fields from
     if let Some(serde_json::Value::Object(fields_map)) = json_map.get("data") {
-        return write_to_tag(fields_map, tag, version);
+        return write_to_tag(fields_map, tag, args);
     };
 
-    let version = version.unwrap_or_else(|| tag.version());
+    let version = args.tag_version.unwrap_or_else(|| tag.version());
+    let separator = args.value_separator.as_deref().unwrap_or(DEFAULT_VALUE_SEPARATOR);
+    let skipped_date_key = unchanged_date_key(json_map, tag)?;
+    let current_year = date::read_recording_date(tag).map(|date| date.year);
+    let mut removed_chapters = Vec::new();
 
     for (key, value) in json_map {
+        if Some(key.as_str()) == skipped_date_key {
+            continue;
+        }
+
         match key.as_str() {
             "title" => {
-                if let Some(title) = extract_string("title", value)? {
-                    tag.set_title(title);
-                } else {
-                    tag.remove_title();
-                }
+                let values = extract_text_values("title", value)?;
+                write_text(tag, "TIT2", values, version, separator);
             },
             "artist" => {
-                if let Some(artist) = extract_string("artist", value)? {
-                    tag.set_artist(artist);
-                } else {
-                    tag.remove_artist();
-                }
+                let values = extract_text_values("artist", value)?;
+                write_text(tag, "TPE1", values, version, separator);
             },
             "album" => {
-                if let Some(album) = extract_string("album", value)? {
-                    tag.set_album(album);
-                } else {
-                    tag.remove_album();
-                }
+                let values = extract_text_values("album", value)?;
+                write_text(tag, "TALB", values, version, separator);
             },
             "track" => {
-                if let Some(track) = extract_u32("track", value)? {
-                    tag.set_track(track);
-                } else {
-                    tag.remove_track();
-                }
+                let (_, total) = read_number_pair(tag, "TRCK");
+                write_number_pair(tag, "TRCK", extract_u32("track", value)?, total);
             },
-            "year" if version < id3::Version::Id3v24 => {
-                if let Some(year) = extract_u32("year", value)? {
-                    tag.set_year(year.try_into()?);
-                } else {
-                    tag.remove_year();
-                }
+            "track_total" => {
+                let (track, _) = read_number_pair(tag, "TRCK");
+                write_number_pair(tag, "TRCK", track, extract_u32("track_total", value)?);
             },
-            "date" if version >= id3::Version::Id3v24 => {
-                if let Some(date) = extract_string("date", value)? {
-                    tag.set_date_recorded(date.parse()?);
-                } else {
-                    tag.remove_date_recorded();
+            "disc" => {
+                let (_, total) = read_number_pair(tag, "TPOS");
+                write_number_pair(tag, "TPOS", extract_u32("disc", value)?, total);
+            },
+            "disc_total" => {
+                let (disc, _) = read_number_pair(tag, "TPOS");
+                write_number_pair(tag, "TPOS", disc, extract_u32("disc_total", value)?);
+            },
+            // "date" is the one most people care about, so it's kept as a shorthand
+            "date" | "recording_date" => {
+                let date = extract_timestamp(key, value)?;
+                date::write_recording_date(tag, date, version);
+            },
+            "release_date" => {
+                let date = extract_timestamp("release_date", value)?;
+                date::write_timestamp(tag, "TDRL", date);
+            },
+            "original_date" => {
+                let date = extract_timestamp("original_date", value)?;
+                date::write_original_date(tag, date, version);
+            },
+            "tagging_date" => {
+                let date = extract_timestamp("tagging_date", value)?;
+                date::write_timestamp(tag, "TDTG", date);
+            },
+            "year" => {
+                // The year is derived from the date, so if it wasn't changed, it's left alone.
+                // Otherwise, an edited date would get its old year back.
+                let year = extract_u32("year", value)?;
+                if year.map(i64::from) == current_year.map(i64::from) {
+                    continue;
                 }
+
+                // Only the year changes, the rest of the date is kept if it's there
+                let date = match year {
+                    Some(year) => {
+                        let date = date::read_recording_date(tag).unwrap_or_default();
+                        Some(id3::Timestamp { year: year.try_into()?, ..date })
+                    },
+                    None => None,
+                };
+                date::write_recording_date(tag, date, version);
             },
             "genre" => {
-                if let Some(genre) = extract_string("genre", value)? {
-                    tag.set_genre(genre);
+                let values = extract_text_values("genre", value)?;
+
+                if args.numeric_genres {
+                    write_numeric_genres(tag, values, version, separator);
                 } else {
-                    tag.remove_genre();
+                    write_text(tag, "TCON", values, version, separator);
                 }
             },
             "comment" => {
@@ -169,38 +274,79 @@ pub fn write_to_tag(
                 tag.remove("APIC");
 
                 for cover_data in covers {
-                    let cover_data = cover_data.as_object().
-                        ok_or_else(|| anyhow!("Entries in the `covers` array need to be objects"))?;
-
-                    let mime_type = cover_data.get("mime_type").
-                        and_then(serde_json::Value::as_str).
-                        map(String::from).
-                        unwrap_or_else(|| String::from("image/jpeg"));
-
-                    let picture_type = match cover_data.get("type").and_then(serde_json::Value::as_str) {
-                        Some("front") => PictureType::CoverFront,
-                        Some("back")  => PictureType::CoverBack,
-                        None          => PictureType::CoverFront,
-                        _             => PictureType::Other,
-                    };
-
-                    let data_base64 = cover_data.get("data").
-                        and_then(serde_json::Value::as_str).
-                        map(String::from).
-                        ok_or_else(|| anyhow!("Entries in the `covers` array need to have a base64-encoded `data` field"))?;
-                    let data = BASE64_STANDARD.decode(&data_base64)?;
-
-                    let description = cover_data.get("description").
-                        and_then(serde_json::Value::as_str).
-                        map(String::from).
-                        unwrap_or_else(String::new);
-
-                    let picture = Picture { mime_type, picture_type, data, description };
-
+                    let picture = picture_from_json("Entries in the `covers` array", cover_data)?;
                     tag.add_frame(Frame::with_content("APIC", Content::Picture(picture)));
                 }
             },
-            _ => (),
+            "ratings" => {
+                rating::write_ratings(tag, value, args.rating_scale)?;
+            },
+            "play_count" => {
+                let play_count = extract_u64("play_count", value)?;
+                rating::write_play_count(tag, play_count);
+            },
+            "volume_adjustments" => {
+                let entries = value.as_array().
+                    ok_or_else(|| anyhow!("The `volume_adjustments` key needs to be an array of entries"))?;
+                let adjustments = entries.iter().
+                    map(VolumeAdjustment::from_json).
+                    collect::<anyhow::Result<Vec<_>>>()?;
+
+                tag.remove("RVA2");
+                for adjustment in adjustments {
+                    volume::set_volume_adjustment(tag, &adjustment);
+                }
+            },
+            "equalizations" => {
+                let entries = value.as_array().
+                    ok_or_else(|| anyhow!("The `equalizations` key needs to be an array of entries"))?;
+                let equalizations = entries.iter().
+                    map(Equalization::from_json).
+                    collect::<anyhow::Result<Vec<_>>>()?;
+
+                tag.remove("EQU2");
+                for equalization in equalizations {
+                    tag.add_frame(equalization.to_frame());
+                }
+            },
+            "chapters" => {
+                removed_chapters = chapters::write_chapters(tag, value)?;
+            },
+            "tables_of_contents" => {
+                chapters::write_tables_of_contents(tag, value, &removed_chapters)?;
+            },
+            "bpm" => {
+                if let Some(bpm) = extract_u32("bpm", value)? {
+                    tag.set_text("TBPM", bpm.to_string());
+                } else {
+                    tag.remove("TBPM");
+                }
+            },
+            "compilation" => {
+                if let Some(compilation) = extract_bool("compilation", value)? {
+                    tag.set_text("TCMP", if compilation { "1" } else { "0" });
+                } else {
+                    tag.remove("TCMP");
+                }
+            },
+            _ => {
+                if let Some((key, frame_id)) = TEXT_FIELDS.iter().find(|(k, _)| k == key) {
+                    let values = extract_text_values(key, value)?;
+                    write_text(tag, frame_id, values, version, separator);
+                } else if let Some((key, frame_id, v22_frame_id)) = SORT_FIELDS.iter().find(|(k, _, _)| k == key) {
+                    let values = extract_text_values(key, value)?;
+                    tag.remove(frame_id);
+                    tag.remove(v22_frame_id);
+
+                    // There's no standard ID3v2.2 mapping for these, so the id3 crate can't
+                    // convert them when writing the tag.
+                    if version == id3::Version::Id3v22 {
+                        write_text(tag, v22_frame_id, values, version, separator);
+                    } else {
+                        write_text(tag, frame_id, values, version, separator);
+                    }
+                }
+            },
         }
     }
 
@@ -208,6 +354,34 @@ pub fn write_to_tag(
 }
 
 
+// Both "date" and "recording_date" are read from the same frames, so JSON that was read, edited,
+// and written back has both of them, but only one was changed. The one that's the same as the
+// tag's current date is skipped. If both are different from it, and from each other, it's not
+// clear which one is right.
+fn unchanged_date_key(
+    json_map: &serde_json::Map<String, serde_json::Value>,
+    tag: &id3::Tag,
+) -> anyhow::Result<Option<&'static str>> {
+    let (Some(date), Some(recording_date)) = (json_map.get("date"), json_map.get("recording_date")) else {
+        return Ok(None);
+    };
+
+    let date = extract_timestamp("date", date)?;
+    let recording_date = extract_timestamp("recording_date", recording_date)?;
+    let current_date = date::read_recording_date(tag);
+
+    if date == recording_date || recording_date == current_date {
+        Ok(Some("recording_date"))
+    } else if date == current_date {
+        Ok(Some("date"))
+    } else {
+        Err(anyhow!(
+            "The `date` and `recording_date` keys are the same field, but have different values: {:?} and {:?}",
+            json_map["date"], json_map["recording_date"],
+        ))
+    }
+}
+
 fn extract_string(label: &str, json_value: &serde_json::Value) -> anyhow::Result<Option<String>> {
     match json_value {
         serde_json::Value::Null          => Ok(None),
@@ -217,23 +391,242 @@ fn extract_string(label: &str, json_value: &serde_json::Value) -> anyhow::Result
 }
 
 fn extract_u32(label: &str, json_value: &serde_json::Value) -> anyhow::Result<Option<u32>> {
+    match extract_u64(label, json_value)? {
+        Some(value) => Ok(Some(value.try_into()?)),
+        None        => Ok(None),
+    }
+}
+
+fn extract_u64(label: &str, json_value: &serde_json::Value) -> anyhow::Result<Option<u64>> {
     let invalid_number = || anyhow!("Invalid numeric value for \"{}\": {:?}", label, json_value);
 
     match json_value {
         serde_json::Value::Null => Ok(None),
         serde_json::Value::String(value) => Ok(Some(value.parse()?)),
-        serde_json::Value::Number(number) => {
-            let value = number.as_u64().ok_or_else(invalid_number)?.try_into()?;
-            Ok(Some(value))
-        },
+        serde_json::Value::Number(number) => Ok(Some(number.as_u64().ok_or_else(invalid_number)?)),
         _ => Err(invalid_number()),
     }
 }
 
+fn extract_text_values(label: &str, json_value: &serde_json::Value) -> anyhow::Result<Option<Vec<String>>> {
+    let invalid_text = || anyhow!("Invalid text value for \"{}\": {:?}", label, json_value);
+
+    let values = match json_value {
+        serde_json::Value::Null          => return Ok(None),
+        serde_json::Value::String(value) => vec![value.clone()],
+        serde_json::Value::Array(values) => {
+            values.iter().
+                map(|v| v.as_str().map(String::from).ok_or_else(invalid_text)).
+                collect::<anyhow::Result<Vec<_>>>()?
+        },
+        _ => return Err(invalid_text()),
+    };
+
+    // NUL separates the values in ID3v2.4, so it can't be part of one
+    if values.iter().any(|v| v.contains('\0')) {
+        return Err(anyhow!("Text values for \"{}\" can't contain NUL characters", label));
+    }
+
+    if values.is_empty() { Ok(None) } else { Ok(Some(values)) }
+}
+
+fn extract_timestamp(label: &str, json_value: &serde_json::Value) -> anyhow::Result<Option<id3::Timestamp>> {
+    match extract_string(label, json_value)? {
+        Some(text) => Ok(Some(date::parse_timestamp(label, &text)?)),
+        None       => Ok(None),
+    }
+}
+
+fn extract_bool(label: &str, json_value: &serde_json::Value) -> anyhow::Result<Option<bool>> {
+    match json_value {
+        serde_json::Value::Null        => Ok(None),
+        serde_json::Value::Bool(value) => Ok(Some(*value)),
+        _ => Err(anyhow!("Invalid boolean value for \"{}\": {:?}", label, json_value)),
+    }
+}
+
+// A text frame could contain multiple NUL-separated values. A single value is returned as a
+// string, multiple values are returned as an array, unless asked to join them together.
+fn read_text(frame: Option<&Frame>, args: &Args) -> serde_json::Value {
+    let Some(text) = frame.and_then(|f| f.content().text()) else {
+        return serde_json::Value::Null;
+    };
+
+    let values = remove_nul_byte(text).split('\u{0000}').collect::<Vec<_>>();
+    text_values_to_json(&values, args)
+}
+
+// Unless asked for the raw value, genre references like "(17)" are resolved to genre names.
+fn read_genre(tag: &id3::Tag, args: &Args) -> serde_json::Value {
+    if args.raw_genres {
+        return read_text(tag.get("TCON"), args);
+    }
+
+    let Some(text) = tag.get("TCON").and_then(|f| f.content().text()) else {
+        return serde_json::Value::Null;
+    };
+
+    let mut genres: Vec<String> = Vec::new();
+    for genre in remove_nul_byte(text).split('\u{0000}').flat_map(genre::resolve) {
+        if !genres.iter().any(|g| g.eq_ignore_ascii_case(&genre)) {
+            genres.push(genre);
+        }
+    }
+
+    text_values_to_json(&genres, args)
+}
+
+fn text_values_to_json<S: AsRef<str>>(values: &[S], args: &Args) -> serde_json::Value {
+    let values = values.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+
+    if values.is_empty() {
+        serde_json::Value::Null
+    } else if values.len() == 1 {
+        values[0].into()
+    } else if args.join_values {
+        let separator = args.value_separator.as_deref().unwrap_or(DEFAULT_VALUE_SEPARATOR);
+        values.join(separator).into()
+    } else {
+        values.into()
+    }
+}
+
+// Writes multiple values NUL-separated for ID3v2.4 and joined with the separator for older
+// versions. No values at all means the frame gets removed.
+fn write_text(
+    tag: &mut id3::Tag,
+    frame_id: &str,
+    values: Option<Vec<String>>,
+    version: id3::Version,
+    separator: &str,
+) {
+    match values {
+        None => {
+            tag.remove(frame_id);
+        },
+        Some(values) if version >= id3::Version::Id3v24 => {
+            tag.set_text_values(frame_id, values);
+        },
+        Some(values) => {
+            tag.set_text(frame_id, values.join(separator));
+        },
+    }
+}
+
+// Some legacy players only understand genre references to the ID3v1 list. In ID3v2.4, these are
+// plain numbers like "17". In older versions, they look like "(17)" and can be chained, with any
+// genres that aren't on the list added at the end.
+fn write_numeric_genres(
+    tag: &mut id3::Tag,
+    values: Option<Vec<String>>,
+    version: id3::Version,
+    separator: &str,
+) {
+    let Some(values) = values else {
+        tag.remove("TCON");
+        return;
+    };
+
+    if version >= id3::Version::Id3v24 {
+        let values = values.into_iter().
+            map(|v| genre::genre_id(&v).map(|id| id.to_string()).unwrap_or(v)).
+            collect();
+        write_text(tag, "TCON", Some(values), version, separator);
+    } else {
+        let (known, unknown): (Vec<_>, Vec<_>) = values.into_iter().
+            partition(|v| genre::genre_id(v).is_some());
+
+        let references = known.iter().
+            filter_map(|v| genre::genre_id(v)).
+            map(|id| format!("({})", id)).
+            collect::<String>();
+
+        tag.set_text("TCON", references + &unknown.join(separator));
+    }
+}
+
+// Reads a "n/m" text frame like TRCK or TPOS into its two numbers. Either half can be missing
+// or invalid independently of the other, so a "/12" frame still has a total.
+fn read_number_pair(tag: &id3::Tag, frame_id: &str) -> (Option<u32>, Option<u32>) {
+    let Some(text) = tag.get(frame_id).and_then(|f| f.content().text()) else {
+        return (None, None);
+    };
+
+    // The ID3 spec says "/" separates the two, but multiple values can also be NUL-separated
+    let mut parts = remove_nul_byte(text).splitn(2, ['/', '\u{0000}']);
+    let number = parts.next().and_then(|n| n.trim().parse().ok());
+    let total = parts.next().and_then(|t| t.trim().parse().ok());
+
+    (number, total)
+}
+
+// Writes a "n/m" text frame, removing it entirely if both halves are missing.
+fn write_number_pair(tag: &mut id3::Tag, frame_id: &str, number: Option<u32>, total: Option<u32>) {
+    let text = match (number, total) {
+        (Some(number), Some(total)) => format!("{}/{}", number, total),
+        (Some(number), None)        => format!("{}", number),
+        (None, Some(total))         => format!("/{}", total),
+        (None, None) => {
+            tag.remove(frame_id);
+            return;
+        },
+    };
+
+    tag.set_text(frame_id, text);
+}
+
 fn remove_nul_byte(input: &str) -> &str {
     input.trim_end_matches('\u{0000}')
 }
 
+// Cover images, and chapter images, only have their data included if asked for
+pub fn picture_to_json(picture: &Picture, args: &Args) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "mime_type":   picture.mime_type,
+        "type":        cover_type(picture),
+        "description": picture.description,
+        "size":        picture.data.len(),
+    });
+
+    if args.with_covers {
+        json["data"] = BASE64_STANDARD.encode(&picture.data).into();
+    }
+
+    json
+}
+
+// The `label` describes where the image is in the JSON, for error messages, like "Entries in the
+// `covers` array".
+pub fn picture_from_json(label: &str, json_value: &serde_json::Value) -> anyhow::Result<Picture> {
+    let picture_data = json_value.as_object().
+        ok_or_else(|| anyhow!("{} need to be objects", label))?;
+
+    let mime_type = picture_data.get("mime_type").
+        and_then(serde_json::Value::as_str).
+        map(String::from).
+        unwrap_or_else(|| String::from("image/jpeg"));
+
+    let picture_type = match picture_data.get("type").and_then(serde_json::Value::as_str) {
+        Some("front") => PictureType::CoverFront,
+        Some("back")  => PictureType::CoverBack,
+        None          => PictureType::CoverFront,
+        _             => PictureType::Other,
+    };
+
+    let data_base64 = picture_data.get("data").
+        and_then(serde_json::Value::as_str).
+        map(String::from).
+        ok_or_else(|| anyhow!("{} need to have a base64-encoded `data` field", label))?;
+    let data = BASE64_STANDARD.decode(&data_base64)?;
+
+    let description = picture_data.get("description").
+        and_then(serde_json::Value::as_str).
+        map(String::from).
+        unwrap_or_else(String::new);
+
+    Ok(Picture { mime_type, picture_type, data, description })
+}
+
 fn is_cover(picture: &Picture) -> bool {
     matches!(
         picture.picture_type,
@@ -260,18 +653,18 @@ mod tests {
         assert_eq!(value, Some(String::from("String!")));
 
         let json = serde_json::json!({ "key": "String!" });
-        let value = extract_string("key", &json.get("key").unwrap()).unwrap();
+        let value = extract_string("key", json.get("key").unwrap()).unwrap();
         assert_eq!(value, Some(String::from("String!")));
 
         let json = serde_json::json!({ "key": None::<String> });
-        let value = extract_string("key", &json.get("key").unwrap()).unwrap();
+        let value = extract_string("key", json.get("key").unwrap()).unwrap();
         assert_eq!(value, None);
 
         let json = serde_json::json!({ "key": 13 });
-        assert!(extract_string("key", &json.get("key").unwrap()).is_err());
+        assert!(extract_string("key", json.get("key").unwrap()).is_err());
 
         let json = serde_json::json!({ "key": ["String!"] });
-        assert!(extract_string("key", &json.get("key").unwrap()).is_err());
+        assert!(extract_string("key", json.get("key").unwrap()).is_err());
     }
 
     #[test]
@@ -285,16 +678,58 @@ mod tests {
         assert_eq!(value, None);
 
         let json = serde_json::json!({ "key": "13" });
-        let value = extract_u32("key", &json.get("key").unwrap()).unwrap();
+        let value = extract_u32("key", json.get("key").unwrap()).unwrap();
         assert_eq!(value, Some(13));
 
         let json = serde_json::json!({ "key": "String!" });
-        assert!(extract_u32("key", &json.get("key").unwrap()).is_err());
+        assert!(extract_u32("key", json.get("key").unwrap()).is_err());
 
         let json = serde_json::json!({ "key": ["String!"] });
-        assert!(extract_u32("key", &json.get("key").unwrap()).is_err());
+        assert!(extract_u32("key", json.get("key").unwrap()).is_err());
 
         let json = serde_json::json!({ "key": u64::MAX });
-        assert!(extract_u32("key", &json.get("key").unwrap()).is_err());
+        assert!(extract_u32("key", json.get("key").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_extract_text_values() {
+        let json = serde_json::json!("String!");
+        let value = extract_text_values("_", &json).unwrap();
+        assert_eq!(value, Some(vec![String::from("String!")]));
+
+        let json = serde_json::json!(["One", "Two"]);
+        let value = extract_text_values("_", &json).unwrap();
+        assert_eq!(value, Some(vec![String::from("One"), String::from("Two")]));
+
+        let json = serde_json::json!([]);
+        let value = extract_text_values("_", &json).unwrap();
+        assert_eq!(value, None);
+
+        let json = serde_json::json!(None::<String>);
+        let value = extract_text_values("_", &json).unwrap();
+        assert_eq!(value, None);
+
+        let json = serde_json::json!({ "key": ["One", 2] });
+        assert!(extract_text_values("key", json.get("key").unwrap()).is_err());
+
+        let json = serde_json::json!({ "key": 13 });
+        assert!(extract_text_values("key", json.get("key").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_extract_bool() {
+        let json = serde_json::json!(true);
+        let value = extract_bool("_", &json).unwrap();
+        assert_eq!(value, Some(true));
+
+        let json = serde_json::json!(None::<bool>);
+        let value = extract_bool("_", &json).unwrap();
+        assert_eq!(value, None);
+
+        let json = serde_json::json!({ "key": "true" });
+        assert!(extract_bool("key", json.get("key").unwrap()).is_err());
+
+        let json = serde_json::json!({ "key": 1 });
+        assert!(extract_bool("key", json.get("key").unwrap()).is_err());
     }
 }
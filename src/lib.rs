@@ -1,2 +1,20 @@
+pub mod chapter_formats;
+pub mod chapters;
+pub mod container;
+pub mod convert;
+pub mod date;
+pub mod dsf;
+pub mod dupes;
+pub mod flac;
+pub mod foreign;
+pub mod genre;
+pub mod id3v1;
 pub mod input;
 pub mod json;
+pub mod mp4;
+pub mod mpeg;
+pub mod ogg;
+pub mod rating;
+pub mod replaygain;
+pub mod vorbis;
+pub mod volume;
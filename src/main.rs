@@ -1,8 +1,18 @@
 use std::process::ExitCode;
 use std::fs::File;
 
-use id3_json::input;
+use anyhow::anyhow;
+
+use id3_json::chapter_formats;
+use id3_json::container;
+use id3_json::convert;
+use id3_json::dupes;
+use id3_json::foreign;
+use id3_json::id3v1;
+use id3_json::input::{self, Args, DupesArgs, ReplayGainMode, V1Mode};
 use id3_json::json;
+use id3_json::mpeg;
+use id3_json::replaygain::{self, Gain, Loudness};
 
 fn main() -> ExitCode {
     match run() {
@@ -15,15 +25,48 @@ fn main() -> ExitCode {
 }
 
 fn run() -> anyhow::Result<()> {
-    let args = input::parse_args(std::env::args_os())?;
+    let mut raw_args = std::env::args_os().collect::<Vec<_>>();
+    if raw_args.get(1).is_some_and(|arg| arg == "dupes") {
+        raw_args.remove(1);
+        return run_dupes(input::parse_dupes_args(raw_args)?);
+    }
 
-    let mut tag = match id3::Tag::read_from_path(&args.filename) {
-        Ok(t) => t,
-        Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) => id3::Tag::new(),
-        Err(e) => return Err(e.into()),
-    };
+    let args = input::parse_args(raw_args)?;
+    let container = container::detect_from_path(&args.filename)?;
+
+    let mut tag = container::read_tag(&args.filename, container)?;
+
+    // Anything to report in addition to the tags themselves
+    let mut extra_json = serde_json::Map::new();
+    let mut warnings: Vec<String> = Vec::new();
+
+    if args.verify_audio && container != container::Container::Mpeg {
+        return Err(anyhow!("Audio verification is only supported in MPEG files, not {}", container));
+    }
+
+    if args.replaygain.is_some() && container != container::Container::Mpeg {
+        return Err(anyhow!("ReplayGain analysis is only supported in MPEG files, not {}", container));
+    }
+
+    if !container.supports_trailing_tags() && (args.v1.is_some() || args.strip_foreign_tags) {
+        return Err(anyhow!("ID3v1 and other trailing tags are only supported in MPEG files, not {}", container));
+    }
+
+    let uses_chapters = args.import_chapters.is_some() || args.export_chapters.is_some();
+    if uses_chapters && container.tag_format().is_some() {
+        return Err(anyhow!("Chapters are only supported in files with ID3 tags, not {}", container));
+    }
+
+    // Imported chapters are checked against the duration, which is only known for MPEG files
+    if args.import_chapters.is_some() && container != container::Container::Mpeg {
+        return Err(anyhow!("Importing chapters is only supported in MPEG files, not {}", container));
+    }
 
     if args.write {
+        if let (Some(tag_format), Some(_)) = (container.tag_format(), args.tag_version) {
+            return Err(anyhow!("{} files use a {}, which doesn't have an ID3 version", container, tag_format));
+        }
+
         let input = if let Some(ref path) = args.in_json {
             let file = File::open(path)?;
             serde_json::from_reader(file)?
@@ -31,14 +74,123 @@ fn run() -> anyhow::Result<()> {
             serde_json::from_reader(std::io::stdin())?
         };
 
-        json::write_to_tag(&input, &mut tag, args.tag_version)?;
-
         let tag_version = args.tag_version.unwrap_or_else(|| tag.version());
-        tag.write_to_path(&args.filename, tag_version)?;
+        let separator = args.value_separator.as_deref().unwrap_or(json::DEFAULT_VALUE_SEPARATOR);
+        let report = convert::convert_tag(&mut tag, tag_version, separator);
+
+        if args.strict_conversion && report.is_lossy() {
+            let frames = report.lossy_entries().
+                map(|e| e.frame_id.as_str()).
+                collect::<Vec<_>>().
+                join(", ");
+
+            return Err(anyhow!(
+                "Converting from {} to {} would lose information in: {}",
+                report.from, report.to, frames
+            ));
+        }
+
+        if report.from != report.to {
+            extra_json.insert(String::from("conversion"), report.to_json());
+        }
+
+        json::write_to_tag(&input, &mut tag, &args)?;
+        let skipped = container::write_tag(&args.filename, container, &tag, tag_version)?;
+
+        if !skipped.is_empty() {
+            warnings.push(format!("Not stored in {} files: {}", container, skipped.join(", ")));
+        }
+    }
+
+    if let Some(ref path) = args.import_chapters {
+        let contents = std::fs::read_to_string(path)?;
+        let format = args.chapters_format.unwrap_or_else(|| chapter_formats::detect_format(&contents));
+
+        // Rounded up, so that a chapter that ends at the rounded duration of the file still fits
+        let bytes = std::fs::read(&args.filename)?;
+        let duration = mpeg::analyze(&bytes).map(|info| (info.duration() * 1000.0).ceil() as u32);
+
+        let entries = chapter_formats::resolve(chapter_formats::parse(&contents, format)?, duration)?;
+        chapter_formats::write_to_tag(&mut tag, &entries);
+
+        let version = args.tag_version.unwrap_or_else(|| tag.version());
+        container::write_tag(&args.filename, container, &tag, version)?;
+    }
+
+    if let Some(ref path) = args.export_chapters {
+        let format = args.chapters_format.unwrap_or_else(|| chapter_formats::format_from_extension(path));
+        let entries = chapter_formats::read_from_tag(&tag);
+        std::fs::write(path, chapter_formats::serialize(&entries, format))?;
+    }
+
+    if let Some(mode) = args.replaygain {
+        extra_json.insert(String::from("replaygain"), apply_replaygain(&args, mode, &mut tag, &mut warnings)?);
+    }
+
+    if args.strip_foreign_tags {
+        foreign::strip_from_path(&args.filename)?;
+    }
+
+    match args.v1 {
+        Some(V1Mode::Strip) => {
+            id3v1::strip_from_path(&args.filename)?;
+        },
+        Some(mode) if mode == V1Mode::Write || id3v1::is_present(&args.filename)? => {
+            let (v1_tag, v1_warnings) = id3v1::from_v2_tag(&tag);
+            id3v1::write_to_path(&args.filename, &v1_tag)?;
+            warnings.extend(v1_warnings);
+        },
+        _ => (),
+    }
+
+    if args.verify_audio {
+        let bytes = std::fs::read(&args.filename)?;
+        extra_json.insert(String::from("audio_verification"), mpeg::verify(&bytes).to_json());
+    }
+
+    if !warnings.is_empty() {
+        extra_json.insert(String::from("warnings"), warnings.into());
     }
 
     if args.read {
-        let tag_json = json::read_from_tag(&tag, &args);
+        let mut tag_json = json::read_from_tag(&tag, &args);
+
+        if let Some(tag_format) = container.tag_format() {
+            tag_json["version"] = tag_format.into();
+        }
+
+        // Read once, for the foreign tags at the end, and for the audio in between
+        let mpeg_bytes = if container == container::Container::Mpeg {
+            Some(std::fs::read(&args.filename)?)
+        } else {
+            None
+        };
+
+        if container.supports_trailing_tags() {
+            match id3::v1::Tag::read_from_path(&args.filename) {
+                Ok(v1_tag) => tag_json["v1"] = json::read_from_v1_tag(&v1_tag),
+                Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) => (),
+                Err(e) => return Err(e.into()),
+            }
+
+            let foreign_tags = foreign::read_from_bytes(mpeg_bytes.as_deref().unwrap_or_default());
+            if !foreign_tags.is_empty() {
+                tag_json["foreign_tags"] = foreign_tags.iter().map(|t| t.to_json()).collect();
+            }
+        }
+
+        if args.audio {
+            if let Some(bytes) = &mpeg_bytes {
+                tag_json["audio"] = mpeg::analyze(bytes).map(|info| info.to_json()).unwrap_or_default();
+                tag_json["audio_hash"] = mpeg::audio_hash(bytes).into();
+            } else {
+                tag_json["audio"] = serde_json::Value::Null;
+            }
+        }
+
+        for (key, value) in extra_json {
+            tag_json[key] = value;
+        }
 
         if let Some(path) = args.out_json {
             let file = File::create(path)?;
@@ -46,6 +198,106 @@ fn run() -> anyhow::Result<()> {
         } else {
             serde_json::to_writer(std::io::stdout(), &tag_json)?;
         }
+    } else if !extra_json.is_empty() {
+        serde_json::to_writer(std::io::stdout(), &extra_json)?;
+    }
+
+    Ok(())
+}
+
+// Analyzes the file, and the rest of the album in album mode, and writes the gain to all of them.
+// The main file's tag is updated in place, so the output has the new values.
+fn apply_replaygain(
+    args: &Args,
+    mode: ReplayGainMode,
+    tag: &mut id3::Tag,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<serde_json::Value> {
+    let mut paths = vec![&args.filename];
+    paths.extend(&args.album_filenames);
+
+    let mut loudnesses = Vec::new();
+    let mut tracks = Vec::new();
+
+    for path in &paths {
+        let path_container = container::detect_from_path(path)?;
+        if path_container != container::Container::Mpeg {
+            return Err(anyhow!("ReplayGain analysis is only supported in MPEG files, not {}", path_container));
+        }
+
+        let loudness = replaygain::analyze_path(path)?;
+        let gain = Gain::from_loudness(&loudness).
+            ok_or_else(|| anyhow!("Can't calculate ReplayGain for {}: the audio is silent", path.display()))?;
+
+        loudnesses.push(loudness);
+        tracks.push(gain);
+    }
+
+    let album = match mode {
+        ReplayGainMode::Track => None,
+        // Unwrap: if every track has audible blocks, so does the album
+        ReplayGainMode::Album => Some(Gain::from_loudness(&Loudness::combine(&loudnesses)).unwrap()),
+    };
+
+    let mut skipped_rva2 = false;
+
+    for (index, (path, track)) in paths.iter().zip(&tracks).enumerate() {
+        if index == 0 {
+            let version = args.tag_version.unwrap_or_else(|| tag.version());
+            skipped_rva2 |= !replaygain::write_to_tag(tag, track, album.as_ref(), version);
+            container::write_tag(path, container::Container::Mpeg, tag, version)?;
+        } else {
+            let mut other_tag = container::read_tag(path, container::Container::Mpeg)?;
+            let version = other_tag.version();
+            skipped_rva2 |= !replaygain::write_to_tag(&mut other_tag, track, album.as_ref(), version);
+            container::write_tag(path, container::Container::Mpeg, &other_tag, version)?;
+        }
+    }
+
+    if skipped_rva2 {
+        warnings.push(String::from("RVA2 frames are only supported in ID3v2.4 tags, only TXXX frames were written"));
+    }
+
+    let tracks_json = paths.iter().zip(&tracks).
+        map(|(path, track)| {
+            let mut json = track.to_json();
+            json["path"] = path.to_string_lossy().into();
+            json
+        }).
+        collect::<Vec<_>>();
+
+    Ok(serde_json::json!({
+        "tracks": tracks_json,
+        "album":  album.map(|a| a.to_json()),
+    }))
+}
+
+// Files that aren't audio, like cover images next to the music, are skipped, and files that can't
+// be read are listed as errors, instead of stopping the search.
+fn run_dupes(args: DupesArgs) -> anyhow::Result<()> {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+
+    for path in dupes::collect_files(&args.paths)? {
+        match dupes::Entry::read(&path) {
+            Ok(Some(entry)) => entries.push(entry),
+            Ok(None)        => (),
+            Err(e)          => errors.push(serde_json::json!({ "path": path.to_string_lossy(), "error": format!("{}", e) })),
+        }
+    }
+
+    let groups = dupes::find_groups(&entries, args.by);
+    let output = serde_json::json!({
+        "scanned": entries.len(),
+        "groups":  dupes::groups_to_json(&entries, &groups),
+        "errors":  errors,
+    });
+
+    if let Some(path) = args.out_json {
+        let file = File::create(path)?;
+        serde_json::to_writer(file, &output)?;
+    } else {
+        serde_json::to_writer(std::io::stdout(), &output)?;
     }
 
     Ok(())
@@ -0,0 +1,593 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use id3::{Frame, TagLike};
+use id3::frame::{Comment, Content, ExtendedText, Picture, PictureType};
+
+use crate::genre;
+use crate::vorbis;
+
+// Atoms that only contain other atoms, on the way to the metadata in moov/udta/meta/ilst, and to
+// the chunk offsets in moov/trak/mdia/minf/stbl. Everything else is kept as raw bytes.
+const CONTAINER_ATOMS: &[&[u8; 4]] = &[b"moov", b"udta", b"meta", b"ilst", b"trak", b"mdia", b"minf", b"stbl"];
+
+// iTunes metadata items with a plain UTF-8 value, and the ID3 frames with the same meaning
+const TEXT_ITEMS: &[(&[u8; 4], &str)] = &[
+    (b"\xA9nam", "TIT2"),
+    (b"\xA9ART", "TPE1"),
+    (b"\xA9alb", "TALB"),
+    (b"aART",    "TPE2"),
+    (b"\xA9wrt", "TCOM"),
+    (b"\xA9gen", "TCON"),
+    (b"\xA9day", "TDRC"),
+    (b"\xA9grp", "TIT1"),
+    (b"\xA9too", "TSSE"),
+    (b"cprt",    "TCOP"),
+    (b"soar",    "TSOP"),
+    (b"soal",    "TSOA"),
+    (b"sonm",    "TSOT"),
+    (b"soaa",    "TSO2"),
+    (b"soco",    "TSOC"),
+];
+
+// Fields that iTunes doesn't have, stored in "----" atoms. These are the names MusicBrainz
+// Picard uses. Any other freeform items are kept as TXXX frames.
+const FREEFORM_MEAN: &str = "com.apple.iTunes";
+const FREEFORM_FIELDS: &[(&str, &str)] = &[
+    ("CONDUCTOR",  "TPE3"),
+    ("REMIXER",    "TPE4"),
+    ("LYRICIST",   "TEXT"),
+    ("SUBTITLE",   "TIT3"),
+    ("LABEL",      "TPUB"),
+    ("LANGUAGE",   "TLAN"),
+    ("MOOD",       "TMOO"),
+    ("ISRC",       "TSRC"),
+    ("initialkey", "TKEY"),
+];
+
+// Type indicators of "data" atoms
+const TYPE_BINARY: u32  = 0;
+const TYPE_UTF8: u32    = 1;
+const TYPE_JPEG: u32    = 13;
+const TYPE_PNG: u32     = 14;
+const TYPE_INTEGER: u32 = 21;
+const TYPE_BMP: u32     = 27;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Atom {
+    kind: [u8; 4],
+    // The payload of a leaf atom, or the version and flags of a "meta" atom, before its children
+    data: Vec<u8>,
+    children: Option<Vec<Atom>>,
+}
+
+impl Atom {
+    fn leaf(kind: &[u8; 4], data: Vec<u8>) -> Self {
+        Atom { kind: *kind, data, children: None }
+    }
+
+    fn container(kind: &[u8; 4], data: Vec<u8>, children: Vec<Atom>) -> Self {
+        Atom { kind: *kind, data, children: Some(children) }
+    }
+
+    fn children(&self) -> &[Atom] {
+        self.children.as_deref().unwrap_or_default()
+    }
+
+    fn child(&self, kind: &[u8; 4]) -> Option<&Atom> {
+        self.children().iter().find(|a| &a.kind == kind)
+    }
+
+    // Finds the child container with the given kind, or creates it with the given prefix data
+    fn child_container_mut(&mut self, kind: &[u8; 4], data: Vec<u8>) -> &mut Atom {
+        let children = self.children.get_or_insert_with(Vec::new);
+
+        let index = match children.iter().position(|a| &a.kind == kind) {
+            Some(index) => index,
+            None => {
+                children.push(Atom::container(kind, data, Vec::new()));
+                children.len() - 1
+            },
+        };
+        &mut children[index]
+    }
+
+    fn size(&self) -> usize {
+        8 + self.data.len() + self.children().iter().map(Atom::size).sum::<usize>()
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) -> anyhow::Result<()> {
+        let size = u32::try_from(self.size()).
+            map_err(|_| anyhow!("MP4 atom is too large: {}", String::from_utf8_lossy(&self.kind)))?;
+
+        output.extend(size.to_be_bytes());
+        output.extend(self.kind);
+        output.extend(&self.data);
+
+        for child in self.children() {
+            child.encode(output)?;
+        }
+        Ok(())
+    }
+
+    // The type indicator and value of each "data" atom in a metadata item
+    fn data_values(&self) -> Vec<(u32, &[u8])> {
+        self.children().iter().
+            filter(|a| &a.kind == b"data" && a.data.len() >= 8).
+            map(|a| {
+                let data_type = u32::from_be_bytes([0, a.data[1], a.data[2], a.data[3]]);
+                (data_type, &a.data[8..])
+            }).
+            collect()
+    }
+
+    fn text_values(&self) -> Option<Vec<String>> {
+        let values = self.data_values();
+        if values.is_empty() || values.iter().any(|(data_type, _)| *data_type != TYPE_UTF8) {
+            return None;
+        }
+        Some(values.iter().map(|(_, v)| String::from_utf8_lossy(v).to_string()).collect())
+    }
+}
+
+// Reads the iTunes metadata into an ID3v2.4 tag, so it can be handled with the same JSON fields.
+pub fn read_tag(path: &Path) -> anyhow::Result<id3::Tag> {
+    let bytes = fs::read(path)?;
+    let (_, moov) = read_moov(&bytes)?;
+    let (tag, _) = to_id3_tag(ilst_items(&moov));
+
+    Ok(tag)
+}
+
+// Replaces the iTunes metadata with the data from the tag. Items that aren't mapped to a field are
+// kept as they are. If the metadata is before the audio data, the chunk offsets that point to it
+// are updated. Returns the IDs of frames that can't be stored in MP4 metadata.
+pub fn write_tag(path: &Path, tag: &id3::Tag) -> anyhow::Result<Vec<String>> {
+    let bytes = fs::read(path)?;
+    let ((moov_start, moov_end), mut moov) = read_moov(&bytes)?;
+
+    let (_, unmapped_items) = to_id3_tag(ilst_items(&moov));
+    let (items, skipped) = from_id3_tag(tag, unmapped_items);
+
+    let udta = moov.child_container_mut(b"udta", Vec::new());
+    let meta = udta.child_container_mut(b"meta", vec![0; 4]);
+    if meta.child(b"hdlr").is_none() {
+        meta.children.get_or_insert_with(Vec::new).insert(0, handler_atom());
+    }
+    meta.child_container_mut(b"ilst", Vec::new()).children = Some(items);
+
+    let delta = moov.size() as i64 - (moov_end - moov_start) as i64;
+    if delta != 0 {
+        adjust_chunk_offsets(&mut moov, moov_end as u64, delta)?;
+    }
+
+    let mut output = bytes[..moov_start].to_vec();
+    moov.encode(&mut output)?;
+    output.extend(&bytes[moov_end..]);
+
+    fs::write(path, output)?;
+    Ok(skipped)
+}
+
+// Finds the top-level "moov" atom, returning its position in the file and its parsed contents.
+fn read_moov(bytes: &[u8]) -> anyhow::Result<((usize, usize), Atom)> {
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let (kind, header_size, size) = read_atom_header(bytes, position)?;
+
+        if &kind == b"moov" {
+            let payload = &bytes[(position + header_size)..(position + size)];
+            let moov = Atom::container(&kind, Vec::new(), parse_atoms(payload, &kind)?);
+            return Ok(((position, position + size), moov));
+        }
+
+        position += size;
+    }
+
+    Err(anyhow!("Invalid MP4 file: no \"moov\" atom"))
+}
+
+// Returns the kind, the size of the header, and the size of the whole atom
+fn read_atom_header(bytes: &[u8], position: usize) -> anyhow::Result<([u8; 4], usize, usize)> {
+    let cut_off = || anyhow!("Invalid MP4 file: atom at byte {} is cut off", position);
+
+    let header = bytes.get(position..(position + 8)).ok_or_else(cut_off)?;
+    let kind = [header[4], header[5], header[6], header[7]];
+
+    let (header_size, size) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+        0 => (8, bytes.len() - position),
+        1 => {
+            let large_size = bytes.get((position + 8)..(position + 16)).ok_or_else(cut_off)?;
+            (16, u64::from_be_bytes(large_size.try_into()?) as usize)
+        },
+        size => (8, size as usize),
+    };
+
+    if size < header_size || position.checked_add(size).is_none_or(|end| end > bytes.len()) {
+        return Err(cut_off());
+    }
+
+    Ok((kind, header_size, size))
+}
+
+fn parse_atoms(bytes: &[u8], parent: &[u8; 4]) -> anyhow::Result<Vec<Atom>> {
+    let mut atoms = Vec::new();
+    let mut position = 0;
+
+    while position + 8 <= bytes.len() {
+        let (kind, header_size, size) = read_atom_header(bytes, position)?;
+        let payload = &bytes[(position + header_size)..(position + size)];
+
+        // Items in "ilst" contain "data" atoms. The "meta" atom usually has a version and flags
+        // before its children, except in some QuickTime files.
+        let atom = if &kind == b"meta" && payload.starts_with(&[0; 4]) {
+            Atom::container(&kind, payload[0..4].to_vec(), parse_atoms(&payload[4..], &kind)?)
+        } else if CONTAINER_ATOMS.contains(&&kind) || parent == b"ilst" {
+            Atom::container(&kind, Vec::new(), parse_atoms(payload, &kind)?)
+        } else {
+            Atom::leaf(&kind, payload.to_vec())
+        };
+
+        atoms.push(atom);
+        position += size;
+    }
+
+    Ok(atoms)
+}
+
+fn ilst_items(moov: &Atom) -> &[Atom] {
+    moov.child(b"udta").
+        and_then(|udta| udta.child(b"meta")).
+        and_then(|meta| meta.child(b"ilst")).
+        map(Atom::children).
+        unwrap_or_default()
+}
+
+// A "meta" atom needs a handler that says it's iTunes metadata
+fn handler_atom() -> Atom {
+    let mut data = vec![0; 8];
+    data.extend(b"mdir");
+    data.extend(b"appl");
+    data.extend([0; 9]);
+
+    Atom::leaf(b"hdlr", data)
+}
+
+// The stco and co64 atoms have the offset of every chunk of audio in the file. Changing the size of
+// the moov atom moves everything after it.
+fn adjust_chunk_offsets(atom: &mut Atom, moov_end: u64, delta: i64) -> anyhow::Result<()> {
+    if let Some(children) = atom.children.as_mut() {
+        for child in children {
+            adjust_chunk_offsets(child, moov_end, delta)?;
+        }
+        return Ok(());
+    }
+
+    let entry_size = match &atom.kind {
+        b"stco" => 4,
+        b"co64" => 8,
+        _ => return Ok(()),
+    };
+
+    // Version and flags, then the number of entries
+    for entry in atom.data.get_mut(8..).unwrap_or_default().chunks_exact_mut(entry_size) {
+        let offset = if entry_size == 4 {
+            u64::from(u32::from_be_bytes(entry.try_into()?))
+        } else {
+            u64::from_be_bytes(entry.try_into()?)
+        };
+
+        if offset < moov_end {
+            continue;
+        }
+
+        let offset = offset.checked_add_signed(delta).
+            ok_or_else(|| anyhow!("Invalid MP4 file: chunk offset {} is out of range", offset))?;
+
+        if entry_size == 4 {
+            let offset = u32::try_from(offset).
+                map_err(|_| anyhow!("MP4 chunk offsets don't fit in the \"stco\" atom anymore"))?;
+            entry.copy_from_slice(&offset.to_be_bytes());
+        } else {
+            entry.copy_from_slice(&offset.to_be_bytes());
+        }
+    }
+
+    Ok(())
+}
+
+// iTunes writes dates like "2011-09-07T07:00:00Z", but ID3 timestamps don't have a time zone
+fn itunes_date(value: &str) -> &str {
+    let Some(time_start) = value.find('T') else { return value };
+
+    match value[time_start..].find(['Z', '+', '-']) {
+        Some(zone_start) => &value[..(time_start + zone_start)],
+        None             => value,
+    }
+}
+
+// Returns the tag, and the items that don't map to any frames, so they can be written back as-is.
+fn to_id3_tag(items: &[Atom]) -> (id3::Tag, Vec<Atom>) {
+    let mut tag = id3::Tag::with_version(id3::Version::Id3v24);
+    let mut unmapped = Vec::new();
+    let mut numeric_genre = None;
+
+    for item in items {
+        let data_values = item.data_values();
+        let first_value = data_values.first().map(|(_, v)| *v).unwrap_or_default();
+
+        if let Some((_, frame_id)) = TEXT_ITEMS.iter().find(|(kind, _)| **kind == item.kind) {
+            match item.text_values() {
+                Some(values) if *frame_id == "TDRC" => {
+                    let dates = values.iter().map(|v| itunes_date(v)).collect::<Vec<_>>();
+                    vorbis::set_text_values(&mut tag, frame_id, &dates);
+                },
+                Some(values) => vorbis::set_text_values(&mut tag, frame_id, &values),
+                None         => unmapped.push(item.clone()),
+            }
+            continue;
+        }
+
+        match &item.kind {
+            b"\xA9cmt" => match item.text_values() {
+                Some(values) => {
+                    let comment = Comment {
+                        lang:        String::new(),
+                        description: String::new(),
+                        text:        values.join("\n"),
+                    };
+                    tag.add_frame(Frame::with_content("COMM", Content::Comment(comment)));
+                },
+                None => unmapped.push(item.clone()),
+            },
+            b"trkn" | b"disk" if first_value.len() >= 6 => {
+                let number = u16::from_be_bytes([first_value[2], first_value[3]]);
+                let total  = u16::from_be_bytes([first_value[4], first_value[5]]);
+                let frame_id = if &item.kind == b"trkn" { "TRCK" } else { "TPOS" };
+
+                let text = match (number, total) {
+                    (0, 0)      => continue,
+                    (number, 0) => number.to_string(),
+                    (0, total)  => format!("/{}", total),
+                    _           => format!("{}/{}", number, total),
+                };
+                tag.set_text(frame_id, text);
+            },
+            b"cpil" if !first_value.is_empty() => {
+                tag.set_text("TCMP", if first_value[0] != 0 { "1" } else { "0" });
+            },
+            b"tmpo" if first_value.len() == 2 => {
+                tag.set_text("TBPM", u16::from_be_bytes([first_value[0], first_value[1]]).to_string());
+            },
+            // The legacy genre is an ID3v1 genre ID, plus one
+            b"gnre" if first_value.len() == 2 => {
+                let id = u16::from_be_bytes([first_value[0], first_value[1]]);
+                numeric_genre = id.checked_sub(1).and_then(|id| genre::genre_name(u32::from(id)));
+            },
+            b"covr" => {
+                // There can only be one ID3 picture of each type, so the first one is the front
+                // cover, and the next one is "other"
+                for (index, (data_type, data)) in data_values.iter().enumerate() {
+                    let mime_type = match *data_type {
+                        TYPE_PNG => "image/png",
+                        TYPE_BMP => "image/bmp",
+                        _        => "image/jpeg",
+                    };
+                    let picture = Picture {
+                        mime_type:    String::from(mime_type),
+                        picture_type: if index == 0 { PictureType::CoverFront } else { PictureType::Other },
+                        description:  String::new(),
+                        data:         data.to_vec(),
+                    };
+                    tag.add_frame(Frame::with_content("APIC", Content::Picture(picture)));
+                }
+            },
+            b"----" => {
+                let (mean, name) = freeform_names(item);
+
+                match (mean.as_deref(), name, item.text_values()) {
+                    (Some(FREEFORM_MEAN), Some(name), Some(values)) => {
+                        match FREEFORM_FIELDS.iter().find(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+                            Some((_, frame_id)) => vorbis::set_text_values(&mut tag, frame_id, &values),
+                            None => {
+                                let extended_text = ExtendedText {
+                                    description: name,
+                                    value:       values.join("\u{0000}"),
+                                };
+                                tag.add_frame(Frame::with_content("TXXX", Content::ExtendedText(extended_text)));
+                            },
+                        }
+                    },
+                    _ => unmapped.push(item.clone()),
+                }
+            },
+            _ => unmapped.push(item.clone()),
+        }
+    }
+
+    if let (Some(name), None) = (numeric_genre, tag.get("TCON")) {
+        tag.set_genre(name);
+    }
+
+    (tag, unmapped)
+}
+
+// Returns the items, followed by the given unmapped ones, and the IDs of frames that can't be
+// stored in MP4 metadata.
+fn from_id3_tag(tag: &id3::Tag, unmapped: Vec<Atom>) -> (Vec<Atom>, Vec<String>) {
+    let mut items = Vec::new();
+    let mut covers = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+
+    let text = |frame: &Frame| frame.content().text().unwrap_or_default().trim_end_matches('\u{0000}').to_string();
+    let values = |text: &str| text.split('\u{0000}').map(String::from).collect::<Vec<_>>();
+
+    for frame in tag.frames() {
+        let frame_id = frame.id();
+
+        if let Some((kind, _)) = TEXT_ITEMS.iter().find(|(_, id)| *id == frame_id) {
+            items.push(text_item(kind, &values(&text(frame))));
+            continue;
+        }
+
+        if let Some((name, _)) = FREEFORM_FIELDS.iter().find(|(_, id)| *id == frame_id) {
+            items.push(freeform_item(name, &values(&text(frame))));
+            continue;
+        }
+
+        let item = match frame_id {
+            "TRCK" | "TPOS" => {
+                let text = text(frame);
+                let (number, total) = text.split_once('/').unwrap_or((&text, ""));
+                let parse = |n: &str| if n.is_empty() { Some(0) } else { n.trim().parse::<u16>().ok() };
+
+                match (parse(number), parse(total)) {
+                    (Some(number), Some(total)) => {
+                        let mut value = vec![0, 0];
+                        value.extend(number.to_be_bytes());
+                        value.extend(total.to_be_bytes());
+
+                        // The track number has two more bytes of padding than the disc number
+                        let kind = if frame_id == "TRCK" {
+                            value.extend([0, 0]);
+                            b"trkn"
+                        } else {
+                            b"disk"
+                        };
+                        Some(data_item(kind, TYPE_BINARY, value))
+                    },
+                    _ => None,
+                }
+            },
+            "TCMP" => Some(data_item(b"cpil", TYPE_INTEGER, vec![u8::from(text(frame) == "1")])),
+            "TBPM" => text(frame).parse::<u16>().ok().
+                map(|bpm| data_item(b"tmpo", TYPE_INTEGER, bpm.to_be_bytes().to_vec())),
+            "COMM" => frame.content().comment().
+                filter(|c| c.description.is_empty()).
+                map(|c| text_item(b"\xA9cmt", &[c.text.trim_end_matches('\u{0000}').to_string()])),
+            "TXXX" => frame.content().extended_text().
+                map(|e| freeform_item(&e.description, &values(e.value.trim_end_matches('\u{0000}')))),
+            "APIC" => {
+                if let Some(picture) = frame.content().picture() {
+                    let data_type = match picture.mime_type.as_str() {
+                        "image/png" => TYPE_PNG,
+                        "image/bmp" => TYPE_BMP,
+                        _           => TYPE_JPEG,
+                    };
+                    let data_atom = data_atom(data_type, picture.data.clone());
+
+                    if picture.picture_type == PictureType::CoverFront {
+                        covers.insert(0, data_atom);
+                    } else {
+                        covers.push(data_atom);
+                    }
+                }
+                continue;
+            },
+            _ => None,
+        };
+
+        match item {
+            Some(item) => items.push(item),
+            None => if !skipped.iter().any(|id| id == frame_id) {
+                skipped.push(frame_id.to_string());
+            },
+        }
+    }
+
+    if !covers.is_empty() {
+        items.push(Atom::container(b"covr", Vec::new(), covers));
+    }
+
+    items.extend(unmapped);
+    (items, skipped)
+}
+
+fn data_atom(data_type: u32, value: Vec<u8>) -> Atom {
+    // The type indicator, then the locale, which is always zero
+    let mut data = data_type.to_be_bytes().to_vec();
+    data.extend([0; 4]);
+    data.extend(value);
+
+    Atom::leaf(b"data", data)
+}
+
+fn data_item(kind: &[u8; 4], data_type: u32, value: Vec<u8>) -> Atom {
+    Atom::container(kind, Vec::new(), vec![data_atom(data_type, value)])
+}
+
+fn text_item(kind: &[u8; 4], values: &[String]) -> Atom {
+    let data_atoms = values.iter().
+        map(|v| data_atom(TYPE_UTF8, v.as_bytes().to_vec())).
+        collect();
+
+    Atom::container(kind, Vec::new(), data_atoms)
+}
+
+fn freeform_item(name: &str, values: &[String]) -> Atom {
+    // "mean" and "name" have a version and flags before the text
+    let mut mean = vec![0; 4];
+    mean.extend(FREEFORM_MEAN.as_bytes());
+    let mut name_data = vec![0; 4];
+    name_data.extend(name.as_bytes());
+
+    let mut children = vec![Atom::leaf(b"mean", mean), Atom::leaf(b"name", name_data)];
+    children.extend(values.iter().map(|v| data_atom(TYPE_UTF8, v.as_bytes().to_vec())));
+
+    Atom::container(b"----", Vec::new(), children)
+}
+
+fn freeform_names(item: &Atom) -> (Option<String>, Option<String>) {
+    let text = |kind: &[u8; 4]| {
+        item.child(kind).
+            and_then(|a| a.data.get(4..)).
+            map(|t| String::from_utf8_lossy(t).to_string())
+    };
+
+    (text(b"mean"), text(b"name"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atom_round_trip() {
+        let item = text_item(b"\xA9nam", &[String::from("Title")]);
+        let ilst = Atom::container(b"ilst", Vec::new(), vec![item]);
+        let meta = Atom::container(b"meta", vec![0; 4], vec![handler_atom(), ilst]);
+
+        let mut bytes = Vec::new();
+        meta.encode(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), meta.size());
+
+        let parsed = parse_atoms(&bytes, b"udta").unwrap();
+        assert_eq!(parsed, vec![meta]);
+    }
+
+    #[test]
+    fn test_itunes_date() {
+        assert_eq!(itunes_date("2011-09-07T07:00:00Z"), "2011-09-07T07:00:00");
+        assert_eq!(itunes_date("2011-09-07T07:00:00+02:00"), "2011-09-07T07:00:00");
+        assert_eq!(itunes_date("2011-09-07T07:00:00-05:00"), "2011-09-07T07:00:00");
+        assert_eq!(itunes_date("2011-09-07"), "2011-09-07");
+        assert_eq!(itunes_date("2011"), "2011");
+    }
+
+    #[test]
+    fn test_adjust_chunk_offsets() {
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        data.extend(50_u32.to_be_bytes());
+        data.extend(1000_u32.to_be_bytes());
+        let mut stco = Atom::leaf(b"stco", data);
+
+        adjust_chunk_offsets(&mut stco, 100, 24).unwrap();
+        assert_eq!(&stco.data[8..12], &50_u32.to_be_bytes());
+        assert_eq!(&stco.data[12..16], &1024_u32.to_be_bytes());
+
+        adjust_chunk_offsets(&mut stco, 100, -4).unwrap();
+        assert_eq!(&stco.data[12..16], &1020_u32.to_be_bytes());
+    }
+}
@@ -0,0 +1,545 @@
+use std::ops::Range;
+
+use sha2::{Digest, Sha256};
+
+use crate::foreign;
+
+// Bitrates in kbps, by bitrate index. Index 0 is "free format", and 15 is invalid.
+const BITRATES_V1_L1: [u32; 15] = [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448];
+const BITRATES_V1_L2: [u32; 15] = [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384];
+const BITRATES_V1_L3: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const BITRATES_V2_L1: [u32; 15] = [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256];
+const BITRATES_V2_L2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+const LAME_VBR_METHODS: &[(u8, &str, &str)] = &[
+    (1, "CBR", "cbr"),
+    (2, "ABR", "abr"),
+    (3, "VBR", "vbr-old"),
+    (4, "VBR", "vbr-mtrh"),
+    (5, "VBR", "vbr-mt"),
+    (6, "VBR", "vbr-new"),
+    (8, "CBR", "cbr-2pass"),
+    (9, "ABR", "abr-2pass"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: Version,
+    pub layer: u8,
+    pub protected: bool,
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channel_mode: u8,
+}
+
+impl FrameHeader {
+    // Parses the 4-byte header at the start of the given bytes. Free-format frames aren't
+    // supported, since their size can't be known from the header.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let header = u32::from_be_bytes(bytes.get(0..4)?.try_into().ok()?);
+
+        if header >> 21 != 0x7FF {
+            return None;
+        }
+
+        let version = match (header >> 19) & 0b11 {
+            0b00 => Version::Mpeg25,
+            0b10 => Version::Mpeg2,
+            0b11 => Version::Mpeg1,
+            _    => return None,
+        };
+        let layer = match (header >> 17) & 0b11 {
+            0b01 => 3,
+            0b10 => 2,
+            0b11 => 1,
+            _    => return None,
+        };
+
+        let bitrates = match (version, layer) {
+            (Version::Mpeg1, 1) => &BITRATES_V1_L1,
+            (Version::Mpeg1, 2) => &BITRATES_V1_L2,
+            (Version::Mpeg1, _) => &BITRATES_V1_L3,
+            (_, 1)              => &BITRATES_V2_L1,
+            (_, _)              => &BITRATES_V2_L2,
+        };
+        let bitrate = *bitrates.get(((header >> 12) & 0b1111) as usize).filter(|b| **b > 0)?;
+
+        let sample_rate = match ((header >> 10) & 0b11, version) {
+            (0b11, _)                => return None,
+            (index, Version::Mpeg1)  => [44100, 48000, 32000][index as usize],
+            (index, Version::Mpeg2)  => [22050, 24000, 16000][index as usize],
+            (index, Version::Mpeg25) => [11025, 12000, 8000][index as usize],
+        };
+
+        Some(FrameHeader {
+            version,
+            layer,
+            protected:    (header >> 16) & 1 == 0,
+            bitrate,
+            sample_rate,
+            padding:      (header >> 9) & 1 == 1,
+            channel_mode: ((header >> 6) & 0b11) as u8,
+        })
+    }
+
+    pub fn samples(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _)              => 384,
+            (3, Version::Mpeg1) => 1152,
+            (3, _)              => 576,
+            _                   => 1152,
+        }
+    }
+
+    // The size of the whole frame in bytes, including the header
+    pub fn frame_size(&self) -> usize {
+        let padding = u32::from(self.padding);
+
+        if self.layer == 1 {
+            // Layer I has 4-byte slots, so the padding is 4 bytes
+            ((12 * self.bitrate * 1000 / self.sample_rate + padding) * 4) as usize
+        } else {
+            (self.samples() / 8 * self.bitrate * 1000 / self.sample_rate + padding) as usize
+        }
+    }
+
+    pub fn is_mono(&self) -> bool {
+        self.channel_mode == 0b11
+    }
+
+    fn version_str(&self) -> &'static str {
+        match self.version {
+            Version::Mpeg1  => "1",
+            Version::Mpeg2  => "2",
+            Version::Mpeg25 => "2.5",
+        }
+    }
+
+    fn channel_mode_str(&self) -> &'static str {
+        match self.channel_mode {
+            0b00 => "stereo",
+            0b01 => "joint stereo",
+            0b10 => "dual channel",
+            _    => "mono",
+        }
+    }
+
+    // Where the Xing or Info header would be in the first frame, right after the side information
+    fn xing_offset(&self) -> usize {
+        let side_info = match (self.version, self.is_mono()) {
+            (Version::Mpeg1, false) => 32,
+            (Version::Mpeg1, true)  => 17,
+            (_, false)              => 17,
+            (_, true)               => 9,
+        };
+        4 + side_info
+    }
+}
+
+// The encoder info that LAME writes after the Xing header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LameInfo {
+    pub version: String,
+    pub vbr_method: Option<u8>,
+    pub lowpass: u32,
+    pub delay: u32,
+    pub padding: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub first_frame: FrameHeader,
+    pub vbr_header: Option<&'static str>,
+    pub frames: u64,
+    pub bytes: u64,
+    pub bitrate_mode: &'static str,
+    pub lame: Option<LameInfo>,
+}
+
+impl AudioInfo {
+    pub fn duration(&self) -> f64 {
+        let samples = self.frames * u64::from(self.first_frame.samples());
+        samples as f64 / f64::from(self.first_frame.sample_rate)
+    }
+
+    // In kbps. For CBR files, it's the one in the frame headers, otherwise it's the average.
+    pub fn bitrate(&self) -> u32 {
+        let duration = self.duration();
+
+        if self.bitrate_mode == "CBR" || duration == 0.0 {
+            self.first_frame.bitrate
+        } else {
+            (self.bytes as f64 * 8.0 / duration / 1000.0).round() as u32
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let encoder = self.lame.as_ref().map(|lame| {
+            let vbr_method = LAME_VBR_METHODS.iter().
+                find(|(id, _, _)| Some(*id) == lame.vbr_method).
+                map(|(_, _, name)| *name);
+
+            serde_json::json!({
+                "version":    lame.version,
+                "vbr_method": vbr_method,
+                "lowpass":    (lame.lowpass > 0).then_some(lame.lowpass),
+                "delay":      lame.delay,
+                "padding":    lame.padding,
+            })
+        });
+
+        serde_json::json!({
+            "mpeg_version": self.first_frame.version_str(),
+            "layer":        self.first_frame.layer,
+            "sample_rate":  self.first_frame.sample_rate,
+            "channel_mode": self.first_frame.channel_mode_str(),
+            "bitrate":      self.bitrate(),
+            "bitrate_mode": self.bitrate_mode,
+            "duration":     (self.duration() * 1000.0).round() / 1000.0,
+            "frames":       self.frames,
+            "vbr_header":   self.vbr_header,
+            "encoder":      encoder,
+        })
+    }
+}
+
+// The part of the file between the ID3v2 tag at the start, and any ID3v1, APEv2, or Lyrics3v2
+// tags at the end.
+pub fn audio_range(bytes: &[u8]) -> Range<usize> {
+    let mut start = 0;
+
+    // There might be more than one ID3v2 tag, if a tagger prepended a new one
+    while bytes.get(start..(start + 3)) == Some(b"ID3") && bytes.len() >= start + 10 {
+        let header = &bytes[start..(start + 10)];
+        let size = header[6..10].iter().fold(0, |size, b| (size << 7) | usize::from(b & 0x7F));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+
+        start += 10 + size + footer;
+    }
+
+    let end = foreign::audio_end(bytes);
+    start.min(end)..end
+}
+
+// Finds the first frame whose header is valid, and is followed by another valid frame, or by the
+// end of the audio, so random bytes that look like a sync word aren't mistaken for a frame.
+pub fn find_first_frame(bytes: &[u8], range: Range<usize>) -> Option<(usize, FrameHeader)> {
+    (range.start..range.end.saturating_sub(4)).find_map(|position| {
+        let header = FrameHeader::parse(&bytes[position..range.end])?;
+        let next = position + header.frame_size();
+
+        let is_followed_by_frame =
+            next == range.end ||
+            FrameHeader::parse(bytes.get(next..range.end).unwrap_or_default()).is_some();
+
+        is_followed_by_frame.then_some((position, header))
+    })
+}
+
+// A SHA-256 hash of the audio, from the first frame to the tags at the end, as a hex string. It
+// stays the same when the tags change, so it can find copies of the same recording.
+pub fn audio_hash(bytes: &[u8]) -> Option<String> {
+    let range = audio_range(bytes);
+    let (start, _) = find_first_frame(bytes, range.clone())?;
+
+    Some(format!("{:x}", Sha256::digest(&bytes[start..range.end])))
+}
+
+// Reads the stream properties from the first frame and its Xing, Info, or VBRI header. Without
+// one of those, every frame is counted, to tell CBR from VBR, and to get an accurate duration.
+pub fn analyze(bytes: &[u8]) -> Option<AudioInfo> {
+    let range = audio_range(bytes);
+    let (start, first_frame) = find_first_frame(bytes, range.clone())?;
+    let frame = &bytes[start..(start + first_frame.frame_size()).min(range.end)];
+
+    if let Some(info) = read_xing(frame, &first_frame, (range.end - start) as u64) {
+        return Some(info);
+    }
+    if let Some(info) = read_vbri(frame, &first_frame) {
+        return Some(info);
+    }
+
+    let mut frames = 0;
+    let mut bitrates = Vec::new();
+    let mut position = start;
+
+    while let Some(header) = FrameHeader::parse(bytes.get(position..range.end).unwrap_or_default()) {
+        frames += 1;
+        if !bitrates.contains(&header.bitrate) {
+            bitrates.push(header.bitrate);
+        }
+        position += header.frame_size();
+    }
+
+    Some(AudioInfo {
+        first_frame,
+        vbr_header:   None,
+        frames,
+        bytes:        (position - start) as u64,
+        bitrate_mode: if bitrates.len() > 1 { "VBR" } else { "CBR" },
+        lame:         None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    // Bytes that aren't part of any frame, between frames or before the first one
+    Garbage { offset: usize, size: usize },
+    // A frame that's cut short by the next one, so its data is corrupted
+    SyncError { offset: usize, expected_size: usize, actual_size: usize },
+    // The last frame goes past the end of the audio
+    TruncatedFrame { offset: usize, expected_size: usize, actual_size: usize },
+    // The Xing, Info, or VBRI header has a different number of frames than the stream
+    FrameCountMismatch { header: &'static str, expected: u64, actual: u64 },
+    NoFrames,
+}
+
+impl StreamError {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            StreamError::Garbage { offset, size } => {
+                serde_json::json!({ "type": "garbage", "offset": offset, "size": size })
+            },
+            StreamError::SyncError { offset, expected_size, actual_size } => {
+                serde_json::json!({
+                    "type":          "sync_error",
+                    "offset":        offset,
+                    "expected_size": expected_size,
+                    "actual_size":   actual_size,
+                })
+            },
+            StreamError::TruncatedFrame { offset, expected_size, actual_size } => {
+                serde_json::json!({
+                    "type":          "truncated_frame",
+                    "offset":        offset,
+                    "expected_size": expected_size,
+                    "actual_size":   actual_size,
+                })
+            },
+            StreamError::FrameCountMismatch { header, expected, actual } => {
+                serde_json::json!({
+                    "type":     "frame_count_mismatch",
+                    "header":   header,
+                    "expected": expected,
+                    "actual":   actual,
+                })
+            },
+            StreamError::NoFrames => serde_json::json!({ "type": "no_frames" }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub frames: u64,
+    pub errors: Vec<StreamError>,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "valid":  self.is_valid(),
+            "frames": self.frames,
+            "errors": self.errors.iter().map(|e| e.to_json()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+// Walks every frame between the tags, and when one doesn't end where the next one starts, finds
+// the next valid frame to tell apart garbage that was inserted between intact frames, and a frame
+// that's cut short. The number of frames is compared to the one in the VBR header, if there is
+// one, which catches files that were cut or concatenated after encoding.
+pub fn verify(bytes: &[u8]) -> Verification {
+    let range = audio_range(bytes);
+    let mut frames = 0;
+    let mut errors = Vec::new();
+
+    // A frame right after the tag doesn't need to be followed by a valid one, since that could be
+    // one of the errors to report
+    let start = if FrameHeader::parse(&bytes[range.clone()]).is_some() {
+        range.start
+    } else if let Some((start, _)) = find_first_frame(bytes, range.clone()) {
+        start
+    } else {
+        return Verification { frames, errors: vec![StreamError::NoFrames] };
+    };
+    if start > range.start {
+        errors.push(StreamError::Garbage { offset: range.start, size: start - range.start });
+    }
+
+    let mut position = start;
+    while position < range.end {
+        // Unwrap: each position is either the first frame, or a frame found by checking its header
+        let header = FrameHeader::parse(&bytes[position..range.end]).unwrap();
+        let expected_size = header.frame_size();
+        let next = position + expected_size;
+        frames += 1;
+
+        if next > range.end {
+            let actual_size = range.end - position;
+            errors.push(StreamError::TruncatedFrame { offset: position, expected_size, actual_size });
+            break;
+        }
+        if next == range.end || FrameHeader::parse(&bytes[next..range.end]).is_some() {
+            position = next;
+            continue;
+        }
+
+        let Some((found, _)) = find_first_frame(bytes, (position + 1)..range.end) else {
+            errors.push(StreamError::Garbage { offset: next, size: range.end - next });
+            break;
+        };
+
+        if found < next {
+            let actual_size = found - position;
+            errors.push(StreamError::SyncError { offset: position, expected_size, actual_size });
+        } else {
+            errors.push(StreamError::Garbage { offset: next, size: found - next });
+        }
+        position = found;
+    }
+
+    // The frame with the VBR header is silent, and isn't counted in it
+    if let Some(info) = analyze(bytes) {
+        if let Some(header) = info.vbr_header {
+            let actual = frames.saturating_sub(1);
+            if info.frames != actual {
+                errors.push(StreamError::FrameCountMismatch { header, expected: info.frames, actual });
+            }
+        }
+    }
+
+    Verification { frames, errors }
+}
+
+// A Xing header is written by VBR encoders, and an "Info" one by LAME for CBR files. The frame
+// itself is silent, and isn't counted in the number of frames. The byte count is optional, so the
+// size of the audio is used without it.
+fn read_xing(frame: &[u8], header: &FrameHeader, audio_size: u64) -> Option<AudioInfo> {
+    let offset = header.xing_offset();
+    let id = frame.get(offset..(offset + 4))?;
+    let vbr_header = match id {
+        b"Xing" => "Xing",
+        b"Info" => "Info",
+        _       => return None,
+    };
+
+    let flags = read_u32(frame, offset + 4)?;
+    let mut position = offset + 8;
+    let mut field = |flag: u32, size: usize| {
+        if flags & flag == 0 {
+            return None;
+        }
+        let value = read_u32(frame, position);
+        position += size;
+        value
+    };
+
+    let frames = field(0x1, 4);
+    let bytes  = field(0x2, 4);
+    let _toc   = field(0x4, 100);
+    let _scale = field(0x8, 4);
+
+    let lame = read_lame(frame, position);
+    let lame_bitrate_mode = lame.as_ref().
+        and_then(|l| LAME_VBR_METHODS.iter().find(|(id, _, _)| Some(*id) == l.vbr_method)).
+        map(|(_, mode, _)| *mode);
+
+    let bitrate_mode = match (vbr_header, lame_bitrate_mode) {
+        (_, Some(mode)) => mode,
+        ("Info", None)  => "CBR",
+        (_, None)       => "VBR",
+    };
+
+    Some(AudioInfo {
+        first_frame: *header,
+        vbr_header: Some(vbr_header),
+        frames: u64::from(frames?),
+        bytes: bytes.map(u64::from).unwrap_or(audio_size),
+        bitrate_mode,
+        lame,
+    })
+}
+
+fn read_lame(frame: &[u8], offset: usize) -> Option<LameInfo> {
+    let tag = frame.get(offset..(offset + 24))?;
+    if !tag.starts_with(b"LAME") && !tag.starts_with(b"Lavc") && !tag.starts_with(b"Lavf") {
+        return None;
+    }
+
+    let version = String::from_utf8_lossy(&tag[0..9]).trim_end_matches(['\u{0000}', ' ']).to_string();
+    let vbr_method = tag[9] & 0x0F;
+    let delay_and_padding = &tag[21..24];
+
+    Some(LameInfo {
+        version,
+        vbr_method: (vbr_method > 0).then_some(vbr_method),
+        lowpass:    u32::from(tag[10]) * 100,
+        delay:      (u32::from(delay_and_padding[0]) << 4) | (u32::from(delay_and_padding[1]) >> 4),
+        padding:    (u32::from(delay_and_padding[1] & 0x0F) << 8) | u32::from(delay_and_padding[2]),
+    })
+}
+
+// The VBRI header is written by the Fraunhofer encoder, always 32 bytes after the frame header
+fn read_vbri(frame: &[u8], header: &FrameHeader) -> Option<AudioInfo> {
+    if frame.get(36..40)? != b"VBRI" {
+        return None;
+    }
+
+    Some(AudioInfo {
+        first_frame:  *header,
+        vbr_header:   Some("VBRI"),
+        frames:       u64::from(read_u32(frame, 50)?),
+        bytes:        u64::from(read_u32(frame, 46)?),
+        bitrate_mode: "VBR",
+        lame:         None,
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..(offset + 4))?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_header() {
+        let header = FrameHeader::parse(&[0xFF, 0xFB, 0x90, 0x44]).unwrap();
+        assert_eq!(header.version, Version::Mpeg1);
+        assert_eq!(header.layer, 3);
+        assert_eq!(header.bitrate, 128);
+        assert_eq!(header.sample_rate, 44100);
+        assert_eq!(header.channel_mode_str(), "joint stereo");
+        assert_eq!(header.frame_size(), 417);
+
+        let padded = FrameHeader::parse(&[0xFF, 0xFB, 0x92, 0x44]).unwrap();
+        assert_eq!(padded.frame_size(), 418);
+
+        // MPEG 2, 64kbps, 22050Hz, mono
+        let header = FrameHeader::parse(&[0xFF, 0xF3, 0x80, 0xC4]).unwrap();
+        assert_eq!(header.version, Version::Mpeg2);
+        assert_eq!(header.samples(), 576);
+        assert_eq!(header.frame_size(), 208);
+        assert!(header.is_mono());
+
+        // Bad sync, reserved version, free format bitrate, reserved sample rate
+        assert_eq!(FrameHeader::parse(&[0xFF, 0x7B, 0x90, 0x44]), None);
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xEB, 0x90, 0x44]), None);
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xFB, 0x00, 0x44]), None);
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xFB, 0x9C, 0x44]), None);
+    }
+}
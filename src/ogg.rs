@@ -0,0 +1,358 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use base64::prelude::*;
+
+use crate::vorbis::{self, PictureBlock};
+
+const CAPTURE_PATTERN: &[u8] = b"OggS";
+const PAGE_HEADER_SIZE: usize = 27;
+const MAX_SEGMENTS: usize = 255;
+
+const CONTINUED_PACKET: u8 = 0x01;
+
+// Header pages that don't finish a packet have no granule position
+const NO_GRANULE_POSITION: u64 = u64::MAX;
+
+// Cover images are stored as base64-encoded FLAC picture blocks
+const PICTURE_KEY: &str = "METADATA_BLOCK_PICTURE";
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    // The comment packet starts with this, and ends with a framing bit for Vorbis
+    fn comment_prefix(self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => b"\x03vorbis",
+            Codec::Opus   => b"OpusTags",
+        }
+    }
+
+    // Vorbis has a third "setup" header after the comments
+    fn header_count(self) -> usize {
+        match self {
+            Codec::Vorbis => 3,
+            Codec::Opus   => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Page {
+    header_type: u8,
+    granule_position: u64,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Page {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = CAPTURE_PATTERN.to_vec();
+        bytes.push(0);
+        bytes.push(self.header_type);
+        bytes.extend(self.granule_position.to_le_bytes());
+        bytes.extend(self.serial.to_le_bytes());
+        bytes.extend(self.sequence.to_le_bytes());
+        bytes.extend([0; 4]);
+        bytes.push(self.segments.len() as u8);
+        bytes.extend(&self.segments);
+        bytes.extend(&self.data);
+
+        let checksum = crc32(&bytes);
+        bytes[22..26].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+}
+
+// The header packets of the first logical stream, and the pages they take up
+struct Headers {
+    codec: Codec,
+    serial: u32,
+    packets: Vec<Vec<u8>>,
+    page_count: usize,
+}
+
+struct CommentHeader {
+    vendor: String,
+    comments: Vec<(String, String)>,
+    pictures: Vec<PictureBlock>,
+}
+
+// Reads the Vorbis comments and pictures into an ID3v2.4 tag, so they can be handled with the
+// same JSON fields.
+pub fn read_tag(path: &Path) -> anyhow::Result<id3::Tag> {
+    let bytes = fs::read(path)?;
+    let pages = parse_pages(&bytes)?;
+    let headers = read_headers(&pages)?;
+    let comment_header = read_comments(&headers)?;
+
+    Ok(vorbis::to_id3_tag(&comment_header.comments, &comment_header.pictures))
+}
+
+// Replaces the comment header with the data from the tag. The header pages are rebuilt, and if
+// their number changed, the audio pages after them are renumbered, which means recalculating
+// their checksums. Returns the IDs of frames that can't be stored in a Vorbis comment.
+pub fn write_tag(path: &Path, tag: &id3::Tag) -> anyhow::Result<Vec<String>> {
+    let bytes = fs::read(path)?;
+    let pages = parse_pages(&bytes)?;
+    let headers = read_headers(&pages)?;
+
+    let CommentHeader { vendor, pictures: original_pictures, .. } = read_comments(&headers)?;
+    let converted = vorbis::from_id3_tag(tag, &original_pictures);
+
+    let mut comments = converted.comments;
+    for picture in &converted.pictures {
+        comments.push((String::from(PICTURE_KEY), BASE64_STANDARD.encode(picture.encode())));
+    }
+
+    let mut comment_packet = headers.codec.comment_prefix().to_vec();
+    comment_packet.extend(vorbis::encode_comments(&vendor, &comments));
+    if headers.codec == Codec::Vorbis {
+        comment_packet.push(1);
+    }
+
+    // The identification header is always alone on the first page
+    let mut packets = vec![comment_packet];
+    packets.extend(headers.packets[2..].iter().cloned());
+
+    let mut header_pages = vec![pages[0].0.clone()];
+    header_pages.extend(paginate(&packets, headers.serial, 1));
+    let sequence_delta = header_pages.len() as i64 - headers.page_count as i64;
+
+    let mut output = Vec::new();
+    for page in &header_pages {
+        output.extend(page.encode());
+    }
+
+    for (page, raw) in &pages[headers.page_count..] {
+        if page.serial != headers.serial || sequence_delta == 0 {
+            output.extend(*raw);
+            continue;
+        }
+
+        let sequence = u32::try_from(i64::from(page.sequence) + sequence_delta)?;
+        output.extend(Page { sequence, ..page.clone() }.encode());
+    }
+
+    fs::write(path, output)?;
+    Ok(converted.skipped)
+}
+
+fn parse_pages(bytes: &[u8]) -> anyhow::Result<Vec<(Page, &[u8])>> {
+    let mut pages = Vec::new();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let invalid = |reason: &str| anyhow!("Invalid Ogg file: page at byte {} {}", position, reason);
+
+        let header = bytes.get(position..(position + PAGE_HEADER_SIZE)).ok_or_else(|| invalid("is cut off"))?;
+        if &header[0..4] != CAPTURE_PATTERN {
+            return Err(invalid("doesn't start with \"OggS\""));
+        }
+
+        let segment_count = header[26] as usize;
+        let segments_end = position + PAGE_HEADER_SIZE + segment_count;
+        let segments = bytes.get((position + PAGE_HEADER_SIZE)..segments_end).ok_or_else(|| invalid("is cut off"))?;
+
+        let data_size = segments.iter().map(|s| *s as usize).sum::<usize>();
+        let data = bytes.get(segments_end..(segments_end + data_size)).ok_or_else(|| invalid("is cut off"))?;
+
+        let page = Page {
+            header_type:      header[5],
+            granule_position: u64::from_le_bytes(header[6..14].try_into()?),
+            serial:           u32::from_le_bytes(header[14..18].try_into()?),
+            sequence:         u32::from_le_bytes(header[18..22].try_into()?),
+            segments:         segments.to_vec(),
+            data:             data.to_vec(),
+        };
+
+        let end = segments_end + data_size;
+        pages.push((page, &bytes[position..end]));
+        position = end;
+    }
+
+    Ok(pages)
+}
+
+fn read_headers(pages: &[(Page, &[u8])]) -> anyhow::Result<Headers> {
+    let Some((first_page, _)) = pages.first() else {
+        return Err(anyhow!("Invalid Ogg file: no pages"));
+    };
+
+    let codec = if first_page.data.starts_with(b"\x01vorbis") {
+        Codec::Vorbis
+    } else if first_page.data.starts_with(b"OpusHead") {
+        Codec::Opus
+    } else {
+        // Other codecs start with a byte or two of packet type, and then their name
+        let codec = first_page.data.iter().
+            skip_while(|b| !b.is_ascii_alphanumeric()).
+            take_while(|b| b.is_ascii_alphanumeric()).
+            map(|b| char::from(*b)).
+            collect::<String>();
+        return Err(anyhow!("Unsupported container: Ogg ({})", codec));
+    };
+
+    let serial = first_page.serial;
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+
+    for (index, (page, _)) in pages.iter().enumerate() {
+        if page.serial != serial {
+            return Err(anyhow!("Multiplexed Ogg streams aren't supported"));
+        }
+
+        let mut position = 0;
+        for segment in &page.segments {
+            let segment = *segment as usize;
+            packet.extend(&page.data[position..(position + segment)]);
+            position += segment;
+
+            if segment < 255 {
+                packets.push(std::mem::take(&mut packet));
+            }
+        }
+
+        if packets.len() >= codec.header_count() {
+            packets.truncate(codec.header_count());
+            return Ok(Headers { codec, serial, packets, page_count: index + 1 });
+        }
+    }
+
+    Err(anyhow!("Invalid Ogg file: the {:?} headers are cut off", codec))
+}
+
+fn read_comments(headers: &Headers) -> anyhow::Result<CommentHeader> {
+    let prefix = headers.codec.comment_prefix();
+    let packet = &headers.packets[1];
+
+    if !packet.starts_with(prefix) {
+        return Err(anyhow!("Invalid Ogg file: the second header isn't a comment header"));
+    }
+
+    let (vendor, all_comments) = vorbis::decode_comments(&packet[prefix.len()..])?;
+    let mut comments = Vec::new();
+    let mut pictures = Vec::new();
+
+    for (key, value) in all_comments {
+        if key.eq_ignore_ascii_case(PICTURE_KEY) {
+            pictures.push(PictureBlock::decode(&BASE64_STANDARD.decode(value.trim())?)?);
+        } else {
+            comments.push((key, value));
+        }
+    }
+
+    Ok(CommentHeader { vendor, comments, pictures })
+}
+
+// Splits the packets into pages of up to 255 segments. Each page gets a granule position of 0 if
+// a packet ends on it, and the last packet ends the last page, so the audio starts on a fresh one.
+fn paginate(packets: &[Vec<u8>], serial: u32, first_sequence: u32) -> Vec<Page> {
+    let mut pages = Vec::new();
+    let mut page = Page {
+        header_type: 0,
+        granule_position: NO_GRANULE_POSITION,
+        serial,
+        sequence: first_sequence,
+        segments: Vec::new(),
+        data: Vec::new(),
+    };
+
+    for packet in packets {
+        let mut chunks = packet.chunks(255).collect::<Vec<_>>();
+        // A packet that's a multiple of 255 bytes needs an empty segment to end it
+        if packet.len() % 255 == 0 {
+            chunks.push(&[]);
+        }
+
+        for chunk in chunks {
+            if page.segments.len() == MAX_SEGMENTS {
+                let next_page = Page {
+                    header_type: if page.segments.last() == Some(&255) { CONTINUED_PACKET } else { 0 },
+                    granule_position: NO_GRANULE_POSITION,
+                    serial,
+                    sequence: page.sequence + 1,
+                    segments: Vec::new(),
+                    data: Vec::new(),
+                };
+                pages.push(std::mem::replace(&mut page, next_page));
+            }
+
+            page.segments.push(chunk.len() as u8);
+            page.data.extend(chunk);
+
+            if chunk.len() < 255 {
+                page.granule_position = 0;
+            }
+        }
+    }
+
+    pages.push(page);
+    pages
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |crc, byte| {
+        (crc << 8) ^ CRC_TABLE[(((crc >> 24) as u8) ^ byte) as usize]
+    })
+}
+
+// Ogg uses the CRC-32 polynomial 0x04c11db7, without reflecting the bits or inverting the result
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+
+    while index < 256 {
+        let mut crc = (index as u32) << 24;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+            bit += 1;
+        }
+
+        table[index] = crc;
+        index += 1;
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        // The same as the CRC-32/POSIX check value for "123456789", 0x765E7680, without inverting
+        // the result
+        assert_eq!(crc32(b"123456789"), !0x765E_7680);
+    }
+
+    #[test]
+    fn test_paginate() {
+        let pages = paginate(&[vec![1; 300], vec![2; 255]], 7, 1);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].segments, vec![255, 45, 255, 0]);
+        assert_eq!(pages[0].granule_position, 0);
+
+        // 255 segments of 255 bytes fill a page, the last segment spills over
+        let pages = paginate(&[vec![3; 255 * 255 + 10]], 7, 1);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].granule_position, NO_GRANULE_POSITION);
+        assert_eq!(pages[1].header_type, CONTINUED_PACKET);
+        assert_eq!(pages[1].segments, vec![10]);
+        assert_eq!(pages[1].sequence, 2);
+        assert_eq!(pages[1].granule_position, 0);
+    }
+}
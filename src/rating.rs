@@ -0,0 +1,186 @@
+use anyhow::anyhow;
+use id3::TagLike;
+use id3::frame::{Content, Frame, Popularimeter, Unknown};
+
+use crate::input::RatingScale;
+
+// POPM frames have a rating from 1 to 255 for each user, usually an email address, or the name of
+// the player that set it, and 0 for "not rated". Players disagree on how that maps to stars.
+//
+// Windows Media Player writes 1, 64, 128, 196, and 255, and reads anything in between as the
+// closest lower step. Foobar2000 spreads the stars evenly, 51 apart.
+const WMP_STARS: [u8; 5] = [1, 64, 128, 196, 255];
+const WMP_THRESHOLDS: [u8; 5] = [1, 32, 96, 160, 224];
+
+const FOOBAR_STEP: u8 = 51;
+
+// The PCNT frame is a big-endian counter of at least 4 bytes
+const PLAY_COUNT_MIN_SIZE: usize = 4;
+
+pub fn stars(rating: u8, scale: RatingScale) -> Option<u8> {
+    if rating == 0 {
+        return None;
+    }
+
+    let stars = match scale {
+        RatingScale::Wmp    => WMP_THRESHOLDS.iter().filter(|t| rating >= **t).count() as u8,
+        RatingScale::Foobar => ((u32::from(rating) + u32::from(FOOBAR_STEP) / 2) / u32::from(FOOBAR_STEP)).max(1) as u8,
+    };
+
+    Some(stars)
+}
+
+pub fn rating_from_stars(stars: u8, scale: RatingScale) -> anyhow::Result<u8> {
+    match (stars, scale) {
+        (0, _)                       => Ok(0),
+        (1..=5, RatingScale::Wmp)    => Ok(WMP_STARS[usize::from(stars) - 1]),
+        (1..=5, RatingScale::Foobar) => Ok(stars * FOOBAR_STEP),
+        _                            => Err(anyhow!("Invalid number of stars: {}. Expected 0 to 5", stars)),
+    }
+}
+
+// An object with an entry for each user
+pub fn read_ratings(tag: &id3::Tag, scale: RatingScale) -> serde_json::Value {
+    tag.frames().
+        filter_map(|f| f.content().popularimeter()).
+        map(|p| {
+            let entry = serde_json::json!({
+                "rating":  p.rating,
+                "stars":   stars(p.rating, scale),
+                "counter": p.counter,
+            });
+            (p.user.clone(), entry)
+        }).
+        collect::<serde_json::Map<_, _>>().
+        into()
+}
+
+// Only the users in the object are changed, and `null` removes a user's rating. Each entry has
+// a raw `rating`, or `stars` that are converted with the given scale, or both, if the stars are
+// for the same rating. A missing `counter` keeps the existing one.
+pub fn write_ratings(tag: &mut id3::Tag, json_value: &serde_json::Value, scale: RatingScale) -> anyhow::Result<()> {
+    let entries = match json_value {
+        serde_json::Value::Null => {
+            tag.remove("POPM");
+            return Ok(());
+        },
+        serde_json::Value::Object(entries) => entries,
+        _ => return Err(anyhow!("The `ratings` key needs to be an object with users as keys")),
+    };
+
+    for (user, entry) in entries {
+        let existing = tag.frames().
+            filter_map(|f| f.content().popularimeter()).
+            find(|p| &p.user == user).
+            cloned();
+
+        if entry.is_null() {
+            let others = tag.remove("POPM").into_iter().
+                filter(|f| f.content().popularimeter().is_some_and(|p| &p.user != user)).
+                collect::<Vec<_>>();
+            for frame in others {
+                tag.add_frame(frame);
+            }
+            continue;
+        }
+
+        let invalid = || anyhow!("Invalid rating for {:?}: {}", user, entry);
+        let number = |key: &str| match entry.get(key) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(value) => value.as_u64().map(Some).ok_or_else(invalid),
+        };
+
+        let rating = number("rating")?.map(|r| u8::try_from(r).map_err(|_| invalid())).transpose()?;
+        let star_count = number("stars")?.map(|s| u8::try_from(s).map_err(|_| invalid())).transpose()?;
+
+        // Read output has both, so the stars win if they were edited to not match the rating
+        let rating = match (rating, star_count) {
+            (Some(rating), Some(star_count)) if stars(rating, scale).unwrap_or(0) != star_count => {
+                rating_from_stars(star_count, scale)?
+            },
+            (Some(rating), _)        => rating,
+            (None, Some(star_count)) => rating_from_stars(star_count, scale)?,
+            (None, None)             => existing.as_ref().map(|p| p.rating).unwrap_or(0),
+        };
+        let counter = number("counter")?.or(existing.map(|p| p.counter)).unwrap_or(0);
+
+        let popularimeter = Popularimeter { user: user.clone(), rating, counter };
+        tag.add_frame(Frame::with_content("POPM", Content::Popularimeter(popularimeter)));
+    }
+
+    Ok(())
+}
+
+pub fn read_play_count(tag: &id3::Tag) -> Option<u64> {
+    let data = tag.get("PCNT")?.content().to_unknown().ok()?.data.clone();
+
+    // Counters too large for 64 bits are unlikely to be real
+    if data.len() < PLAY_COUNT_MIN_SIZE || data.len() > 8 {
+        return None;
+    }
+    Some(data.iter().fold(0, |count, b| (count << 8) | u64::from(*b)))
+}
+
+pub fn write_play_count(tag: &mut id3::Tag, play_count: Option<u64>) {
+    tag.remove("PCNT");
+
+    let Some(play_count) = play_count else { return };
+
+    let bytes = play_count.to_be_bytes();
+    let first_significant = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    let data = bytes[first_significant.min(bytes.len() - PLAY_COUNT_MIN_SIZE)..].to_vec();
+
+    let content = Content::Unknown(Unknown { data, version: id3::Version::Id3v24 });
+    tag.add_frame(Frame::with_content("PCNT", content));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stars() {
+        assert_eq!(stars(0, RatingScale::Wmp), None);
+        assert_eq!(stars(1, RatingScale::Wmp), Some(1));
+        assert_eq!(stars(31, RatingScale::Wmp), Some(1));
+        assert_eq!(stars(64, RatingScale::Wmp), Some(2));
+        assert_eq!(stars(196, RatingScale::Wmp), Some(4));
+        assert_eq!(stars(255, RatingScale::Wmp), Some(5));
+
+        assert_eq!(stars(1, RatingScale::Foobar), Some(1));
+        assert_eq!(stars(102, RatingScale::Foobar), Some(2));
+        assert_eq!(stars(196, RatingScale::Foobar), Some(4));
+        assert_eq!(stars(255, RatingScale::Foobar), Some(5));
+    }
+
+    #[test]
+    fn test_rating_from_stars() {
+        for scale in [RatingScale::Wmp, RatingScale::Foobar] {
+            for star_count in 0..=5 {
+                let rating = rating_from_stars(star_count, scale).unwrap();
+                assert_eq!(stars(rating, scale).unwrap_or(0), star_count);
+            }
+        }
+
+        assert_eq!(rating_from_stars(4, RatingScale::Wmp).unwrap(), 196);
+        assert_eq!(rating_from_stars(4, RatingScale::Foobar).unwrap(), 204);
+        assert!(rating_from_stars(6, RatingScale::Wmp).is_err());
+    }
+
+    #[test]
+    fn test_play_count() {
+        let mut tag = id3::Tag::new();
+        assert_eq!(read_play_count(&tag), None);
+
+        write_play_count(&mut tag, Some(300));
+        assert_eq!(tag.get("PCNT").unwrap().content().to_unknown().unwrap().data, vec![0, 0, 1, 44]);
+        assert_eq!(read_play_count(&tag), Some(300));
+
+        write_play_count(&mut tag, Some(0x01_0000_0000));
+        assert_eq!(tag.get("PCNT").unwrap().content().to_unknown().unwrap().data, vec![1, 0, 0, 0, 0]);
+        assert_eq!(read_play_count(&tag), Some(0x01_0000_0000));
+
+        write_play_count(&mut tag, None);
+        assert_eq!(read_play_count(&tag), None);
+    }
+}
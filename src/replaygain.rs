@@ -0,0 +1,354 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::anyhow;
+use id3::TagLike;
+use id3::frame::{Content, ExtendedText, Frame};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::volume::{self, ChannelAdjustment, VolumeAdjustment};
+
+// ReplayGain 2.0 adjusts everything to the same loudness as EBU R128, though at -18 LUFS instead
+// of -23, so it's closer to the volume of the original ReplayGain.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+// EBU R128 measures loudness in overlapping blocks of 400ms, one every 100ms. Blocks quieter than
+// the absolute gate are silence, and then, blocks 10 LU quieter than the average of the rest are
+// quiet passages that shouldn't affect the result.
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+const MASTER_VOLUME: u8 = 0x01;
+
+const TRACK_GAIN: &str = "REPLAYGAIN_TRACK_GAIN";
+const TRACK_PEAK: &str = "REPLAYGAIN_TRACK_PEAK";
+const ALBUM_GAIN: &str = "REPLAYGAIN_ALBUM_GAIN";
+const ALBUM_PEAK: &str = "REPLAYGAIN_ALBUM_PEAK";
+
+// Direct form II transposed, with a separate state for every channel
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    state: Vec<[f64; 2]>,
+}
+
+impl Biquad {
+    fn process(&mut self, channel: usize, input: f64) -> f64 {
+        let state = &mut self.state[channel];
+        let output = self.b[0] * input + state[0];
+
+        state[0] = self.b[1] * input - self.a[1] * output + state[1];
+        state[1] = self.b[2] * input - self.a[2] * output;
+        output
+    }
+}
+
+// The K-weighting filter from ITU-R BS.1770: a high shelf that models the acoustic effect of the
+// head, and a high pass that ignores the lowest frequencies. The coefficients in the standard are
+// for 48kHz, these formulas are from libebur128, and work for any sample rate.
+fn k_weighting(sample_rate: u32, channels: usize) -> (Biquad, Biquad) {
+    let rate = f64::from(sample_rate);
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / rate).tan();
+    let vh = 10_f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: vec![[0.0; 2]; channels],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: vec![[0.0; 2]; channels],
+    };
+
+    (shelf, high_pass)
+}
+
+fn energy_to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+// The energy of each 400ms block, and the highest sample, so the loudness of an album can be
+// calculated from the blocks of all of its tracks together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Loudness {
+    blocks: Vec<f64>,
+    pub peak: f64,
+}
+
+impl Loudness {
+    // The gated loudness in LUFS, or None if it's all silence
+    pub fn integrated(&self) -> Option<f64> {
+        let above = |blocks: &[f64], gate: f64| {
+            blocks.iter().copied().filter(|e| energy_to_loudness(*e) > gate).collect::<Vec<_>>()
+        };
+        let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+
+        let audible = above(&self.blocks, ABSOLUTE_GATE);
+        if audible.is_empty() {
+            return None;
+        }
+
+        let relative_gate = energy_to_loudness(mean(&audible)) + RELATIVE_GATE;
+        let gated = above(&audible, relative_gate);
+
+        Some(energy_to_loudness(mean(&gated)))
+    }
+
+    pub fn combine(tracks: &[Loudness]) -> Loudness {
+        Loudness {
+            blocks: tracks.iter().flat_map(|t| t.blocks.iter().copied()).collect(),
+            peak:   tracks.iter().map(|t| t.peak).fold(0.0, f64::max),
+        }
+    }
+}
+
+pub struct Meter {
+    channels: usize,
+    filters: (Biquad, Biquad),
+    sub_block_size: usize,
+    sub_block_samples: usize,
+    sub_block_sum: f64,
+    sub_blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Meter {
+            channels,
+            filters:           k_weighting(sample_rate, channels),
+            sub_block_size:    (sample_rate / 10) as usize,
+            sub_block_samples: 0,
+            sub_block_sum:     0.0,
+            sub_blocks:        Vec::new(),
+            peak:              0.0,
+        }
+    }
+
+    // Takes interleaved samples, from -1.0 to 1.0
+    pub fn add_samples(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = f64::from(*sample);
+                self.peak = self.peak.max(sample.abs());
+
+                let shelved = self.filters.0.process(channel, sample);
+                let weighted = self.filters.1.process(channel, shelved);
+                self.sub_block_sum += weighted * weighted;
+            }
+
+            self.sub_block_samples += 1;
+            if self.sub_block_samples == self.sub_block_size {
+                self.sub_blocks.push(self.sub_block_sum / self.sub_block_size as f64);
+                self.sub_block_samples = 0;
+                self.sub_block_sum = 0.0;
+            }
+        }
+    }
+
+    // A partial block at the end is dropped, like in the standard
+    pub fn finish(self) -> Loudness {
+        let blocks = self.sub_blocks.
+            windows(SUB_BLOCKS_PER_BLOCK).
+            map(|window| window.iter().sum::<f64>() / SUB_BLOCKS_PER_BLOCK as f64).
+            collect();
+
+        Loudness { blocks, peak: self.peak }
+    }
+}
+
+// Decodes the whole file, skipping frames that can't be decoded, like a player would
+pub fn analyze_path(path: &Path) -> anyhow::Result<Loudness> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension("mp3");
+
+    let format_options = FormatOptions { enable_gapless: true, ..Default::default() };
+    let probed = symphonia::default::get_probe().
+        format(&hint, source, &format_options, &MetadataOptions::default())?;
+    let mut format = probed.format;
+
+    let track = format.default_track().ok_or_else(|| anyhow!("No audio in {}", path.display()))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut meter: Option<Meter> = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let buffer = buffer.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, spec));
+        buffer.copy_interleaved_ref(decoded);
+
+        meter.
+            get_or_insert_with(|| Meter::new(spec.rate, spec.channels.count())).
+            add_samples(buffer.samples());
+    }
+
+    Ok(meter.map(Meter::finish).unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gain {
+    pub loudness: f64,
+    pub gain: f64,
+    pub peak: f64,
+}
+
+impl Gain {
+    pub fn from_loudness(loudness: &Loudness) -> Option<Self> {
+        let integrated = loudness.integrated()?;
+        Some(Gain { loudness: integrated, gain: REFERENCE_LOUDNESS - integrated, peak: loudness.peak })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "loudness": (self.loudness * 100.0).round() / 100.0,
+            "gain":     (self.gain * 100.0).round() / 100.0,
+            "peak":     (self.peak * 1_000_000.0).round() / 1_000_000.0,
+        })
+    }
+}
+
+// Sets the TXXX frames that most players read, and RVA2 frames for the ones that only support
+// those. RVA2 only exists in ID3v2.4, so for older versions, returns false and only sets the TXXX
+// frames. Album values that are already there are kept when only the track is analyzed.
+pub fn write_to_tag(tag: &mut id3::Tag, track: &Gain, album: Option<&Gain>, version: id3::Version) -> bool {
+    let mut values = vec![("track", TRACK_GAIN, TRACK_PEAK, track)];
+    if let Some(album) = album {
+        values.push(("album", ALBUM_GAIN, ALBUM_PEAK, album));
+    }
+
+    let supports_rva2 = version == id3::Version::Id3v24;
+
+    for (identification, gain_key, peak_key, gain) in values {
+        set_extended_text(tag, gain_key, format!("{:.2} dB", gain.gain));
+        set_extended_text(tag, peak_key, format!("{:.6}", gain.peak));
+
+        if supports_rva2 {
+            let adjustment = VolumeAdjustment {
+                identification: String::from(identification),
+                channels: vec![ChannelAdjustment {
+                    channel_type: MASTER_VOLUME,
+                    adjustment:   gain.gain,
+                    peak:         Some(gain.peak),
+                }],
+            };
+            volume::set_volume_adjustment(tag, &adjustment);
+        }
+    }
+
+    supports_rva2
+}
+
+// The values of the TXXX frames, as numbers, or null if there are none
+pub fn read_from_tag(tag: &id3::Tag) -> serde_json::Value {
+    let read_value = |key: &str| {
+        tag.extended_texts().
+            find(|t| t.description.eq_ignore_ascii_case(key)).
+            and_then(|t| t.value.trim_end_matches('\u{0000}').trim().trim_end_matches("dB").trim().parse::<f64>().ok())
+    };
+
+    let values = [
+        ("track_gain", read_value(TRACK_GAIN)),
+        ("track_peak", read_value(TRACK_PEAK)),
+        ("album_gain", read_value(ALBUM_GAIN)),
+        ("album_peak", read_value(ALBUM_PEAK)),
+    ];
+
+    if values.iter().all(|(_, value)| value.is_none()) {
+        return serde_json::Value::Null;
+    }
+
+    values.into_iter().
+        map(|(key, value)| (String::from(key), value.into())).
+        collect::<serde_json::Map<_, _>>().
+        into()
+}
+
+fn set_extended_text(tag: &mut id3::Tag, description: &str, value: String) {
+    let text = ExtendedText { description: String::from(description), value };
+    tag.add_frame(Frame::with_content("TXXX", Content::ExtendedText(text)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(sample_rate: u32, frequency: f64, amplitude: f64, seconds: f64) -> Vec<f32> {
+        let count = (f64::from(sample_rate) * seconds) as usize;
+
+        (0..count).
+            flat_map(|i| {
+                let sample = (amplitude * (2.0 * PI * frequency * i as f64 / f64::from(sample_rate)).sin()) as f32;
+                [sample, sample]
+            }).
+            collect()
+    }
+
+    #[test]
+    fn test_loudness_of_sine() {
+        // Like the EBU Tech 3341 test cases: a 1kHz stereo sine at -20 dBFS is -20 LUFS
+        for sample_rate in [44100, 48000] {
+            let mut meter = Meter::new(sample_rate, 2);
+            meter.add_samples(&sine(sample_rate, 1000.0, 0.1, 20.0));
+
+            let loudness = meter.finish();
+            assert!((loudness.integrated().unwrap() - -20.0).abs() < 0.1);
+            assert!((loudness.peak - 0.1).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_gating() {
+        let mut meter = Meter::new(48000, 2);
+        meter.add_samples(&sine(48000, 1000.0, 0.1, 10.0));
+        // Silence and very quiet parts don't make it quieter
+        meter.add_samples(&vec![0.0; 48000 * 2 * 10]);
+        meter.add_samples(&sine(48000, 1000.0, 0.001, 10.0));
+
+        let loudness = meter.finish();
+        assert!((loudness.integrated().unwrap() - -20.0).abs() < 0.1);
+
+        assert_eq!(Loudness::default().integrated(), None);
+    }
+}
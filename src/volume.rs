@@ -0,0 +1,378 @@
+use anyhow::anyhow;
+use id3::TagLike;
+use id3::frame::{Content, Frame, Unknown};
+
+// RVA2 and EQU2 are ID3v2.4 frames that the id3 crate doesn't parse, so they're read from and
+// written to the raw frame data. Both start with an identification string, so there can be
+// several of them, like one with the track's ReplayGain and one with the album's.
+//
+// Volume adjustments are in 1/512 dB, as signed 16-bit big-endian integers.
+
+const CHANNEL_TYPES: &[(u8, &str)] = &[
+    (0, "other"),
+    (1, "master"),
+    (2, "front_right"),
+    (3, "front_left"),
+    (4, "back_right"),
+    (5, "back_left"),
+    (6, "front_center"),
+    (7, "back_center"),
+    (8, "subwoofer"),
+];
+
+const INTERPOLATION_METHODS: &[(u8, &str)] = &[
+    (0, "band"),
+    (1, "linear"),
+];
+
+// Peaks are written with 16 bits, like mutagen does it
+const PEAK_BITS: u8 = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelAdjustment {
+    pub channel_type: u8,
+    // In dB
+    pub adjustment: f64,
+    // From 0.0 to 1.0 for a full-scale sample, if there is one
+    pub peak: Option<f64>,
+}
+
+// The contents of an RVA2 frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeAdjustment {
+    pub identification: String,
+    pub channels: Vec<ChannelAdjustment>,
+}
+
+impl VolumeAdjustment {
+    // Each channel is its type, the adjustment, the number of bits of the peak, and the peak
+    // itself, which is a fixed-point number with a single bit before the point.
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let (identification, mut rest) = split_identification(data, "RVA2")?;
+        let mut channels = Vec::new();
+
+        while !rest.is_empty() {
+            let cut_off = || anyhow!("Invalid RVA2 frame: channel {} is cut off", channels.len() + 1);
+
+            let header = rest.get(0..4).ok_or_else(cut_off)?;
+            let peak_bytes = usize::from(header[3]).div_ceil(8);
+            let peak_data = rest.get(4..(4 + peak_bytes)).ok_or_else(cut_off)?;
+
+            let peak = (header[3] > 0).then(|| {
+                let value = peak_data.iter().fold(0_u64, |value, b| (value << 8) | u64::from(*b));
+                // The peak's bits are the lowest ones of its bytes
+                value as f64 / 2_f64.powi(i32::from(header[3]) - 1)
+            });
+
+            channels.push(ChannelAdjustment {
+                channel_type: header[0],
+                adjustment:   f64::from(i16::from_be_bytes([header[1], header[2]])) / 512.0,
+                peak,
+            });
+            rest = &rest[(4 + peak_bytes)..];
+        }
+
+        Ok(VolumeAdjustment { identification, channels })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = self.identification.chars().map(latin1_byte).collect::<Vec<_>>();
+        data.push(0);
+
+        for channel in &self.channels {
+            data.push(channel.channel_type);
+            data.extend(encode_adjustment(channel.adjustment).to_be_bytes());
+
+            if let Some(peak) = channel.peak {
+                let peak = (peak * 2_f64.powi(i32::from(PEAK_BITS) - 1)).round().clamp(0.0, f64::from(u16::MAX)) as u16;
+                data.push(PEAK_BITS);
+                data.extend(peak.to_be_bytes());
+            } else {
+                data.push(0);
+            }
+        }
+
+        data
+    }
+
+    pub fn to_frame(&self) -> Frame {
+        Frame::with_content("RVA2", Content::Unknown(Unknown { data: self.encode(), version: id3::Version::Id3v24 }))
+    }
+
+    pub fn from_frame(frame: &Frame) -> anyhow::Result<Self> {
+        Self::decode(&frame.content().to_unknown()?.data)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let channels = self.channels.iter().
+            map(|channel| serde_json::json!({
+                "type":       channel_type_name(channel.channel_type),
+                "adjustment": channel.adjustment,
+                "peak":       channel.peak.map(|p| (p * 1_000_000.0).round() / 1_000_000.0),
+            })).
+            collect::<Vec<_>>();
+
+        serde_json::json!({
+            "identification": self.identification,
+            "channels":       channels,
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> anyhow::Result<Self> {
+        let label = "volume_adjustments";
+        let entry = json.as_object().
+            ok_or_else(|| anyhow!("Entries in the `{}` array need to be objects", label))?;
+
+        let identification = read_identification(label, entry)?;
+        let channels = entry.get("channels").
+            and_then(serde_json::Value::as_array).
+            ok_or_else(|| anyhow!("Entries in the `{}` array need to have a `channels` array", label))?;
+
+        let channels = channels.iter().
+            map(|channel| {
+                let channel_type = match channel.get("type").and_then(serde_json::Value::as_str) {
+                    None => 1,
+                    Some(name) => CHANNEL_TYPES.iter().
+                        find(|(_, n)| *n == name).
+                        map(|(id, _)| *id).
+                        ok_or_else(|| anyhow!("Unknown channel type in `{}`: {:?}", label, name))?,
+                };
+
+                let adjustment = channel.get("adjustment").
+                    and_then(serde_json::Value::as_f64).
+                    ok_or_else(|| anyhow!("Channels in `{}` need to have a numeric `adjustment`", label))?;
+
+                let peak = match channel.get("peak") {
+                    None | Some(serde_json::Value::Null) => None,
+                    Some(peak) => Some(peak.as_f64().ok_or_else(|| anyhow!("Invalid peak in `{}`: {:?}", label, peak))?),
+                };
+
+                Ok(ChannelAdjustment { channel_type, adjustment, peak })
+            }).
+            collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(VolumeAdjustment { identification, channels })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EqualizationPoint {
+    // In Hz, in steps of 0.5
+    pub frequency: f64,
+    // In dB
+    pub adjustment: f64,
+}
+
+// The contents of an EQU2 frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equalization {
+    pub interpolation: u8,
+    pub identification: String,
+    pub points: Vec<EqualizationPoint>,
+}
+
+impl Equalization {
+    // The interpolation method comes before the identification, and each point is a frequency in
+    // units of 1/2 Hz, and an adjustment.
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let (&interpolation, data) = data.split_first().ok_or_else(|| anyhow!("Invalid EQU2 frame: it's empty"))?;
+        let (identification, rest) = split_identification(data, "EQU2")?;
+
+        if rest.len() % 4 != 0 {
+            return Err(anyhow!("Invalid EQU2 frame: the last point is cut off"));
+        }
+
+        let points = rest.chunks_exact(4).
+            map(|point| EqualizationPoint {
+                frequency:  f64::from(u16::from_be_bytes([point[0], point[1]])) / 2.0,
+                adjustment: f64::from(i16::from_be_bytes([point[2], point[3]])) / 512.0,
+            }).
+            collect();
+
+        Ok(Equalization { interpolation, identification, points })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.interpolation];
+        data.extend(self.identification.chars().map(latin1_byte));
+        data.push(0);
+
+        for point in &self.points {
+            let frequency = (point.frequency * 2.0).round().clamp(0.0, f64::from(u16::MAX)) as u16;
+            data.extend(frequency.to_be_bytes());
+            data.extend(encode_adjustment(point.adjustment).to_be_bytes());
+        }
+
+        data
+    }
+
+    pub fn to_frame(&self) -> Frame {
+        Frame::with_content("EQU2", Content::Unknown(Unknown { data: self.encode(), version: id3::Version::Id3v24 }))
+    }
+
+    pub fn from_frame(frame: &Frame) -> anyhow::Result<Self> {
+        Self::decode(&frame.content().to_unknown()?.data)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let interpolation = INTERPOLATION_METHODS.iter().
+            find(|(id, _)| *id == self.interpolation).
+            map(|(_, name)| *name);
+
+        let points = self.points.iter().
+            map(|point| serde_json::json!({ "frequency": point.frequency, "adjustment": point.adjustment })).
+            collect::<Vec<_>>();
+
+        serde_json::json!({
+            "identification": self.identification,
+            "interpolation":  interpolation,
+            "points":         points,
+        })
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> anyhow::Result<Self> {
+        let label = "equalizations";
+        let entry = json.as_object().
+            ok_or_else(|| anyhow!("Entries in the `{}` array need to be objects", label))?;
+
+        let identification = read_identification(label, entry)?;
+
+        let interpolation = match entry.get("interpolation").and_then(serde_json::Value::as_str) {
+            None => 1,
+            Some(name) => INTERPOLATION_METHODS.iter().
+                find(|(_, n)| *n == name).
+                map(|(id, _)| *id).
+                ok_or_else(|| anyhow!("Unknown interpolation in `{}`: {:?}. Expected band or linear", label, name))?,
+        };
+
+        let points = entry.get("points").
+            and_then(serde_json::Value::as_array).
+            ok_or_else(|| anyhow!("Entries in the `{}` array need to have a `points` array", label))?;
+
+        let points = points.iter().
+            map(|point| {
+                let number = |key: &str| {
+                    point.get(key).
+                        and_then(serde_json::Value::as_f64).
+                        ok_or_else(|| anyhow!("Points in `{}` need to have a numeric `{}`", label, key))
+                };
+
+                Ok(EqualizationPoint { frequency: number("frequency")?, adjustment: number("adjustment")? })
+            }).
+            collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Equalization { interpolation, identification, points })
+    }
+}
+
+// Frames that can't be decoded are skipped, since they'd be replaced anyway on writing
+pub fn read_from_tag(tag: &id3::Tag) -> (Vec<VolumeAdjustment>, Vec<Equalization>) {
+    let volume_adjustments = tag.frames().
+        filter(|f| f.id() == "RVA2").
+        filter_map(|f| VolumeAdjustment::from_frame(f).ok()).
+        collect();
+
+    let equalizations = tag.frames().
+        filter(|f| f.id() == "EQU2").
+        filter_map(|f| Equalization::from_frame(f).ok()).
+        collect();
+
+    (volume_adjustments, equalizations)
+}
+
+// Replaces the RVA2 frame with the same identification, or adds a new one
+pub fn set_volume_adjustment(tag: &mut id3::Tag, adjustment: &VolumeAdjustment) {
+    let frames = tag.remove("RVA2");
+
+    for frame in frames {
+        let is_same = VolumeAdjustment::from_frame(&frame).
+            is_ok_and(|existing| existing.identification == adjustment.identification);
+
+        if !is_same {
+            tag.add_frame(frame);
+        }
+    }
+
+    tag.add_frame(adjustment.to_frame());
+}
+
+fn split_identification<'a>(data: &'a [u8], frame_id: &str) -> anyhow::Result<(String, &'a [u8])> {
+    let end = data.iter().
+        position(|b| *b == 0).
+        ok_or_else(|| anyhow!("Invalid {} frame: the identification isn't terminated", frame_id))?;
+
+    // The identification is ISO-8859-1, which maps directly to the first 256 code points
+    let identification = data[..end].iter().map(|b| char::from(*b)).collect();
+    Ok((identification, &data[(end + 1)..]))
+}
+
+fn read_identification(label: &str, entry: &serde_json::Map<String, serde_json::Value>) -> anyhow::Result<String> {
+    match entry.get("identification") {
+        None | Some(serde_json::Value::Null) => Ok(String::new()),
+        Some(serde_json::Value::String(identification)) => Ok(identification.clone()),
+        Some(other) => Err(anyhow!("Invalid identification in `{}`: {:?}", label, other)),
+    }
+}
+
+fn latin1_byte(c: char) -> u8 {
+    u8::try_from(c).unwrap_or(b'?')
+}
+
+fn encode_adjustment(adjustment: f64) -> i16 {
+    (adjustment * 512.0).round().clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16
+}
+
+fn channel_type_name(channel_type: u8) -> &'static str {
+    CHANNEL_TYPES.iter().
+        find(|(id, _)| *id == channel_type).
+        map(|(_, name)| *name).
+        unwrap_or("other")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_adjustment_round_trip() {
+        let adjustment = VolumeAdjustment {
+            identification: String::from("track"),
+            channels: vec![
+                ChannelAdjustment { channel_type: 1, adjustment: -4.0, peak: Some(0.5) },
+                ChannelAdjustment { channel_type: 8, adjustment: 1.5, peak: None },
+            ],
+        };
+
+        let data = adjustment.encode();
+        assert_eq!(data, b"track\x00\x01\xF8\x00\x10\x40\x00\x08\x03\x00\x00");
+        assert_eq!(VolumeAdjustment::decode(&data).unwrap(), adjustment);
+    }
+
+    #[test]
+    fn test_volume_adjustment_peak_bits() {
+        // An 8-bit peak of 0x40 is 0.5, just like a 16-bit peak of 0x4000
+        let adjustment = VolumeAdjustment::decode(b"\x00\x01\x00\x00\x08\x40").unwrap();
+        assert_eq!(adjustment.channels[0].peak, Some(0.5));
+
+        assert!(VolumeAdjustment::decode(b"track").is_err());
+        assert!(VolumeAdjustment::decode(b"track\x00\x01\x00\x00\x10\x40").is_err());
+    }
+
+    #[test]
+    fn test_equalization_round_trip() {
+        let equalization = Equalization {
+            interpolation: 0,
+            identification: String::from("bass boost"),
+            points: vec![
+                EqualizationPoint { frequency: 60.0, adjustment: 3.0 },
+                EqualizationPoint { frequency: 120.5, adjustment: -1.25 },
+            ],
+        };
+
+        let data = equalization.encode();
+        assert_eq!(&data[0..12], b"\x00bass boost\x00");
+        assert_eq!(Equalization::decode(&data).unwrap(), equalization);
+
+        assert!(Equalization::decode(b"\x01id\x00\x00\x78\x06").is_err());
+    }
+}
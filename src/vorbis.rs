@@ -0,0 +1,400 @@
+use anyhow::anyhow;
+use id3::{Frame, TagLike};
+use id3::frame::{Comment, Content, ExtendedText, Picture, PictureType};
+
+// Vorbis comments are free-form "KEY=value" pairs, used by FLAC and Ogg files. The keys with a
+// common meaning are mapped to the ID3 frames with the same meaning, so they can be read and
+// written with the same JSON fields as ID3 tags. Anything else is kept in a TXXX frame with the
+// key as its description, so it survives a round trip. Keys are case-insensitive.
+const TEXT_FIELDS: &[(&str, &str)] = &[
+    ("TITLE",           "TIT2"),
+    ("ARTIST",          "TPE1"),
+    ("ALBUM",           "TALB"),
+    ("GENRE",           "TCON"),
+    ("DATE",            "TDRC"),
+    ("RELEASEDATE",     "TDRL"),
+    ("ORIGINALDATE",    "TDOR"),
+    ("ALBUMARTIST",     "TPE2"),
+    ("COMPOSER",        "TCOM"),
+    ("CONDUCTOR",       "TPE3"),
+    ("REMIXER",         "TPE4"),
+    ("LYRICIST",        "TEXT"),
+    ("GROUPING",        "TIT1"),
+    ("SUBTITLE",        "TIT3"),
+    ("LABEL",           "TPUB"),
+    ("COPYRIGHT",       "TCOP"),
+    ("KEY",             "TKEY"),
+    ("LANGUAGE",        "TLAN"),
+    ("MOOD",            "TMOO"),
+    ("ISRC",            "TSRC"),
+    ("ARTISTSORT",      "TSOP"),
+    ("ALBUMSORT",       "TSOA"),
+    ("TITLESORT",       "TSOT"),
+    ("ALBUMARTISTSORT", "TSO2"),
+    ("COMPOSERSORT",    "TSOC"),
+    ("BPM",             "TBPM"),
+    ("COMPILATION",     "TCMP"),
+];
+
+// Other names that different taggers use for the same things. They're read, but the tag is
+// written with the names above.
+const ALIASES: &[(&str, &str)] = &[
+    ("ALBUM ARTIST", "ALBUMARTIST"),
+    ("ALBUM_ARTIST", "ALBUMARTIST"),
+    ("PUBLISHER",    "LABEL"),
+    ("ORGANIZATION", "LABEL"),
+    ("TOTALTRACKS",  "TRACKTOTAL"),
+    ("TOTALDISCS",   "DISCTOTAL"),
+    ("DESCRIPTION",  "COMMENT"),
+    ("YEAR",         "DATE"),
+];
+
+// Track and disc numbers are stored as separate number and total comments
+const NUMBER_PAIRS: &[(&str, &str, &str)] = &[
+    ("TRCK", "TRACKNUMBER", "TRACKTOTAL"),
+    ("TPOS", "DISCNUMBER",  "DISCTOTAL"),
+];
+
+// In the order of their numeric IDs, which are the same for FLAC and ID3
+const PICTURE_TYPES: &[PictureType] = &[
+    PictureType::Other,
+    PictureType::Icon,
+    PictureType::OtherIcon,
+    PictureType::CoverFront,
+    PictureType::CoverBack,
+    PictureType::Leaflet,
+    PictureType::Media,
+    PictureType::LeadArtist,
+    PictureType::Artist,
+    PictureType::Conductor,
+    PictureType::Band,
+    PictureType::Composer,
+    PictureType::Lyricist,
+    PictureType::RecordingLocation,
+    PictureType::DuringRecording,
+    PictureType::DuringPerformance,
+    PictureType::ScreenCapture,
+    PictureType::BrightFish,
+    PictureType::Illustration,
+    PictureType::BandLogo,
+    PictureType::PublisherLogo,
+];
+
+// A FLAC PICTURE block, which is also used base64-encoded in Ogg files. The image dimensions
+// aren't a part of ID3 pictures, so they're kept around to be written back unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PictureBlock {
+    pub picture: Picture,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub colors: u32,
+}
+
+impl PictureBlock {
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        let picture_type = reader.u32_be()?;
+        let mime_type    = reader.string_be()?;
+        let description  = reader.string_be()?;
+        let width        = reader.u32_be()?;
+        let height       = reader.u32_be()?;
+        let depth        = reader.u32_be()?;
+        let colors       = reader.u32_be()?;
+        let data_length  = reader.u32_be()? as usize;
+        let data         = reader.take(data_length)?.to_vec();
+
+        let picture_type = PICTURE_TYPES.get(picture_type as usize).copied().
+            unwrap_or_else(|| PictureType::Undefined(u8::try_from(picture_type).unwrap_or(u8::MAX)));
+
+        let picture = Picture { mime_type, picture_type, description, data };
+        Ok(PictureBlock { picture, width, height, depth, colors })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let picture = &self.picture;
+
+        bytes.extend(u32::from(u8::from(picture.picture_type)).to_be_bytes());
+        bytes.extend((picture.mime_type.len() as u32).to_be_bytes());
+        bytes.extend(picture.mime_type.as_bytes());
+        bytes.extend((picture.description.len() as u32).to_be_bytes());
+        bytes.extend(picture.description.as_bytes());
+        bytes.extend(self.width.to_be_bytes());
+        bytes.extend(self.height.to_be_bytes());
+        bytes.extend(self.depth.to_be_bytes());
+        bytes.extend(self.colors.to_be_bytes());
+        bytes.extend((picture.data.len() as u32).to_be_bytes());
+        bytes.extend(&picture.data);
+
+        bytes
+    }
+}
+
+// The vendor string and the "KEY=value" comments, all with little-endian lengths. Ogg Vorbis adds
+// a framing bit after this, which is left to the caller.
+pub fn decode_comments(bytes: &[u8]) -> anyhow::Result<(String, Vec<(String, String)>)> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    let vendor = reader.string_le()?;
+    let count = reader.u32_le()?;
+    let mut comments = Vec::new();
+
+    for _ in 0..count {
+        let comment = reader.string_le()?;
+        let Some((key, value)) = comment.split_once('=') else {
+            return Err(anyhow!("Invalid Vorbis comment: {:?}", comment));
+        };
+        comments.push((key.to_string(), value.to_string()));
+    }
+
+    Ok((vendor, comments))
+}
+
+pub fn encode_comments(vendor: &str, comments: &[(String, String)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    bytes.extend((vendor.len() as u32).to_le_bytes());
+    bytes.extend(vendor.as_bytes());
+    bytes.extend((comments.len() as u32).to_le_bytes());
+
+    for (key, value) in comments {
+        let comment = format!("{}={}", key, value);
+        bytes.extend((comment.len() as u32).to_le_bytes());
+        bytes.extend(comment.as_bytes());
+    }
+
+    bytes
+}
+
+// Builds an ID3v2.4 tag with the same data, since that version supports multiple values, like
+// Vorbis comments do.
+pub fn to_id3_tag(comments: &[(String, String)], pictures: &[PictureBlock]) -> id3::Tag {
+    let mut tag = id3::Tag::with_version(id3::Version::Id3v24);
+    let mut grouped: Vec<(String, Vec<String>)> = Vec::new();
+
+    for (key, value) in comments {
+        let key = canonical_key(key);
+
+        match grouped.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, values)) => values.push(value.clone()),
+            None              => grouped.push((key, vec![value.clone()])),
+        }
+    }
+
+    let first_value = |key: &str| {
+        grouped.iter().find(|(k, _)| k == key).and_then(|(_, values)| values.first()).cloned()
+    };
+
+    for (frame_id, number_key, total_key) in NUMBER_PAIRS {
+        // Some taggers put "3/12" in the track number instead of using the total
+        let (number, total) = match first_value(number_key) {
+            Some(number) => match number.split_once('/') {
+                Some((number, total)) => (Some(number.to_string()), Some(total.to_string())),
+                None                  => (Some(number), None),
+            },
+            None => (None, None),
+        };
+        let total = first_value(total_key).or(total);
+
+        let text = match (number, total) {
+            (Some(number), Some(total)) => format!("{}/{}", number, total),
+            (Some(number), None)        => number,
+            (None, Some(total))         => format!("/{}", total),
+            (None, None)                => continue,
+        };
+        tag.set_text(*frame_id, text);
+    }
+
+    for (key, values) in &grouped {
+        if NUMBER_PAIRS.iter().any(|(_, number_key, total_key)| key == number_key || key == total_key) {
+            continue;
+        }
+
+        if key == "COMMENT" {
+            let comment = Comment {
+                lang:        String::new(),
+                description: String::new(),
+                text:        values.join("\n"),
+            };
+            tag.add_frame(Frame::with_content("COMM", Content::Comment(comment)));
+        } else if let Some((_, frame_id)) = TEXT_FIELDS.iter().find(|(k, _)| k == key) {
+            set_text_values(&mut tag, frame_id, values);
+        } else {
+            let extended_text = ExtendedText {
+                description: key.clone(),
+                value:       values.join("\u{0000}"),
+            };
+            tag.add_frame(Frame::with_content("TXXX", Content::ExtendedText(extended_text)));
+        }
+    }
+
+    for block in pictures {
+        tag.add_frame(Frame::with_content("APIC", Content::Picture(block.picture.clone())));
+    }
+
+    tag
+}
+
+// The result of converting an ID3 tag back into Vorbis comments. Frames that have no Vorbis
+// equivalent are listed in `skipped`, so they don't get lost silently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comments {
+    pub comments: Vec<(String, String)>,
+    pub pictures: Vec<PictureBlock>,
+    pub skipped: Vec<String>,
+}
+
+// The picture blocks that were originally in the file are used to keep the image dimensions of
+// pictures that haven't changed.
+pub fn from_id3_tag(tag: &id3::Tag, original_pictures: &[PictureBlock]) -> Comments {
+    let mut comments = Vec::new();
+    let mut pictures = Vec::new();
+    let mut skipped  = Vec::new();
+
+    let values = |text: &str| {
+        text.trim_end_matches('\u{0000}').split('\u{0000}').map(String::from).collect::<Vec<_>>()
+    };
+
+    for frame in tag.frames() {
+        let frame_id = frame.id();
+
+        if let Some((_, number_key, total_key)) = NUMBER_PAIRS.iter().find(|(id, _, _)| *id == frame_id) {
+            let text = frame.content().text().unwrap_or_default().trim_end_matches('\u{0000}');
+            let (number, total) = text.split_once('/').unwrap_or((text, ""));
+
+            if !number.is_empty() {
+                comments.push((number_key.to_string(), number.to_string()));
+            }
+            if !total.is_empty() {
+                comments.push((total_key.to_string(), total.to_string()));
+            }
+        } else if let Some((key, _)) = TEXT_FIELDS.iter().find(|(_, id)| *id == frame_id) {
+            for value in values(frame.content().text().unwrap_or_default()) {
+                comments.push((key.to_string(), value));
+            }
+        } else if let Some(comment) = frame.content().comment().filter(|c| c.description.is_empty()) {
+            comments.push((String::from("COMMENT"), comment.text.trim_end_matches('\u{0000}').to_string()));
+        } else if let Some(extended_text) = frame.content().extended_text() {
+            for value in values(&extended_text.value) {
+                comments.push((extended_text.description.to_uppercase(), value));
+            }
+        } else if let Some(picture) = frame.content().picture() {
+            let block = original_pictures.iter().
+                find(|b| b.picture == *picture).
+                cloned().
+                unwrap_or_else(|| PictureBlock {
+                    picture: picture.clone(),
+                    width: 0,
+                    height: 0,
+                    depth: 0,
+                    colors: 0,
+                });
+            pictures.push(block);
+        } else if !skipped.iter().any(|id| id == frame_id) {
+            skipped.push(frame_id.to_string());
+        }
+    }
+
+    Comments { comments, pictures, skipped }
+}
+
+// A NUL in a value read from a file would be taken as a separator in ID3v2.4, and the id3 crate
+// panics on it, so it's treated as one: "a\0b" becomes two values. Also used for MP4 metadata.
+pub(crate) fn set_text_values<S: AsRef<str>>(tag: &mut id3::Tag, frame_id: &str, values: &[S]) {
+    let values = values.iter().
+        flat_map(|v| v.as_ref().split('\0')).
+        filter(|v| !v.is_empty());
+    tag.set_text_values(frame_id, values);
+}
+
+fn canonical_key(key: &str) -> String {
+    let key = key.to_uppercase();
+
+    match ALIASES.iter().find(|(alias, _)| *alias == key) {
+        Some((_, canonical)) => canonical.to_string(),
+        None                 => key,
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.position.checked_add(length).filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(anyhow!("Unexpected end of metadata block at byte {}", self.position));
+        };
+
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn u32_be(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u32_le(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn string_be(&mut self) -> anyhow::Result<String> {
+        let length = self.u32_be()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).to_string())
+    }
+
+    fn string_le(&mut self) -> anyhow::Result<String> {
+        let length = self.u32_le()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments_round_trip() {
+        let comments = vec![
+            (String::from("TITLE"), String::from("Elevator Music")),
+            (String::from("artist"), String::from("Christiaan Bakker")),
+        ];
+        let bytes = encode_comments("reference libFLAC 1.4.3", &comments);
+        let (vendor, decoded) = decode_comments(&bytes).unwrap();
+
+        assert_eq!(vendor, "reference libFLAC 1.4.3");
+        assert_eq!(decoded, comments);
+
+        assert!(decode_comments(&bytes[0..(bytes.len() - 1)]).is_err());
+    }
+
+    #[test]
+    fn test_picture_round_trip() {
+        let block = PictureBlock {
+            picture: Picture {
+                mime_type:    String::from("image/png"),
+                picture_type: PictureType::CoverBack,
+                description:  String::from("Back"),
+                data:         vec![1, 2, 3],
+            },
+            width: 300,
+            height: 200,
+            depth: 24,
+            colors: 0,
+        };
+
+        assert_eq!(PictureBlock::decode(&block.encode()).unwrap(), block);
+    }
+
+    #[test]
+    fn test_canonical_key() {
+        assert_eq!(canonical_key("Album Artist"), "ALBUMARTIST");
+        assert_eq!(canonical_key("totaltracks"), "TRACKTOTAL");
+        assert_eq!(canonical_key("replaygain_track_gain"), "REPLAYGAIN_TRACK_GAIN");
+    }
+}
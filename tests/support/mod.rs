@@ -1,2 +1,5 @@
+// Not every test file uses every helper
+#![allow(dead_code)]
+
 pub mod fixture;
 pub mod tag;
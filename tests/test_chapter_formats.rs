@@ -0,0 +1,207 @@
+use std::path::Path;
+
+use id3_json::chapter_formats::*;
+use id3_json::input::ChapterFormat;
+
+mod support;
+use support::fixture::Fixture;
+use support::tag::read_tag;
+
+fn entry(start: u32, end: Option<u32>, title: &str) -> ChapterEntry {
+    ChapterEntry { start, end, title: title.to_string(), url: None }
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(detect_format(";FFMETADATA1\ntitle=Episode\n"), ChapterFormat::FfMetadata);
+    assert_eq!(detect_format("\u{feff}[{ \"start\": \"00:00:00\" }]"), ChapterFormat::Podlove);
+    assert_eq!(detect_format("  { \"chapters\": [] }"), ChapterFormat::Podlove);
+    assert_eq!(detect_format("00:00 Intro\n"), ChapterFormat::Text);
+
+    assert_eq!(format_from_extension(Path::new("chapters.JSON")), ChapterFormat::Podlove);
+    assert_eq!(format_from_extension(Path::new("chapters.txt")), ChapterFormat::Text);
+    assert_eq!(format_from_extension(Path::new("metadata.ffmeta")), ChapterFormat::FfMetadata);
+    assert_eq!(format_from_extension(Path::new("chapters")), ChapterFormat::FfMetadata);
+}
+
+#[test]
+fn test_parse_ffmetadata() {
+    let contents = "\
+;FFMETADATA1
+title=Episode 12
+artist=Someone
+
+[CHAPTER]
+TIMEBASE=1/1000
+START=0
+END=60000
+title=Intro
+
+; A comment
+[CHAPTER]
+TIMEBASE=1/44100
+START=2646000
+END=6197805
+title=News \\= Weather\\; and \\#sports
+
+[STREAM]
+title=Ignored
+";
+
+    let entries = parse(contents, ChapterFormat::FfMetadata).unwrap();
+    assert_eq!(entries, vec![
+        entry(0, Some(60000), "Intro"),
+        entry(60000, Some(140539), "News = Weather; and #sports"),
+    ]);
+
+    assert!(parse("[CHAPTER]\nSTART=0\nEND=1\n", ChapterFormat::FfMetadata).is_err());
+    assert!(parse(";FFMETADATA1\n[CHAPTER]\nSTART=0\n", ChapterFormat::FfMetadata).is_err());
+    assert!(parse(";FFMETADATA1\n[CHAPTER]\nTIMEBASE=1/0\nSTART=0\nEND=1\n", ChapterFormat::FfMetadata).is_err());
+
+    let huge_time_base = ";FFMETADATA1\n[CHAPTER]\nTIMEBASE=18446744073709551615/1\nSTART=0\nEND=1\ntitle=Intro\n";
+    let error = parse(huge_time_base, ChapterFormat::FfMetadata).unwrap_err();
+    assert_eq!(format!("{}", error), "Chapter \"Intro\" is too long");
+}
+
+#[test]
+fn test_parse_podlove() {
+    let contents = r#"[
+        { "start": "00:00:00.000", "title": "Intro", "href": "", "image": "" },
+        { "start": "00:01:00.5", "title": "News", "href": "https://example.com/news" },
+        { "start": 90, "title": "Weather" }
+    ]"#;
+
+    let entries = parse(contents, ChapterFormat::Podlove).unwrap();
+    assert_eq!(entries, vec![
+        entry(0, None, "Intro"),
+        ChapterEntry { url: Some(String::from("https://example.com/news")), ..entry(60500, None, "News") },
+        entry(90000, None, "Weather"),
+    ]);
+
+    let entries = parse(r#"{ "chapters": [{ "start": "1:00", "title": "News" }] }"#, ChapterFormat::Podlove).unwrap();
+    assert_eq!(entries, vec![entry(60000, None, "News")]);
+
+    assert!(parse(r#"[{ "title": "No start" }]"#, ChapterFormat::Podlove).is_err());
+    assert!(parse(r#"{ "start": "00:00" }"#, ChapterFormat::Podlove).is_err());
+    assert!(parse("[", ChapterFormat::Podlove).is_err());
+}
+
+#[test]
+fn test_parse_text() {
+    let contents = "\
+# Episode 12
+00:00 Intro
+1:00 - News
+
+01:30.250   Weather and sports
+";
+
+    let entries = parse(contents, ChapterFormat::Text).unwrap();
+    assert_eq!(entries, vec![
+        entry(0, None, "Intro"),
+        entry(60000, None, "News"),
+        entry(90250, None, "Weather and sports"),
+    ]);
+
+    assert!(parse("Intro 00:00\n", ChapterFormat::Text).is_err());
+}
+
+#[test]
+fn test_resolve() {
+    let entries = vec![entry(60000, None, "News"), entry(0, None, "Intro")];
+
+    let resolved = resolve(entries.clone(), Some(140539)).unwrap();
+    assert_eq!(resolved, vec![entry(0, Some(60000), "Intro"), entry(60000, Some(140539), "News")]);
+
+    let error = resolve(entries.clone(), None).unwrap_err();
+    assert_eq!(format!("{}", error), "Can't tell where chapter \"News\" ends, since the file's duration is unknown");
+
+    let error = resolve(vec![entry(0, Some(150000), "Intro")], Some(140539)).unwrap_err();
+    assert_eq!(format!("{}", error), "Chapter \"Intro\" ends at 00:02:30, after the end of the file at 00:02:20.539");
+
+    let error = resolve(vec![entry(0, None, "Intro"), entry(150000, None, "Late")], Some(140539)).unwrap_err();
+    assert_eq!(format!("{}", error), "Chapter \"Late\" starts at 00:02:30, after the end of the file at 00:02:20.539");
+
+    let error = resolve(vec![entry(0, Some(70000), "Intro"), entry(60000, None, "News")], Some(140539)).unwrap_err();
+    assert_eq!(format!("{}", error), "Chapters \"Intro\" and \"News\" overlap");
+
+    let error = resolve(vec![entry(0, None, "Intro"), entry(0, None, "Again")], Some(140539)).unwrap_err();
+    assert_eq!(format!("{}", error), "Chapter \"Intro\" doesn't end after it starts");
+
+    // Gaps between chapters are fine, and so are unknown durations with explicit end times
+    let entries = vec![entry(0, Some(10000), "Intro"), entry(20000, Some(30000), "News")];
+    assert_eq!(resolve(entries.clone(), None).unwrap(), entries);
+}
+
+#[test]
+fn test_serialize() {
+    let entries = vec![
+        entry(0, Some(60000), "Intro"),
+        ChapterEntry { url: Some(String::from("https://example.com/news")), ..entry(60500, Some(140539), "News = #1") },
+    ];
+
+    assert_eq!(serialize(&entries, ChapterFormat::FfMetadata), "\
+;FFMETADATA1
+
+[CHAPTER]
+TIMEBASE=1/1000
+START=0
+END=60000
+title=Intro
+
+[CHAPTER]
+TIMEBASE=1/1000
+START=60500
+END=140539
+title=News \\= \\#1
+");
+
+    assert_eq!(serialize(&entries, ChapterFormat::Text), "00:00:00 Intro\n00:01:00.500 News = #1\n");
+
+    let podlove: serde_json::Value = serde_json::from_str(&serialize(&entries, ChapterFormat::Podlove)).unwrap();
+    assert_eq!(podlove, serde_json::json!([
+        { "start": "00:00:00.000", "title": "Intro", "href": "", "image": "" },
+        { "start": "00:01:00.500", "title": "News = #1", "href": "https://example.com/news", "image": "" },
+    ]));
+
+    // Each format reads back what it wrote, apart from what it can't store. Without end times,
+    // the gap between the chapters is gone.
+    for (format, intro_end) in [(ChapterFormat::FfMetadata, 60000), (ChapterFormat::Podlove, 60500), (ChapterFormat::Text, 60500)] {
+        let parsed = parse(&serialize(&entries, format), format).unwrap();
+        let resolved = resolve(parsed, Some(140539)).unwrap();
+
+        assert_eq!(resolved.iter().map(|e| (e.start, e.end)).collect::<Vec<_>>(), vec![
+            (0, Some(intro_end)),
+            (60500, Some(140539)),
+        ]);
+    }
+}
+
+#[test]
+fn test_write_to_tag() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let entries = vec![
+        entry(0, Some(60000), "Intro"),
+        ChapterEntry { url: Some(String::from("https://example.com/news")), ..entry(60000, Some(140539), "News") },
+    ];
+    write_to_tag(&mut tag, &entries);
+    tag.write_to_path(&*song, id3::Version::Id3v24).unwrap();
+
+    let mut tag = read_tag(&song);
+    assert_eq!(read_from_tag(&tag), entries);
+
+    let tables = tag.tables_of_contents().collect::<Vec<_>>();
+    assert_eq!(tables.len(), 1);
+    assert!(tables[0].top_level && tables[0].ordered);
+    assert_eq!(tables[0].elements, vec!["chp0", "chp1"]);
+
+    // Importing again replaces everything
+    write_to_tag(&mut tag, &[entry(0, Some(1000), "Only")]);
+    assert_eq!(read_from_tag(&tag), vec![entry(0, Some(1000), "Only")]);
+    assert_eq!(tag.tables_of_contents().next().unwrap().elements, vec!["chp0"]);
+
+    write_to_tag(&mut tag, &[]);
+    assert_eq!(tag.frames().filter(|f| f.id() == "CHAP" || f.id() == "CTOC").count(), 0);
+}
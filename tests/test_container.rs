@@ -0,0 +1,127 @@
+use std::fs;
+
+use id3::TagLike;
+
+use id3_json::container::*;
+use id3_json::input::Args;
+use id3_json::json;
+
+mod support;
+use support::fixture::Fixture;
+use support::tag::read_tag;
+
+// A WAV file with a format chunk and two samples of 16-bit mono silence
+fn wav_bytes() -> Vec<u8> {
+    let mut fmt = Vec::new();
+    fmt.extend(1_u16.to_le_bytes());     // PCM
+    fmt.extend(1_u16.to_le_bytes());     // Channels
+    fmt.extend(44100_u32.to_le_bytes()); // Sample rate
+    fmt.extend(88200_u32.to_le_bytes()); // Byte rate
+    fmt.extend(2_u16.to_le_bytes());     // Block align
+    fmt.extend(16_u16.to_le_bytes());    // Bits per sample
+
+    let mut body = b"WAVE".to_vec();
+    body.extend(b"fmt ");
+    body.extend((fmt.len() as u32).to_le_bytes());
+    body.extend(fmt);
+    body.extend(b"data");
+    body.extend(4_u32.to_le_bytes());
+    body.extend([0; 4]);
+
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend((body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+// An AIFF file with a common chunk and two samples of 16-bit mono silence
+fn aiff_bytes() -> Vec<u8> {
+    let mut comm = Vec::new();
+    comm.extend(1_u16.to_be_bytes()); // Channels
+    comm.extend(2_u32.to_be_bytes()); // Sample frames
+    comm.extend(16_u16.to_be_bytes()); // Bits per sample
+    comm.extend([0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]); // 44100 as an 80-bit float
+
+    let mut body = b"AIFF".to_vec();
+    body.extend(b"COMM");
+    body.extend((comm.len() as u32).to_be_bytes());
+    body.extend(comm);
+    body.extend(b"SSND");
+    body.extend(12_u32.to_be_bytes());
+    body.extend([0; 12]);
+
+    let mut bytes = b"FORM".to_vec();
+    bytes.extend((body.len() as u32).to_be_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+fn write_and_read(path: &std::path::Path) -> id3::Tag {
+    let mut tag = match id3::Tag::read_from_path(path) {
+        Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) => id3::Tag::new(),
+        other => other.unwrap(),
+    };
+
+    let input = serde_json::json!({ "data": { "title": "Chunked", "track": 3 } });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+    tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+
+    read_tag(path)
+}
+
+#[test]
+fn test_detect_from_path() {
+    let song = Fixture::copy("attempt_1.mp3");
+    assert_eq!(detect_from_path(&song).unwrap(), Container::Mpeg);
+
+    let file = Fixture::blank("song.wav");
+    fs::write(&*file, wav_bytes()).unwrap();
+    assert_eq!(detect_from_path(&file).unwrap(), Container::Wav);
+
+    let file = Fixture::blank("song.aiff");
+    fs::write(&*file, aiff_bytes()).unwrap();
+    assert_eq!(detect_from_path(&file).unwrap(), Container::Aiff);
+
+    // The extension doesn't matter
+    let file = Fixture::blank("song.mp3");
+    fs::write(&*file, b"fLaC\x00\x00\x00\x22").unwrap();
+    assert_eq!(detect_from_path(&file).unwrap(), Container::Flac);
+
+    let file = Fixture::blank("song.mp3");
+    fs::write(&*file, b"wvpk\x00\x00\x00\x00").unwrap();
+    assert_eq!(detect_from_path(&file).unwrap_err().to_string(), "Unsupported container: WavPack");
+}
+
+#[test]
+fn test_wav_tags() {
+    let file = Fixture::blank("song.wav");
+    fs::write(&*file, wav_bytes()).unwrap();
+
+    let tag = write_and_read(&file);
+    assert_eq!(tag.title(), Some("Chunked"));
+    assert_eq!(tag.track(), Some(3));
+
+    // Still a valid WAV file, with the tag in a chunk:
+    let bytes = fs::read(&*file).unwrap();
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(&bytes[8..12], b"WAVE");
+    assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+    assert!(bytes.windows(4).any(|w| w == b"id3 " || w == b"ID3 "));
+
+    let json = json::read_from_tag(&tag, &Args::default());
+    assert_eq!(json.get("data").unwrap().get("title").unwrap(), "Chunked");
+}
+
+#[test]
+fn test_aiff_tags() {
+    let file = Fixture::blank("song.aiff");
+    fs::write(&*file, aiff_bytes()).unwrap();
+
+    let tag = write_and_read(&file);
+    assert_eq!(tag.title(), Some("Chunked"));
+    assert_eq!(tag.track(), Some(3));
+
+    let bytes = fs::read(&*file).unwrap();
+    assert_eq!(&bytes[0..4], b"FORM");
+    assert_eq!(u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+}
@@ -0,0 +1,150 @@
+use id3::{Frame, TagLike, Version};
+use id3::frame::{Content, InvolvedPeopleList, InvolvedPeopleListItem};
+
+use id3_json::convert::*;
+
+mod support;
+use support::fixture::Fixture;
+use support::tag::read_tag;
+
+fn find_entry<'a>(report: &'a Report, frame_id: &str) -> &'a Entry {
+    report.entries.iter().find(|e| e.frame_id == frame_id).unwrap()
+}
+
+#[test]
+fn test_same_version() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+    let frame_count = tag.frames().count();
+
+    let report = convert_tag(&mut tag, Version::Id3v24, "/");
+
+    assert!(report.entries.is_empty());
+    assert!(!report.is_lossy());
+    assert_eq!(tag.frames().count(), frame_count);
+}
+
+#[test]
+fn test_id3v24_to_id3v23() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    tag.set_text("TDRC", "2023-06-01T12:30");
+    tag.set_text("TDOR", "1999");
+    tag.set_text_values("TPE1", ["Simon", "Garfunkel"]);
+    tag.set_text("TSOP", "Simon");
+    tag.set_text("TSST", "Set subtitle");
+
+    let report = convert_tag(&mut tag, Version::Id3v23, "; ");
+    assert_eq!(tag.version(), Version::Id3v23);
+
+    assert_eq!(tag.get("TYER").unwrap().content().text(), Some("2023"));
+    assert_eq!(tag.get("TDAT").unwrap().content().text(), Some("0106"));
+    assert_eq!(tag.get("TIME").unwrap().content().text(), Some("1230"));
+    assert_eq!(tag.get("TORY").unwrap().content().text(), Some("1999"));
+    assert_eq!(tag.get("TPE1").unwrap().content().text(), Some("Simon; Garfunkel"));
+    assert_eq!(tag.get("TSOP").unwrap().content().text(), Some("Simon"));
+    assert!(tag.get("TDRC").is_none());
+    assert!(tag.get("TDOR").is_none());
+    assert!(tag.get("TSST").is_none());
+
+    let entry = find_entry(&report, "TDRC");
+    assert_eq!(entry.action, Action::Converted);
+    assert_eq!(entry.into, vec!["TYER", "TDAT", "TIME"]);
+    assert!(!entry.lossy);
+
+    assert_eq!(find_entry(&report, "TDOR").action, Action::Converted);
+    assert_eq!(find_entry(&report, "TPE1").action, Action::Merged);
+    assert_eq!(find_entry(&report, "TSOP").action, Action::Kept);
+    assert_eq!(find_entry(&report, "TSST").action, Action::Dropped);
+    assert!(report.is_lossy());
+
+    // The converted tag can be written and read back:
+    tag.write_to_path(&*song, Version::Id3v23).unwrap();
+    let tag = read_tag(&song);
+    assert_eq!(tag.version(), Version::Id3v23);
+    assert_eq!(tag.get("TPE1").unwrap().content().text(), Some("Simon; Garfunkel"));
+
+    let json = report.to_json();
+    assert_eq!(json["from"], "ID3v2.4");
+    assert_eq!(json["to"], "ID3v2.3");
+    assert_eq!(json["lossy"], true);
+}
+
+#[test]
+fn test_lossy_date_conversion() {
+    let mut tag = id3::Tag::with_version(Version::Id3v24);
+    tag.set_text("TDRC", "2023-06-01T12:30:15");
+
+    let report = convert_tag(&mut tag, Version::Id3v23, "/");
+    assert!(find_entry(&report, "TDRC").lossy);
+
+    let mut tag = id3::Tag::with_version(Version::Id3v24);
+    tag.set_text("TDRC", "2023");
+
+    let report = convert_tag(&mut tag, Version::Id3v23, "/");
+    assert!(!report.is_lossy());
+    assert_eq!(find_entry(&report, "TDRC").into, vec!["TYER"]);
+}
+
+#[test]
+fn test_id3v23_to_id3v24() {
+    let mut tag = id3::Tag::with_version(Version::Id3v23);
+    tag.set_text("TYER", "2023");
+    tag.set_text("TDAT", "0106");
+    tag.set_text("TORY", "1999");
+    tag.set_text("TSIZ", "12345");
+
+    let people = InvolvedPeopleList {
+        items: vec![InvolvedPeopleListItem {
+            involvement: String::from("producer"),
+            involvee: String::from("Someone"),
+        }],
+    };
+    tag.add_frame(Frame::with_content("IPLS", Content::InvolvedPeopleList(people)));
+
+    let report = convert_tag(&mut tag, Version::Id3v24, "/");
+    assert_eq!(tag.version(), Version::Id3v24);
+
+    assert_eq!(tag.get("TDRC").unwrap().content().text(), Some("2023-06-01"));
+    assert_eq!(tag.get("TDOR").unwrap().content().text(), Some("1999"));
+    assert!(tag.get("TYER").is_none());
+    assert!(tag.get("TDAT").is_none());
+    assert!(tag.get("TSIZ").is_none());
+    assert!(tag.get("IPLS").is_none());
+    assert!(tag.get("TIPL").is_some());
+
+    assert_eq!(find_entry(&report, "TYER").into, vec!["TDRC"]);
+    assert_eq!(find_entry(&report, "IPLS").into, vec!["TIPL"]);
+    assert_eq!(find_entry(&report, "TSIZ").action, Action::Dropped);
+}
+
+#[test]
+fn test_id3v24_to_id3v22() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    tag.set_text("TSOP", "Bakker, Christiaan");
+    tag.set_text("TCMP", "1");
+    tag.set_text("TMOO", "Calm");
+
+    let report = convert_tag(&mut tag, Version::Id3v22, "/");
+
+    assert!(tag.get("TSOP").is_none());
+    assert_eq!(tag.get("TSP").unwrap().content().text(), Some("Bakker, Christiaan"));
+    assert_eq!(tag.get("TCP").unwrap().content().text(), Some("1"));
+    assert!(tag.get("TMOO").is_none());
+
+    assert_eq!(find_entry(&report, "TSOP").into, vec!["TSP"]);
+    assert_eq!(find_entry(&report, "TMOO").action, Action::Dropped);
+
+    // Without the conversion, this would fail, since TSOP and TMOO have no ID3v2.2 IDs:
+    tag.write_to_path(&*song, Version::Id3v22).unwrap();
+
+    // And back again:
+    let mut tag = read_tag(&song);
+    let report = convert_tag(&mut tag, Version::Id3v24, "/");
+
+    assert_eq!(tag.get("TSOP").unwrap().content().text(), Some("Bakker, Christiaan"));
+    assert_eq!(find_entry(&report, "TSP").into, vec!["TSOP"]);
+}
@@ -0,0 +1,116 @@
+use std::fs;
+
+use id3::TagLike;
+
+use id3_json::dsf::*;
+use id3_json::input::Args;
+use id3_json::json;
+
+mod support;
+use support::fixture::Fixture;
+
+const AUDIO: &[u8] = b"pretend these are DSD samples";
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..(offset + 8)].try_into().unwrap())
+}
+
+// A DSF file with an optional ID3v2 tag at the end
+fn dsf_bytes(tag: Option<&id3::Tag>) -> Vec<u8> {
+    let mut fmt = b"fmt ".to_vec();
+    fmt.extend(52_u64.to_le_bytes());
+    fmt.extend([0; 40]);
+
+    let mut data = b"data".to_vec();
+    data.extend(((12 + AUDIO.len()) as u64).to_le_bytes());
+    data.extend(AUDIO);
+
+    let mut tag_bytes = Vec::new();
+    if let Some(tag) = tag {
+        tag.write_to(&mut tag_bytes, id3::Version::Id3v23).unwrap();
+    }
+
+    let audio_end = 28 + fmt.len() + data.len();
+    let metadata_pointer = if tag.is_some() { audio_end } else { 0 };
+
+    let mut bytes = b"DSD ".to_vec();
+    bytes.extend(28_u64.to_le_bytes());
+    bytes.extend(((audio_end + tag_bytes.len()) as u64).to_le_bytes());
+    bytes.extend((metadata_pointer as u64).to_le_bytes());
+    bytes.extend(fmt);
+    bytes.extend(data);
+    bytes.extend(tag_bytes);
+    bytes
+}
+
+#[test]
+fn test_read_dsf_tag() {
+    let mut tag = id3::Tag::new();
+    tag.set_title("Elevator Music Attempt #1");
+    tag.set_artist("Christiaan Bakker");
+
+    let song = Fixture::blank("song.dsf");
+    fs::write(&*song, dsf_bytes(Some(&tag))).unwrap();
+
+    let tag = read_tag(&song).unwrap();
+    let json = json::read_from_tag(&tag, &Args::default());
+    assert_eq!(json.get("version").unwrap(), "ID3v2.3");
+    assert_eq!(json.get("data").unwrap().get("title").unwrap(), "Elevator Music Attempt #1");
+    assert_eq!(json.get("data").unwrap().get("artist").unwrap(), "Christiaan Bakker");
+}
+
+#[test]
+fn test_write_dsf_tag() {
+    let song = Fixture::blank("song.dsf");
+    fs::write(&*song, dsf_bytes(None)).unwrap();
+    let audio_end = fs::metadata(&*song).unwrap().len();
+
+    let mut tag = read_tag(&song).unwrap();
+    assert_eq!(tag.frames().count(), 0);
+
+    tag.set_title("A title long enough to make the tag grow");
+    write_tag(&song, &tag, id3::Version::Id3v24).unwrap();
+
+    let bytes = fs::read(&*song).unwrap();
+    assert_eq!(read_u64(&bytes, 12), bytes.len() as u64);
+    assert_eq!(read_u64(&bytes, 20), audio_end);
+    assert_eq!(&bytes[(audio_end as usize)..(audio_end as usize + 3)], b"ID3");
+
+    let tag = read_tag(&song).unwrap();
+    assert_eq!(tag.title(), Some("A title long enough to make the tag grow"));
+
+    // Shrinking the tag doesn't leave the old one behind
+    let mut tag = read_tag(&song).unwrap();
+    tag.set_title("Short");
+    write_tag(&song, &tag, id3::Version::Id3v24).unwrap();
+
+    let bytes = fs::read(&*song).unwrap();
+    assert_eq!(read_u64(&bytes, 12), bytes.len() as u64);
+    assert_eq!(read_tag(&song).unwrap().title(), Some("Short"));
+
+    // Removing all frames removes the tag
+    write_tag(&song, &id3::Tag::new(), id3::Version::Id3v24).unwrap();
+
+    let bytes = fs::read(&*song).unwrap();
+    assert_eq!(bytes.len() as u64, audio_end);
+    assert_eq!(read_u64(&bytes, 12), audio_end);
+    assert_eq!(read_u64(&bytes, 20), 0);
+    assert!(bytes.ends_with(AUDIO));
+}
+
+#[test]
+fn test_invalid_dsf() {
+    let song = Fixture::blank("song.dsf");
+    let mut bytes = dsf_bytes(None);
+    bytes.truncate(bytes.len() - 5);
+    fs::write(&*song, bytes).unwrap();
+
+    assert_eq!(read_tag(&song).unwrap_err().to_string(), "Invalid DSF file: the audio data is cut off");
+
+    // A chunk size that doesn't fit in the address space
+    let mut bytes = dsf_bytes(None);
+    bytes[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&*song, bytes).unwrap();
+
+    assert_eq!(read_tag(&song).unwrap_err().to_string(), "Invalid DSF file: the \"fmt \" chunk is cut off");
+}
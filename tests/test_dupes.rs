@@ -0,0 +1,108 @@
+use id3::TagLike;
+
+use id3_json::container::Container;
+use id3_json::dupes::*;
+use id3_json::input::DupesMatch;
+
+mod support;
+use support::fixture::Fixture;
+use support::tag::read_tag;
+
+fn set_title(path: &std::path::Path, title: &str) {
+    let mut tag = read_tag(path);
+    tag.set_title(title);
+    tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+}
+
+fn read_entry(path: &std::path::Path) -> Entry {
+    Entry::read(path).unwrap().unwrap()
+}
+
+#[test]
+fn test_entry() {
+    let fixture = Fixture::copy("attempt_1_no_cover.mp3");
+    let entry = read_entry(&fixture);
+
+    assert_eq!(entry.bitrate(), Some(185));
+    assert_eq!(entry.missing_fields(), vec!["track", "year", "genre", "covers"]);
+    assert_eq!(entry.tag_completeness(), 0.43);
+
+    let json = entry.to_json();
+    assert_eq!(json.get("tags").unwrap().get("artist").unwrap(), "Christiaan Bakker");
+    assert_eq!(json.get("duration").unwrap(), 140.539);
+}
+
+#[test]
+fn test_find_groups_by_audio_and_tags() {
+    let with_cover = Fixture::copy("attempt_1.mp3");
+    let without_cover = Fixture::copy("attempt_1_no_cover.mp3");
+    let entries = vec![read_entry(&without_cover), read_entry(&with_cover)];
+
+    // Same audio and same tags, the copy with a cover goes first
+    let groups = find_groups(&entries, DupesMatch::Any);
+    assert_eq!(groups, vec![Group { entries: vec![1, 0], matched_by: vec!["audio", "tags"] }]);
+
+    let groups = find_groups(&entries, DupesMatch::Tags);
+    assert_eq!(groups, vec![Group { entries: vec![1, 0], matched_by: vec!["tags"] }]);
+
+    let json = groups_to_json(&entries, &groups);
+    let files = json[0].get("files").unwrap().as_array().unwrap();
+    assert_eq!(files[0].get("path").unwrap(), with_cover.to_str().unwrap());
+}
+
+#[test]
+fn test_skip_files_without_audio() {
+    let cover = Fixture::copy("attempt_1.jpg");
+    assert!(Entry::read(&cover).unwrap().is_none());
+
+    let notes = Fixture::blank("notes.txt");
+    std::fs::write(&*notes, "Recorded in one take").unwrap();
+    assert!(Entry::read(&notes).unwrap().is_none());
+}
+
+#[test]
+fn test_lossless_goes_first() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let lossy = read_entry(&song);
+    let lossless = Entry {
+        path:       song.with_extension("flac"),
+        container:  Container::Flac,
+        audio:      None,
+        audio_hash: None,
+        ..lossy.clone()
+    };
+
+    let groups = find_groups(&[lossy, lossless], DupesMatch::Tags);
+    assert_eq!(groups, vec![Group { entries: vec![1, 0], matched_by: vec!["tags"] }]);
+}
+
+#[test]
+fn test_find_groups_with_different_tags() {
+    let first = Fixture::copy("attempt_1.mp3");
+    let second = Fixture::copy("attempt_1_no_cover.mp3");
+    set_title(&first, "Attempt #1");
+    set_title(&second, "Something else");
+
+    let entries = vec![read_entry(&first), read_entry(&second)];
+
+    let groups = find_groups(&entries, DupesMatch::Any);
+    assert_eq!(groups, vec![Group { entries: vec![0, 1], matched_by: vec!["audio"] }]);
+    assert_eq!(find_groups(&entries, DupesMatch::Tags), vec![]);
+
+    // Punctuation and case don't matter
+    set_title(&second, "attempt 1");
+    let entries = vec![read_entry(&first), read_entry(&second)];
+    let groups = find_groups(&entries, DupesMatch::Tags);
+    assert_eq!(groups, vec![Group { entries: vec![0, 1], matched_by: vec!["tags"] }]);
+}
+
+#[test]
+fn test_collect_files() {
+    let fixture = Fixture::copy("attempt_1.mp3");
+    let dir = fixture.parent().unwrap().to_path_buf();
+    std::fs::create_dir(dir.join("nested")).unwrap();
+    std::fs::copy(&*fixture, dir.join("nested/copy.mp3")).unwrap();
+
+    let files = collect_files(&[&dir]).unwrap();
+    assert_eq!(files, vec![dir.join("attempt_1.mp3"), dir.join("nested/copy.mp3")]);
+}
@@ -0,0 +1,197 @@
+use std::fs;
+
+use base64::prelude::*;
+use id3::TagLike;
+use id3::frame::PictureType;
+
+use id3_json::flac::*;
+use id3_json::input::Args;
+use id3_json::json;
+use id3_json::vorbis;
+
+mod support;
+use support::fixture::Fixture;
+
+const AUDIO: &[u8] = b"\xFF\xF8 pretend these are audio frames";
+
+fn block(kind: u8, data: &[u8], is_last: bool) -> Vec<u8> {
+    let length = (data.len() as u32).to_be_bytes();
+    let mut bytes = vec![if is_last { kind | 0x80 } else { kind }];
+    bytes.extend(&length[1..4]);
+    bytes.extend(data);
+    bytes
+}
+
+fn flac_bytes(comments: &[(&str, &str)]) -> Vec<u8> {
+    let comments = comments.iter().
+        map(|(k, v)| (k.to_string(), v.to_string())).
+        collect::<Vec<_>>();
+
+    let mut bytes = b"fLaC".to_vec();
+    bytes.extend(block(0, &[0; 34], false));
+    bytes.extend(block(4, &vorbis::encode_comments("reference libFLAC 1.4.3", &comments), false));
+    bytes.extend(block(1, &[0; 100], true));
+    bytes.extend(AUDIO);
+    bytes
+}
+
+fn flac_fixture(comments: &[(&str, &str)]) -> Fixture {
+    let song = Fixture::blank("song.flac");
+    fs::write(&*song, flac_bytes(comments)).unwrap();
+    song
+}
+
+#[test]
+fn test_read_vorbis_comments() {
+    let song = flac_fixture(&[
+        ("TITLE", "Elevator Music Attempt #1"),
+        ("Artist", "Christiaan Bakker"),
+        ("GENRE", "Electronic"),
+        ("GENRE", "Ambient"),
+        ("TRACKNUMBER", "3"),
+        ("TOTALTRACKS", "12"),
+        ("DISCNUMBER", "1/2"),
+        ("DATE", "2011-09-07"),
+        ("ALBUM ARTIST", "Various Artists"),
+        ("COMMENT", "Attribution 3.0"),
+        ("REPLAYGAIN_TRACK_GAIN", "-6.5 dB"),
+    ]);
+
+    let tag = read_tag(&song).unwrap();
+    let json = json::read_from_tag(&tag, &Args::default());
+    let data = json.get("data").unwrap();
+
+    assert_eq!(data.get("title").unwrap(), "Elevator Music Attempt #1");
+    assert_eq!(data.get("artist").unwrap(), "Christiaan Bakker");
+    assert_eq!(data.get("genre").unwrap(), &serde_json::json!(["Electronic", "Ambient"]));
+    assert_eq!(data.get("track").unwrap(), 3);
+    assert_eq!(data.get("track_total").unwrap(), 12);
+    assert_eq!(data.get("disc").unwrap(), 1);
+    assert_eq!(data.get("disc_total").unwrap(), 2);
+    assert_eq!(data.get("date").unwrap(), "2011-09-07");
+    assert_eq!(data.get("year").unwrap(), 2011);
+    assert_eq!(data.get("album_artist").unwrap(), "Various Artists");
+    assert_eq!(data.get("comment").unwrap(), "Attribution 3.0");
+    assert_eq!(data.get("covers").unwrap(), &serde_json::json!([]));
+
+    // Anything else is kept around as a TXXX frame:
+    let extended_text = tag.extended_texts().next().unwrap();
+    assert_eq!(extended_text.description, "REPLAYGAIN_TRACK_GAIN");
+    assert_eq!(extended_text.value, "-6.5 dB");
+}
+
+#[test]
+fn test_read_nul_in_vorbis_comment() {
+    let song = flac_fixture(&[("TITLE", "a\u{0000}b"), ("ARTIST", "Christiaan Bakker\u{0000}")]);
+
+    // A NUL separates values, like it does in ID3v2.4
+    let tag = read_tag(&song).unwrap();
+    let json = json::read_from_tag(&tag, &Args::default());
+    let data = json.get("data").unwrap();
+
+    assert_eq!(data.get("title").unwrap(), &serde_json::json!(["a", "b"]));
+    assert_eq!(data.get("artist").unwrap(), "Christiaan Bakker");
+}
+
+#[test]
+fn test_write_vorbis_comments() {
+    let song = flac_fixture(&[
+        ("TITLE", "Old title"),
+        ("TOTALTRACKS", "12"),
+        ("REPLAYGAIN_TRACK_GAIN", "-6.5 dB"),
+    ]);
+    let original_size = fs::metadata(&*song).unwrap().len();
+
+    let mut tag = read_tag(&song).unwrap();
+    let input = serde_json::json!({
+        "data": {
+            "title": "New title",
+            "artist": ["Christiaan Bakker", "Someone Else"],
+            "track": 3,
+            "tagging_date": "2024-01-01",
+        }
+    });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+
+    let skipped = write_tag(&song, &tag).unwrap();
+    assert_eq!(skipped, vec!["TDTG"]);
+
+    let bytes = fs::read(&*song).unwrap();
+    assert!(bytes.ends_with(AUDIO));
+    // The tags still fit in the old padding, so the audio wasn't moved:
+    assert_eq!(bytes.len() as u64, original_size);
+
+    let tag = read_tag(&song).unwrap();
+    assert_eq!(tag.title(), Some("New title"));
+    assert_eq!(tag.artists(), Some(vec!["Christiaan Bakker", "Someone Else"]));
+    assert_eq!(tag.get("TRCK").unwrap().content().text(), Some("3/12"));
+    assert_eq!(tag.extended_texts().next().unwrap().value, "-6.5 dB");
+}
+
+#[test]
+fn test_write_pictures() {
+    let song = flac_fixture(&[("TITLE", "With a cover")]);
+    let image = fs::read(&*Fixture::copy("attempt_1.jpg")).unwrap();
+
+    let mut tag = read_tag(&song).unwrap();
+    let input = serde_json::json!({
+        "data": {
+            "covers": [{
+                "mime_type": "image/jpeg",
+                "type": "front",
+                "description": "",
+                "data": BASE64_STANDARD.encode(&image),
+            }]
+        }
+    });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+    write_tag(&song, &tag).unwrap();
+
+    // Bigger than the padding, so the file grows
+    let bytes = fs::read(&*song).unwrap();
+    assert!(bytes.len() > image.len());
+    assert!(bytes.ends_with(AUDIO));
+
+    let tag = read_tag(&song).unwrap();
+    let picture = tag.pictures().next().unwrap();
+    assert_eq!(picture.picture_type, PictureType::CoverFront);
+    assert_eq!(picture.mime_type, "image/jpeg");
+    assert_eq!(picture.data, image);
+
+    let json = json::read_from_tag(&tag, &Args::default());
+    let cover = &json.get("data").unwrap().get("covers").unwrap()[0];
+    assert_eq!(cover.get("type").unwrap(), "front");
+    assert_eq!(cover.get("size").unwrap(), image.len());
+}
+
+#[test]
+fn test_write_after_huge_padding() {
+    // Two full padding blocks are more than one block can hold, so they can't be reused
+    let mut bytes = b"fLaC".to_vec();
+    bytes.extend(block(0, &[0; 34], false));
+    bytes.extend(block(4, &vorbis::encode_comments("reference libFLAC 1.4.3", &[]), false));
+    bytes.extend(block(1, &vec![0; 0xFF_FFFF], false));
+    bytes.extend(block(1, &vec![0; 0xFF_FFFF], true));
+    bytes.extend(AUDIO);
+
+    let song = Fixture::blank("song.flac");
+    fs::write(&*song, bytes).unwrap();
+
+    let mut tag = read_tag(&song).unwrap();
+    tag.set_title("Smaller now");
+    write_tag(&song, &tag).unwrap();
+
+    let bytes = fs::read(&*song).unwrap();
+    assert!(bytes.len() < 0xFF_FFFF);
+    assert!(bytes.ends_with(AUDIO));
+    assert_eq!(read_tag(&song).unwrap().title(), Some("Smaller now"));
+}
+
+#[test]
+fn test_invalid_flac() {
+    let song = Fixture::blank("song.flac");
+    fs::write(&*song, b"fLaC\x00\x00\x00\x22").unwrap();
+
+    let error = read_tag(&song).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid FLAC file: metadata block at byte 4 is cut off");
+}
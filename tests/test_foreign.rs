@@ -0,0 +1,149 @@
+use std::fs;
+use std::io::Write;
+
+use id3::TagLike;
+
+use id3_json::foreign::*;
+
+mod support;
+use support::fixture::Fixture;
+use support::tag::read_tag;
+
+fn ape_item(key: &str, value: &[u8], flags: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend((value.len() as u32).to_le_bytes());
+    bytes.extend(flags.to_le_bytes());
+    bytes.extend(key.as_bytes());
+    bytes.push(0);
+    bytes.extend(value);
+    bytes
+}
+
+fn ape_header_or_footer(items: &[u8], item_count: u32, flags: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend(b"APETAGEX");
+    bytes.extend(2000_u32.to_le_bytes());
+    bytes.extend(((items.len() + 32) as u32).to_le_bytes());
+    bytes.extend(item_count.to_le_bytes());
+    bytes.extend(flags.to_le_bytes());
+    bytes.extend([0; 8]);
+    bytes
+}
+
+fn ape_tag() -> Vec<u8> {
+    let mut items = Vec::new();
+    items.extend(ape_item("Artist", b"Christiaan Bakker", 0));
+    items.extend(ape_item("Genre", b"Electronic\0Ambient", 0));
+    items.extend(ape_item("Cover Art (Front)", &[1, 2, 3, 4], 0b10));
+
+    let mut bytes = ape_header_or_footer(&items, 3, 0xA000_0000);
+    bytes.extend(&items);
+    bytes.extend(ape_header_or_footer(&items, 3, 0x8000_0000));
+    bytes
+}
+
+fn lyrics3v2_tag() -> Vec<u8> {
+    let mut content = b"LYRICSBEGIN".to_vec();
+    content.extend(b"IND00003110");
+    content.extend(b"EAR00017Christiaan Bakker");
+
+    let mut bytes = content.clone();
+    bytes.extend(format!("{:06}", content.len()).as_bytes());
+    bytes.extend(b"LYRICS200");
+    bytes
+}
+
+// Inserts the given bytes right before the ID3v1 tag at the end of the file
+fn insert_before_v1(path: &std::path::Path, inserted: &[u8]) {
+    let mut bytes = fs::read(path).unwrap();
+    let v1_start = bytes.len() - 128;
+    bytes.splice(v1_start..v1_start, inserted.iter().cloned());
+
+    let mut file = fs::File::create(path).unwrap();
+    file.write_all(&bytes).unwrap();
+}
+
+#[test]
+fn test_read_foreign_tags() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let file_size = fs::metadata(&*song).unwrap().len() as usize;
+    assert_eq!(read_from_path(&song).unwrap(), vec![]);
+
+    let ape = ape_tag();
+    let lyrics3 = lyrics3v2_tag();
+    insert_before_v1(&song, &[ape.clone(), lyrics3.clone()].concat());
+
+    let tags = read_from_path(&song).unwrap();
+    assert_eq!(tags.len(), 2);
+
+    assert_eq!(tags[0].offset(), file_size - 128);
+    assert_eq!(tags[0].size(), ape.len());
+    assert_eq!(tags[0], ForeignTag::Ape {
+        offset:  file_size - 128,
+        size:    ape.len(),
+        version: 2000,
+        items:   vec![
+            (String::from("Artist"), ApeValue::Text(vec![String::from("Christiaan Bakker")])),
+            (String::from("Genre"), ApeValue::Text(vec![String::from("Electronic"), String::from("Ambient")])),
+            (String::from("Cover Art (Front)"), ApeValue::Binary(4)),
+        ],
+    });
+
+    let json = tags[0].to_json();
+    assert_eq!(json.get("type").unwrap(), "APEv2");
+    assert_eq!(json.get("items").unwrap().get("Artist").unwrap(), "Christiaan Bakker");
+    assert_eq!(json.get("items").unwrap().get("Genre").unwrap(), &serde_json::json!(["Electronic", "Ambient"]));
+    assert_eq!(json.get("items").unwrap().get("Cover Art (Front)").unwrap().get("size").unwrap(), 4);
+
+    assert_eq!(tags[1].offset(), file_size - 128 + ape.len());
+    assert_eq!(tags[1].size(), lyrics3.len());
+
+    let json = tags[1].to_json();
+    assert_eq!(json.get("type").unwrap(), "Lyrics3v2");
+    assert_eq!(json.get("fields").unwrap().get("IND").unwrap(), "110");
+    assert_eq!(json.get("fields").unwrap().get("EAR").unwrap(), "Christiaan Bakker");
+}
+
+#[test]
+fn test_read_ape_footer_without_size() {
+    let song = Fixture::copy("attempt_1.mp3");
+
+    let mut footer = ape_header_or_footer(&[], 0, 0);
+    footer[12..16].copy_from_slice(&0_u32.to_le_bytes());
+    insert_before_v1(&song, &footer);
+
+    assert_eq!(read_from_path(&song).unwrap(), Vec::new());
+}
+
+#[test]
+fn test_read_without_id3v1_tag() {
+    let song = Fixture::copy("attempt_1.mp3");
+    id3::v1::Tag::remove_from_path(&*song).unwrap();
+
+    let mut file = fs::OpenOptions::new().append(true).open(&*song).unwrap();
+    file.write_all(&ape_tag()).unwrap();
+
+    let tags = read_from_path(&song).unwrap();
+    assert_eq!(tags.len(), 1);
+    assert_eq!(tags[0].to_json().get("type").unwrap(), "APEv2");
+}
+
+#[test]
+fn test_strip_foreign_tags() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let original = fs::read(&*song).unwrap();
+
+    // Lyrics3v2 first this time, the order shouldn't matter
+    insert_before_v1(&song, &[lyrics3v2_tag(), ape_tag()].concat());
+
+    let stripped = strip_from_path(&song).unwrap();
+    assert_eq!(stripped.len(), 2);
+    assert_eq!(fs::read(&*song).unwrap(), original);
+
+    // The ID3v1 and ID3v2 tags are still there
+    assert_eq!(id3::v1::Tag::read_from_path(&*song).unwrap().title, "Elevator Music Attempt 1");
+    assert_eq!(read_tag(&song).title(), Some("Elevator Music Attempt #1"));
+
+    // Nothing else to strip
+    assert_eq!(strip_from_path(&song).unwrap(), vec![]);
+}
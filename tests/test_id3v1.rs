@@ -0,0 +1,76 @@
+use id3::TagLike;
+
+use id3_json::id3v1::*;
+
+mod support;
+use support::fixture::Fixture;
+use support::tag::read_tag;
+
+#[test]
+fn test_from_v2_tag() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+    tag.set_track(3);
+    tag.set_genre("Electronic");
+    tag.set_date_recorded("2011-09-07".parse().unwrap());
+
+    let (v1_tag, warnings) = from_v2_tag(&tag);
+
+    assert_eq!(v1_tag.title, "Elevator Music Attempt #1");
+    assert_eq!(v1_tag.artist, "Christiaan Bakker");
+    assert_eq!(v1_tag.album, "Echoes From The Past");
+    assert_eq!(v1_tag.year, "2011");
+    assert_eq!(v1_tag.track, Some(3));
+    assert_eq!(v1_tag.genre_id, 52);
+
+    // ID3v1.1 only has 28 bytes for the comment, with a track number:
+    assert_eq!(v1_tag.comment, "http://www.jamendo.com Attri");
+    assert_eq!(warnings, vec!["ID3v1 fields were truncated: comment"]);
+}
+
+#[test]
+fn test_from_v2_tag_warnings() {
+    let mut tag = id3::Tag::new();
+    tag.set_title("A title that is much too long to fit in thirty bytes");
+    tag.set_genre("Chiptune");
+    tag.set_track(1000);
+
+    let (v1_tag, warnings) = from_v2_tag(&tag);
+
+    assert_eq!(v1_tag.title, "A title that is much too long ");
+    assert_eq!(v1_tag.track, None);
+    assert_eq!(v1_tag.genre_id, 255);
+    assert_eq!(warnings, vec![
+        "ID3v1 fields were truncated: title",
+        "The track number doesn't fit in ID3v1, it needs to be under 256",
+        "The ID3v1 genre list has no entry for \"Chiptune\"",
+    ]);
+}
+
+#[test]
+fn test_write_and_strip() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let file_size = std::fs::metadata(&*song).unwrap().len();
+    let mut tag = read_tag(&song);
+    tag.set_title("New title");
+    tag.set_track(7);
+
+    let (v1_tag, _) = from_v2_tag(&tag);
+    write_to_path(&song, &v1_tag).unwrap();
+
+    // The old tag is replaced, not appended to:
+    assert_eq!(std::fs::metadata(&*song).unwrap().len(), file_size);
+
+    let written = id3::v1::Tag::read_from_path(&*song).unwrap();
+    assert_eq!(written.title, "New title");
+    assert_eq!(written.track, Some(7));
+    assert!(is_present(&song).unwrap());
+
+    // The ID3v2 tag is still there:
+    assert_eq!(read_tag(&song).title(), Some("Elevator Music Attempt #1"));
+
+    assert!(strip_from_path(&song).unwrap());
+    assert!(!is_present(&song).unwrap());
+    assert_eq!(std::fs::metadata(&*song).unwrap().len(), file_size - 128);
+    assert_eq!(read_tag(&song).title(), Some("Elevator Music Attempt #1"));
+}
@@ -35,6 +35,72 @@ fn test_basic_inputs() {
         ..Args::default()
     });
 
+    let args = parse_args(&["id3-json", "filename.mp3", "--join-values", "--value-separator", "; "]).unwrap();
+    assert_eq!(args, Args {
+        filename:        PathBuf::from("filename.mp3"),
+        read:            true,
+        join_values:     true,
+        value_separator: Some(String::from("; ")),
+        ..Args::default()
+    });
+
+    let args = parse_args(&["id3-json", "filename.mp3", "--v1", "sync"]).unwrap();
+    assert_eq!(args, Args {
+        filename: PathBuf::from("filename.mp3"),
+        read:     true,
+        v1:       Some(V1Mode::Sync),
+        ..Args::default()
+    });
+
+    let args = parse_args(&["id3-json", "-w", "filename.mp3", "--strip-foreign-tags"]).unwrap();
+    assert_eq!(args, Args {
+        filename:           PathBuf::from("filename.mp3"),
+        write:              true,
+        strip_foreign_tags: true,
+        ..Args::default()
+    });
+
+    let args = parse_args(&["id3-json", "filename.mp3", "--audio", "--verify-audio"]).unwrap();
+    assert_eq!(args, Args {
+        filename:     PathBuf::from("filename.mp3"),
+        read:         true,
+        audio:        true,
+        verify_audio: true,
+        ..Args::default()
+    });
+
+    let args = parse_args(&["id3-json", "filename.mp3", "--rating-scale", "Foobar"]).unwrap();
+    assert_eq!(args, Args {
+        filename:     PathBuf::from("filename.mp3"),
+        read:         true,
+        rating_scale: RatingScale::Foobar,
+        ..Args::default()
+    });
+
+    let args = parse_args(&[
+        "id3-json", "episode.mp3",
+        "--import-chapters", "chapters.txt",
+        "--export-chapters", "chapters.json",
+        "--chapters-format", "Podlove",
+    ]).unwrap();
+    assert_eq!(args, Args {
+        filename:        PathBuf::from("episode.mp3"),
+        read:            true,
+        import_chapters: Some(PathBuf::from("chapters.txt")),
+        export_chapters: Some(PathBuf::from("chapters.json")),
+        chapters_format: Some(ChapterFormat::Podlove),
+        ..Args::default()
+    });
+
+    let args = parse_args(&["id3-json", "--replaygain", "album", "01.mp3", "02.mp3", "03.mp3"]).unwrap();
+    assert_eq!(args, Args {
+        filename:        PathBuf::from("01.mp3"),
+        read:            true,
+        replaygain:      Some(ReplayGainMode::Album),
+        album_filenames: vec![PathBuf::from("02.mp3"), PathBuf::from("03.mp3")],
+        ..Args::default()
+    });
+
     let args = parse_args(&[
         "id3-json", "-w", "filename.mp3",
         "-i", "some/path.json",
@@ -62,4 +128,43 @@ fn test_invalid_inputs() {
     let args = parse_args(&["id3-json", "filename.mp3", "--tag-version", "foobar"]);
     assert!(args.is_err());
     assert_eq!(format!("{}", args.unwrap_err()), "Unsupported ID3 version: \"foobar\". Expected ID3v2.{2,3,4}");
+
+    let args = parse_args(&["id3-json", "filename.mp3", "--v1", "foobar"]);
+    assert!(args.is_err());
+    assert_eq!(format!("{}", args.unwrap_err()), "Unsupported ID3v1 mode: \"foobar\". Expected write, sync, or strip");
+
+    let args = parse_args(&["id3-json", "filename.mp3", "--replaygain", "foobar"]);
+    assert_eq!(format!("{}", args.unwrap_err()), "Unsupported ReplayGain mode: \"foobar\". Expected track or album");
+
+    let args = parse_args(&["id3-json", "filename.mp3", "--rating-scale", "itunes"]);
+    assert_eq!(format!("{}", args.unwrap_err()), "Unsupported rating scale: \"itunes\". Expected wmp or foobar");
+
+    let args = parse_args(&["id3-json", "filename.mp3", "--chapters-format", "cue"]);
+    assert_eq!(format!("{}", args.unwrap_err()), "Unsupported chapter format: \"cue\". Expected ffmetadata, podlove, or text");
+
+    let args = parse_args(&["id3-json", "--replaygain", "track", "01.mp3", "02.mp3"]);
+    assert_eq!(format!("{}", args.unwrap_err()), "Multiple files are only supported with --replaygain album");
+}
+
+#[test]
+fn test_dupes_inputs() {
+    let args = parse_dupes_args(&["id3-json", "music/", "other.mp3"]).unwrap();
+    assert_eq!(args, DupesArgs {
+        paths: vec![PathBuf::from("music/"), PathBuf::from("other.mp3")],
+        by:    DupesMatch::Any,
+        ..DupesArgs::default()
+    });
+
+    let args = parse_dupes_args(&["id3-json", "--by", "audio", "music/", "-o", "dupes.json"]).unwrap();
+    assert_eq!(args, DupesArgs {
+        paths:    vec![PathBuf::from("music/")],
+        by:       DupesMatch::Audio,
+        out_json: Some(PathBuf::from("dupes.json")),
+    });
+
+    let args = parse_dupes_args(&["id3-json"]);
+    assert_eq!(format!("{}", args.unwrap_err()), "Missing <path>");
+
+    let args = parse_dupes_args(&["id3-json", "music/", "--by", "size"]);
+    assert_eq!(format!("{}", args.unwrap_err()), "Unsupported match: \"size\". Expected tags, audio, or any");
 }
@@ -4,7 +4,7 @@ use serde_json::json;
 use base64::prelude::*;
 
 use id3_json::json::*;
-use id3_json::input::Args;
+use id3_json::input::{Args, RatingScale};
 
 mod support;
 use support::fixture::Fixture;
@@ -33,7 +33,7 @@ fn test_partial_write_to_tag() {
         "comment": "New comment",
     }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     let json = read_from_tag(&tag, &args);
 
     assert_eq!(json.get("data").unwrap().get("title").unwrap(), "New title");
@@ -54,7 +54,7 @@ fn test_partial_write_to_tag_with_nested_fields() {
         }
     }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     let json = read_from_tag(&tag, &args);
 
     assert_eq!(json.get("data").unwrap().get("title").unwrap(), "New title");
@@ -78,7 +78,7 @@ fn test_full_write_to_tag() {
         "comment": "No comment",
     }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     let json = read_from_tag(&tag, &args);
 
     assert_eq!(json.get("data").unwrap().get("title").unwrap(), "New title");
@@ -101,7 +101,7 @@ fn test_tag_removal() {
         "comment": None::<String>,
     }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     let json = read_from_tag(&tag, &args);
 
     assert_eq!(json.get("data").unwrap().get("title").unwrap(), &serde_json::Value::Null);
@@ -149,7 +149,7 @@ fn test_multiple_comments_1() {
     // Update "" comment:
     let new_data = json!({ "comment": "updated value2" }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     assert_eq!(tag.comments().count(), 2);
 
     let json = read_from_tag(&tag, &args);
@@ -158,7 +158,7 @@ fn test_multiple_comments_1() {
     // Remove "" comment, check that the other is still there:
     let new_data = json!({ "comment": None::<String> }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     assert_eq!(tag.comments().count(), 1);
 
     let json = read_from_tag(&tag, &args);
@@ -177,7 +177,7 @@ fn test_nul_byte_at_the_end_of_comment() {
         "comment": "New comment\u{0000}",
     }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     let json = read_from_tag(&tag, &args);
 
     assert_eq!(json.get("data").unwrap().get("comment").unwrap(), "New comment");
@@ -205,7 +205,7 @@ fn test_multiple_comments_2() {
     // Add new "" comment:
     let new_data = json!({ "comment": "value2" }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     assert_eq!(tag.comments().count(), 2);
 
     let json = read_from_tag(&tag, &args);
@@ -232,13 +232,13 @@ fn test_date_from_id3v24_tag() {
     let json = read_from_tag(&tag, &args);
 
     assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2023-06");
-    assert_eq!(json.get("data").unwrap().get("year"), None);
+    assert_eq!(json.get("data").unwrap().get("year").unwrap(), 2023);
 }
 
 #[test]
 fn test_year_from_id3v23_tag() {
     let args = Args::default();
-    let song = Fixture::copy("10. the masochism tango.mp3");
+    let song = Fixture::copy("attempt_1.mp3");
     let tag = read_tag(&song);
     tag.write_to_path(&*song, id3::Version::Id3v23).unwrap();
 
@@ -249,15 +249,152 @@ fn test_year_from_id3v23_tag() {
     assert_eq!(json.get("data").unwrap().get("year").unwrap(), &serde_json::Value::Null);
 
     let new_data = json!({ "year": "2023" }).as_object().unwrap().clone();
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     let json = read_from_tag(&tag, &args);
     assert_eq!(json.get("data").unwrap().get("year").unwrap(), 2023);
 
-    // Writing a date doesn't work:
+    // Writing a date works, too:
     let new_data = json!({ "date": "2023-06-01" }).as_object().unwrap().clone();
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2023-06-01");
+
+    // Writing back what was read, with an edited date, doesn't bring back the old year:
+    let mut read_data = read_from_tag(&tag, &Args { with_covers: true, ..Args::default() }).
+        get("data").unwrap().as_object().unwrap().clone();
+    read_data.insert(String::from("date"), json!("2024-06-01"));
+    write_to_tag(&read_data, &mut tag, &Args::default()).unwrap();
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2024-06-01");
+    assert_eq!(json.get("data").unwrap().get("year").unwrap(), 2024);
+
+    // And an edited year still changes the date:
+    let mut read_data = read_from_tag(&tag, &Args { with_covers: true, ..Args::default() }).
+        get("data").unwrap().as_object().unwrap().clone();
+    read_data.insert(String::from("year"), json!(2025));
+    write_to_tag(&read_data, &mut tag, &Args::default()).unwrap();
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2025-06-01");
+}
+
+#[test]
+fn test_date_in_id3v23_tag() {
+    use id3::TagLike;
+
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let tag = read_tag(&song);
+    tag.write_to_path(&*song, id3::Version::Id3v23).unwrap();
+
+    let mut tag = read_tag(&song);
+    let v23_args = Args { tag_version: Some(id3::Version::Id3v23), ..Args::default() };
+
+    // The date is split into TYER, TDAT and TIME:
+    let new_data = json!({ "date": "2023-06-01T12:30" }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &v23_args).unwrap();
+    assert_eq!(tag.get("TYER").unwrap().content().text(), Some("2023"));
+    assert_eq!(tag.get("TDAT").unwrap().content().text(), Some("0106"));
+    assert_eq!(tag.get("TIME").unwrap().content().text(), Some("1230"));
+    assert!(tag.get("TDRC").is_none());
+
+    tag.write_to_path(&*song, id3::Version::Id3v23).unwrap();
+    let mut tag = read_tag(&song);
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2023-06-01T12:30");
+    assert_eq!(json.get("data").unwrap().get("year").unwrap(), 2023);
+
+    // Writing the year keeps the rest of the date:
+    let new_data = json!({ "year": 2024 }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &v23_args).unwrap();
     let json = read_from_tag(&tag, &args);
-    assert_eq!(json.get("data").unwrap().get("date"), None);
+    assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2024-06-01T12:30");
+
+    // Writing as ID3v2.4 uses TDRC instead:
+    let v24_args = Args { tag_version: Some(id3::Version::Id3v24), ..Args::default() };
+    let new_data = json!({ "date": "2022-12-12" }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &v24_args).unwrap();
+    assert_eq!(tag.get("TDRC").unwrap().content().text(), Some("2022-12-12"));
+    assert!(tag.get("TYER").is_none());
+    assert!(tag.get("TDAT").is_none());
+
+    let new_data = json!({ "year": None::<u32> }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &v24_args).unwrap();
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("date").unwrap(), &serde_json::Value::Null);
+    assert_eq!(json.get("data").unwrap().get("year").unwrap(), &serde_json::Value::Null);
+}
+
+#[test]
+fn test_separate_dates() {
+    use id3::TagLike;
+
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let new_data = json!({
+        "recording_date": "2009-03-10",
+        "release_date": "2009-05",
+        "original_date": "1999-01-02",
+        "tagging_date": "2023-06-01T12:30:15",
+    }).as_object().unwrap().clone();
+
+    let v24_args = Args { tag_version: Some(id3::Version::Id3v24), ..Args::default() };
+    write_to_tag(&new_data, &mut tag, &v24_args).unwrap();
+    assert_eq!(tag.get("TDRC").unwrap().content().text(), Some("2009-03-10"));
+    assert_eq!(tag.get("TDRL").unwrap().content().text(), Some("2009-05"));
+    assert_eq!(tag.get("TDOR").unwrap().content().text(), Some("1999-01-02"));
+    assert_eq!(tag.get("TDTG").unwrap().content().text(), Some("2023-06-01T12:30:15"));
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2009-03-10");
+    assert_eq!(json.get("data").unwrap().get("recording_date").unwrap(), "2009-03-10");
+    assert_eq!(json.get("data").unwrap().get("release_date").unwrap(), "2009-05");
+    assert_eq!(json.get("data").unwrap().get("original_date").unwrap(), "1999-01-02");
+    assert_eq!(json.get("data").unwrap().get("tagging_date").unwrap(), "2023-06-01T12:30:15");
+
+    // Writing back what was read, with only one of the two recording dates changed:
+    for key in ["date", "recording_date"] {
+        let json = read_from_tag(&tag, &args);
+        let mut read_data = json!({
+            "date":           json.get("data").unwrap().get("date").unwrap(),
+            "recording_date": json.get("data").unwrap().get("recording_date").unwrap(),
+        }).as_object().unwrap().clone();
+        read_data.insert(String::from(key), json!("2010-04-11"));
+
+        let mut tag = tag.clone();
+        write_to_tag(&read_data, &mut tag, &v24_args).unwrap();
+        let json = read_from_tag(&tag, &args);
+        assert_eq!(json.get("data").unwrap().get("date").unwrap(), "2010-04-11");
+        assert_eq!(json.get("data").unwrap().get("recording_date").unwrap(), "2010-04-11");
+    }
+
+    let conflicting_data = json!({ "date": "2010-04-11", "recording_date": "2011-05-12" }).as_object().unwrap().clone();
+    assert!(write_to_tag(&conflicting_data, &mut tag.clone(), &v24_args).is_err());
+
+    // ID3v2.3 only has a year for the original release:
+    let v23_args = Args { tag_version: Some(id3::Version::Id3v23), ..Args::default() };
+    write_to_tag(&new_data, &mut tag, &v23_args).unwrap();
+    assert!(tag.get("TDOR").is_none());
+    assert_eq!(tag.get("TORY").unwrap().content().text(), Some("1999"));
+    assert_eq!(tag.get("TYER").unwrap().content().text(), Some("2009"));
+    assert_eq!(tag.get("TDAT").unwrap().content().text(), Some("1003"));
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("original_date").unwrap(), "1999");
+    assert_eq!(json.get("data").unwrap().get("release_date").unwrap(), "2009-05");
+
+    // Invalid timestamps are rejected:
+    for invalid_date in ["2009-13-01", "2009-3-1", "March 2009"] {
+        let new_data = json!({ "release_date": invalid_date }).as_object().unwrap().clone();
+        assert!(write_to_tag(&new_data, &mut tag, &args).is_err());
+    }
+
+    let new_data = json!({ "release_date": None::<String> }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    assert!(tag.get("TDRL").is_none());
 }
 
 #[test]
@@ -281,7 +418,7 @@ fn test_reading_and_writing_cover_images() {
         }],
     }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     assert_eq!(tag.pictures().count(), 1);
 
     // Reading info without --with-covers: has size
@@ -313,7 +450,520 @@ fn test_reading_and_writing_cover_images() {
         }],
     }).as_object().unwrap().clone();
 
-    write_to_tag(&new_data, &mut tag, None).unwrap();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
     assert_eq!(tag.pictures().count(), 3);
     assert_eq!(tag.pictures().nth(2).unwrap().description, "Some description");
 }
+
+#[test]
+fn test_track_and_disc_totals() {
+    use id3::TagLike;
+
+    let args = Args::default();
+    let mut tag = id3::Tag::new();
+    tag.set_text("TRCK", "3/12");
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("track").unwrap(), 3);
+    assert_eq!(json.get("data").unwrap().get("track_total").unwrap(), 12);
+    assert_eq!(json.get("data").unwrap().get("disc").unwrap(), &serde_json::Value::Null);
+    assert_eq!(json.get("data").unwrap().get("disc_total").unwrap(), &serde_json::Value::Null);
+
+    // Writing one half keeps the other one:
+    let new_data = json!({ "track": 4, "disc_total": 2 }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
+    assert_eq!(tag.get("TRCK").unwrap().content().text(), Some("4/12"));
+    assert_eq!(tag.get("TPOS").unwrap().content().text(), Some("/2"));
+
+    let new_data = json!({ "disc": "1", "track_total": None::<u32> }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
+    assert_eq!(tag.get("TRCK").unwrap().content().text(), Some("4"));
+    assert_eq!(tag.get("TPOS").unwrap().content().text(), Some("1/2"));
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("track").unwrap(), 4);
+    assert_eq!(json.get("data").unwrap().get("track_total").unwrap(), &serde_json::Value::Null);
+    assert_eq!(json.get("data").unwrap().get("disc").unwrap(), 1);
+    assert_eq!(json.get("data").unwrap().get("disc_total").unwrap(), 2);
+
+    // Removing both halves removes the frame:
+    let new_data = json!({ "track": None::<u32> }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
+    assert!(tag.get("TRCK").is_none());
+}
+
+#[test]
+fn test_common_text_frames() {
+    use id3::TagLike;
+
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("album_artist").unwrap(), &serde_json::Value::Null);
+    assert_eq!(json.get("data").unwrap().get("compilation").unwrap(), &serde_json::Value::Null);
+
+    let new_data = json!({
+        "album_artist": "Various Artists",
+        "composer": "Christiaan Bakker",
+        "publisher": "Jamendo",
+        "isrc": "USRC17607839",
+        "bpm": "120",
+        "compilation": true,
+    }).as_object().unwrap().clone();
+
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
+    assert_eq!(tag.get("TPE2").unwrap().content().text(), Some("Various Artists"));
+    assert_eq!(tag.get("TCMP").unwrap().content().text(), Some("1"));
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("album_artist").unwrap(), "Various Artists");
+    assert_eq!(json.get("data").unwrap().get("composer").unwrap(), "Christiaan Bakker");
+    assert_eq!(json.get("data").unwrap().get("publisher").unwrap(), "Jamendo");
+    assert_eq!(json.get("data").unwrap().get("isrc").unwrap(), "USRC17607839");
+    assert_eq!(json.get("data").unwrap().get("bpm").unwrap(), 120);
+    assert_eq!(json.get("data").unwrap().get("compilation").unwrap(), true);
+
+    let new_data = json!({
+        "album_artist": None::<String>,
+        "bpm": None::<u32>,
+        "compilation": false,
+    }).as_object().unwrap().clone();
+
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("album_artist").unwrap(), &serde_json::Value::Null);
+    assert_eq!(json.get("data").unwrap().get("composer").unwrap(), "Christiaan Bakker");
+    assert_eq!(json.get("data").unwrap().get("bpm").unwrap(), &serde_json::Value::Null);
+    assert_eq!(json.get("data").unwrap().get("compilation").unwrap(), false);
+
+    let new_data = json!({ "compilation": "yes" }).as_object().unwrap().clone();
+    assert!(write_to_tag(&new_data, &mut tag, &Args::default()).is_err());
+}
+
+#[test]
+fn test_sort_order_frames() {
+    use id3::TagLike;
+
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let new_data = json!({
+        "artist_sort": "Bakker, Christiaan",
+        "album_artist_sort": "Bakker, Christiaan",
+        "composer_sort": "Bakker, Christiaan",
+        "title_sort": "Elevator Music Attempt 1",
+    }).as_object().unwrap().clone();
+
+    write_to_tag(&new_data, &mut tag, &Args { tag_version: Some(id3::Version::Id3v23), ..Args::default() }).unwrap();
+    assert_eq!(tag.get("TSOP").unwrap().content().text(), Some("Bakker, Christiaan"));
+    assert_eq!(tag.get("TSO2").unwrap().content().text(), Some("Bakker, Christiaan"));
+    assert_eq!(tag.get("TSOC").unwrap().content().text(), Some("Bakker, Christiaan"));
+
+    // Non-standard frames survive a round-trip through an ID3v2.3 file:
+    tag.write_to_path(&*song, id3::Version::Id3v23).unwrap();
+    let mut tag = read_tag(&song);
+    let json = read_from_tag(&tag, &args);
+
+    assert_eq!(json.get("data").unwrap().get("artist_sort").unwrap(), "Bakker, Christiaan");
+    assert_eq!(json.get("data").unwrap().get("album_artist_sort").unwrap(), "Bakker, Christiaan");
+    assert_eq!(json.get("data").unwrap().get("composer_sort").unwrap(), "Bakker, Christiaan");
+    assert_eq!(json.get("data").unwrap().get("title_sort").unwrap(), "Elevator Music Attempt 1");
+    assert_eq!(json.get("data").unwrap().get("album_sort").unwrap(), &serde_json::Value::Null);
+
+    // ID3v2.2 uses the iTunes three-letter IDs:
+    let new_data = json!({ "artist_sort": "Christiaan Bakker" }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &Args { tag_version: Some(id3::Version::Id3v22), ..Args::default() }).unwrap();
+    assert!(tag.get("TSOP").is_none());
+    assert_eq!(tag.get("TSP").unwrap().content().text(), Some("Christiaan Bakker"));
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("artist_sort").unwrap(), "Christiaan Bakker");
+
+    let new_data = json!({ "artist_sort": None::<String> }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &Args::default()).unwrap();
+    assert!(tag.get("TSP").is_none());
+}
+
+#[test]
+fn test_multiple_text_values() {
+    use id3::TagLike;
+
+    let args = Args::default();
+    let mut tag = id3::Tag::with_version(id3::Version::Id3v24);
+    tag.set_text_values("TPE1", ["Simon", "Garfunkel"]);
+    tag.set_text("TCON", "Folk");
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("artist").unwrap(), &json!(["Simon", "Garfunkel"]));
+    assert_eq!(json.get("data").unwrap().get("genre").unwrap(), "Folk");
+
+    let join_args = Args { join_values: true, value_separator: Some(String::from("; ")), ..Args::default() };
+    let json = read_from_tag(&tag, &join_args);
+    assert_eq!(json.get("data").unwrap().get("artist").unwrap(), "Simon; Garfunkel");
+
+    // ID3v2.4 stores arrays as NUL-separated values:
+    let new_data = json!({ "genre": ["Folk", "Rock"] }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    assert_eq!(tag.get("TCON").unwrap().content().text(), Some("Folk\u{0000}Rock"));
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("genre").unwrap(), &json!(["Folk", "Rock"]));
+
+    // Older versions join them with a separator:
+    let new_data = json!({ "composer": ["Paul Simon", "Art Garfunkel"] }).as_object().unwrap().clone();
+    let v23_args = Args { tag_version: Some(id3::Version::Id3v23), ..Args::default() };
+    write_to_tag(&new_data, &mut tag, &v23_args).unwrap();
+    assert_eq!(tag.get("TCOM").unwrap().content().text(), Some("Paul Simon/Art Garfunkel"));
+
+    let v23_args = Args { value_separator: Some(String::from(", ")), ..v23_args };
+    write_to_tag(&new_data, &mut tag, &v23_args).unwrap();
+    assert_eq!(tag.get("TCOM").unwrap().content().text(), Some("Paul Simon, Art Garfunkel"));
+
+    let new_data = json!({ "artist": ["Simon", 2] }).as_object().unwrap().clone();
+    assert!(write_to_tag(&new_data, &mut tag, &args).is_err());
+
+    // NUL is the separator itself, so it can't be part of a value
+    let v24_args = Args { tag_version: Some(id3::Version::Id3v24), ..Args::default() };
+    for new_data in [json!({ "title": "a\u{0000}b" }), json!({ "artist": ["Simon", "Gar\u{0000}funkel"] })] {
+        let error = write_to_tag(new_data.as_object().unwrap(), &mut tag, &v24_args).unwrap_err();
+        assert!(error.to_string().contains("can't contain NUL characters"));
+    }
+    assert_eq!(tag.get("TPE1").unwrap().content().text(), Some("Simon\u{0000}Garfunkel"));
+}
+
+#[test]
+fn test_genre_references() {
+    use id3::TagLike;
+
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    // The fixture has "(255)", meaning "no genre":
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("genre").unwrap(), &serde_json::Value::Null);
+
+    let raw_args = Args { raw_genres: true, ..Args::default() };
+    let json = read_from_tag(&tag, &raw_args);
+    assert_eq!(json.get("data").unwrap().get("genre").unwrap(), "(255)");
+
+    tag.set_text("TCON", "(17)Rock");
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("genre").unwrap(), "Rock");
+
+    tag.set_text("TCON", "(52)(RX)");
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("genre").unwrap(), &json!(["Electronic", "Remix"]));
+
+    // Numeric references on write:
+    let new_data = json!({ "genre": ["Rock", "electronic", "Chiptune"] }).as_object().unwrap().clone();
+
+    let numeric_args = Args { numeric_genres: true, ..Args::default() };
+    write_to_tag(&new_data, &mut tag, &numeric_args).unwrap();
+    assert_eq!(tag.get("TCON").unwrap().content().text(), Some("17\u{0000}52\u{0000}Chiptune"));
+
+    let numeric_args = Args { tag_version: Some(id3::Version::Id3v23), ..numeric_args };
+    write_to_tag(&new_data, &mut tag, &numeric_args).unwrap();
+    assert_eq!(tag.get("TCON").unwrap().content().text(), Some("(17)(52)Chiptune"));
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("genre").unwrap(), &json!(["Rock", "Electronic", "Chiptune"]));
+}
+
+#[test]
+fn test_read_from_v1_tag() {
+    let song = Fixture::copy("attempt_1.mp3");
+    let v1_tag = id3::v1::Tag::read_from_path(&*song).unwrap();
+    let json = read_from_v1_tag(&v1_tag);
+
+    assert_eq!(json.get("version").unwrap(), "ID3v1");
+    assert_eq!(json.get("title").unwrap(), "Elevator Music Attempt 1");
+    assert_eq!(json.get("artist").unwrap(), "Christiaan Bakker");
+    assert_eq!(json.get("album").unwrap(), "Echoes From The Past");
+    assert_eq!(json.get("year").unwrap(), 2011);
+    assert_eq!(json.get("comment").unwrap(), "Attribution 3.0");
+    assert_eq!(json.get("track").unwrap(), &serde_json::Value::Null);
+    assert_eq!(json.get("genre").unwrap(), &serde_json::Value::Null);
+
+    let v1_tag = id3::v1::Tag { track: Some(3), genre_id: 17, ..v1_tag };
+    let json = read_from_v1_tag(&v1_tag);
+
+    assert_eq!(json.get("version").unwrap(), "ID3v1.1");
+    assert_eq!(json.get("track").unwrap(), 3);
+    assert_eq!(json.get("genre").unwrap(), "Rock");
+}
+
+#[test]
+fn test_volume_adjustments_and_equalizations() {
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("volume_adjustments").unwrap(), &json!([]));
+    assert_eq!(json.get("data").unwrap().get("equalizations").unwrap(), &json!([]));
+
+    let new_data = json!({
+        "volume_adjustments": [
+            {
+                "identification": "track",
+                "channels": [
+                    { "type": "master", "adjustment": -4.5, "peak": 0.75 },
+                    { "type": "subwoofer", "adjustment": 2.0 },
+                ],
+            },
+            { "identification": "album", "channels": [{ "adjustment": -3.0, "peak": null }] },
+        ],
+        "equalizations": [
+            {
+                "identification": "loudness",
+                "interpolation": "band",
+                "points": [{ "frequency": 60, "adjustment": 3 }, { "frequency": 10000.5, "adjustment": 1.5 }],
+            },
+        ],
+    }).as_object().unwrap().clone();
+
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    tag.write_to_path(&*song, id3::Version::Id3v24).unwrap();
+
+    let json = read_from_tag(&read_tag(&song), &args);
+    assert_eq!(json.get("data").unwrap().get("volume_adjustments").unwrap(), &json!([
+        {
+            "identification": "track",
+            "channels": [
+                { "type": "master", "adjustment": -4.5, "peak": 0.75 },
+                { "type": "subwoofer", "adjustment": 2.0, "peak": null },
+            ],
+        },
+        { "identification": "album", "channels": [{ "type": "master", "adjustment": -3.0, "peak": null }] },
+    ]));
+    assert_eq!(json.get("data").unwrap().get("equalizations").unwrap(), &json!([
+        {
+            "identification": "loudness",
+            "interpolation": "band",
+            "points": [{ "frequency": 60.0, "adjustment": 3.0 }, { "frequency": 10000.5, "adjustment": 1.5 }],
+        },
+    ]));
+
+    let new_data = json!({ "volume_adjustments": [], "equalizations": null }).as_object().unwrap().clone();
+    assert!(write_to_tag(&new_data, &mut tag, &args).is_err());
+
+    let new_data = json!({ "volume_adjustments": [{ "channels": [{ "type": "left" }] }] }).as_object().unwrap().clone();
+    assert!(write_to_tag(&new_data, &mut tag, &args).is_err());
+
+    let new_data = json!({ "volume_adjustments": [], "equalizations": [] }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    assert_eq!(tag.frames().filter(|f| f.id() == "RVA2" || f.id() == "EQU2").count(), 0);
+}
+
+#[test]
+fn test_ratings_and_play_count() {
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("ratings").unwrap(), &json!({}));
+    assert_eq!(json.get("data").unwrap().get("play_count").unwrap(), &json!(null));
+
+    let new_data = json!({
+        "ratings": {
+            "me@example.com":    { "rating": 255, "counter": 12 },
+            "other@example.com": { "stars": 4 },
+        },
+        "play_count": 31,
+    }).as_object().unwrap().clone();
+
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    tag.write_to_path(&*song, id3::Version::Id3v24).unwrap();
+    let mut tag = read_tag(&song);
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("ratings").unwrap(), &json!({
+        "me@example.com":    { "rating": 255, "stars": 5, "counter": 12 },
+        "other@example.com": { "rating": 196, "stars": 4, "counter": 0 },
+    }));
+    assert_eq!(json.get("data").unwrap().get("play_count").unwrap(), 31);
+
+    // The same ratings, as foobar2000 would show them
+    let foobar_args = Args { rating_scale: RatingScale::Foobar, ..Args::default() };
+    let json = read_from_tag(&tag, &foobar_args);
+    assert_eq!(json.get("data").unwrap().get("ratings").unwrap().get("other@example.com").unwrap(), &json!({
+        "rating": 196, "stars": 4, "counter": 0,
+    }));
+
+    // Only the given users change, and the counter is kept if it's missing
+    let new_data = json!({
+        "ratings": {
+            "me@example.com":    { "stars": 2 },
+            "other@example.com": null,
+        },
+    }).as_object().unwrap().clone();
+
+    write_to_tag(&new_data, &mut tag, &foobar_args).unwrap();
+    let json = read_from_tag(&tag, &foobar_args);
+    assert_eq!(json.get("data").unwrap().get("ratings").unwrap(), &json!({
+        "me@example.com": { "rating": 102, "stars": 2, "counter": 12 },
+    }));
+    assert_eq!(json.get("data").unwrap().get("play_count").unwrap(), 31);
+
+    // Editing only the stars of what was read changes the rating
+    let mut read_data = json!({ "ratings": read_from_tag(&tag, &args).get("data").unwrap().get("ratings").unwrap() }).
+        as_object().unwrap().clone();
+    read_data["ratings"]["me@example.com"]["stars"] = json!(5);
+
+    write_to_tag(&read_data, &mut tag, &args).unwrap();
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("ratings").unwrap(), &json!({
+        "me@example.com": { "rating": 255, "stars": 5, "counter": 12 },
+    }));
+
+    // And writing it back unchanged keeps a rating that's between two stars
+    let new_data = json!({ "ratings": { "me@example.com": { "rating": 100, "stars": 3 } } }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("ratings").unwrap().get("me@example.com").unwrap().get("rating").unwrap(), 100);
+
+    let new_data = json!({ "ratings": { "me@example.com": { "stars": 6 } } }).as_object().unwrap().clone();
+    assert!(write_to_tag(&new_data, &mut tag, &args).is_err());
+
+    let new_data = json!({ "ratings": { "me@example.com": { "rating": 256 } } }).as_object().unwrap().clone();
+    assert!(write_to_tag(&new_data, &mut tag, &args).is_err());
+
+    let new_data = json!({ "ratings": [] }).as_object().unwrap().clone();
+    assert!(write_to_tag(&new_data, &mut tag, &args).is_err());
+
+    // Play counts can go past 32 bits, and still be written back as they were read
+    let new_data = json!({ "play_count": 5_000_000_000_u64 }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    let read_data = json!({ "play_count": read_from_tag(&tag, &args)["data"]["play_count"] }).as_object().unwrap().clone();
+    assert_eq!(read_data["play_count"], 5_000_000_000_u64);
+    write_to_tag(&read_data, &mut tag, &args).unwrap();
+    assert_eq!(read_from_tag(&tag, &args)["data"]["play_count"], 5_000_000_000_u64);
+
+    let new_data = json!({ "ratings": null, "play_count": null }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("ratings").unwrap(), &json!({}));
+    assert_eq!(json.get("data").unwrap().get("play_count").unwrap(), &json!(null));
+}
+
+#[test]
+fn test_chapters() {
+    let args = Args::default();
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("chapters").unwrap(), &json!([]));
+    assert_eq!(json.get("data").unwrap().get("tables_of_contents").unwrap(), &json!([]));
+
+    let image = BASE64_STANDARD.encode(b"not really a png");
+    let new_data = json!({
+        "chapters": [
+            { "id": "intro", "start_time": 0, "end_time": 60000, "title": "Intro" },
+            {
+                "id": "news",
+                "start_time": 60000,
+                "end_time": 140539,
+                "start_offset": 1200000,
+                "title": "News",
+                "url": "https://example.com/news",
+                "image": { "mime_type": "image/png", "type": "other", "description": "Headlines", "data": image },
+            },
+        ],
+        "tables_of_contents": [
+            { "id": "toc", "top_level": true, "ordered": true, "elements": ["intro", "news"], "title": "Episode" },
+        ],
+    }).as_object().unwrap().clone();
+
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    tag.write_to_path(&*song, id3::Version::Id3v23).unwrap();
+    let mut tag = read_tag(&song);
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json.get("data").unwrap().get("chapters").unwrap(), &json!([
+        {
+            "id": "intro", "start_time": 0, "end_time": 60000, "start_offset": null, "end_offset": null,
+            "title": "Intro", "url": null, "image": null,
+        },
+        {
+            "id": "news", "start_time": 60000, "end_time": 140539, "start_offset": 1200000, "end_offset": null,
+            "title": "News", "url": "https://example.com/news",
+            "image": { "mime_type": "image/png", "type": "other", "description": "Headlines", "size": 16 },
+        },
+    ]));
+    assert_eq!(json.get("data").unwrap().get("tables_of_contents").unwrap(), &json!([
+        { "id": "toc", "top_level": true, "ordered": true, "elements": ["intro", "news"], "title": "Episode" },
+    ]));
+
+    // Writing back what was read keeps the image, even without its data. Removed chapters are
+    // dropped from the table of contents.
+    let mut chapters = json.get("data").unwrap().get("chapters").unwrap().clone();
+    chapters.as_array_mut().unwrap().remove(0);
+    chapters[0]["title"] = json!(null);
+
+    let new_data = json!({ "chapters": chapters }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+
+    let json = read_from_tag(&tag, &Args { with_covers: true, ..Args::default() });
+    let chapter = &json.get("data").unwrap().get("chapters").unwrap()[0];
+    assert_eq!(chapter.get("title").unwrap(), &json!(null));
+    assert_eq!(chapter.get("image").unwrap().get("data").unwrap(), &json!(image));
+    assert_eq!(json.get("data").unwrap().get("tables_of_contents").unwrap()[0].get("elements").unwrap(), &json!(["news"]));
+
+    let invalid_data = [
+        json!({ "chapters": [{ "id": "a", "start_time": 10 }] }),
+        json!({ "chapters": [{ "id": "a", "start_time": 10, "end_time": 5 }] }),
+        json!({ "chapters": [{ "id": "a", "start_time": 0, "end_time": 5 }, { "id": "a", "start_time": 5, "end_time": 9 }] }),
+        json!({ "chapters": [{ "id": "a", "start_time": 0, "end_time": 5, "image": { "type": "other" } }] }),
+        json!({ "tables_of_contents": [{ "id": "toc", "elements": ["missing"] }] }),
+        json!({ "tables_of_contents": [{ "id": "toc", "elements": ["toc"] }] }),
+        json!({ "tables_of_contents": [{ "id": "news", "elements": [] }] }),
+        json!({ "tables_of_contents": [{ "id": "a", "top_level": true, "elements": [] }, { "id": "b", "top_level": true, "elements": [] }] }),
+        json!({ "chapters": null }),
+    ];
+    for data in invalid_data {
+        let data = data.as_object().unwrap().clone();
+        assert!(write_to_tag(&data, &mut tag, &args).is_err(), "Expected an error for {:?}", data);
+    }
+
+    let new_data = json!({ "chapters": [], "tables_of_contents": [] }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+    assert_eq!(tag.frames().filter(|f| f.id() == "CHAP" || f.id() == "CTOC").count(), 0);
+}
+
+#[test]
+fn test_remove_chapter_from_read_output() {
+    let args = Args { with_covers: true, ..Args::default() };
+    let song = Fixture::copy("attempt_1.mp3");
+    let mut tag = read_tag(&song);
+
+    let new_data = json!({
+        "chapters": [
+            { "id": "intro", "start_time": 0, "end_time": 60000, "title": "Intro" },
+            { "id": "news", "start_time": 60000, "end_time": 140539, "title": "News" },
+        ],
+        "tables_of_contents": [
+            { "id": "toc", "top_level": true, "ordered": true, "elements": ["intro", "news"] },
+        ],
+    }).as_object().unwrap().clone();
+    write_to_tag(&new_data, &mut tag, &args).unwrap();
+
+    // The tables of contents in the output still refer to the removed chapter
+    let mut json = read_from_tag(&tag, &args);
+    json["data"]["chapters"].as_array_mut().unwrap().remove(1);
+    write_to_tag(json.as_object().unwrap(), &mut tag, &args).unwrap();
+
+    let json = read_from_tag(&tag, &args);
+    assert_eq!(json["data"]["chapters"].as_array().unwrap().len(), 1);
+    assert_eq!(json["data"]["tables_of_contents"][0]["elements"], json!(["intro"]));
+
+    // A new reference to a chapter that was never there is still an error
+    let mut json = read_from_tag(&tag, &args);
+    json["data"]["tables_of_contents"][0]["elements"] = json!(["intro", "missing"]);
+    assert!(write_to_tag(json.as_object().unwrap(), &mut tag, &args).is_err());
+}
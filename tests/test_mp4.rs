@@ -0,0 +1,221 @@
+use std::fs;
+
+use base64::prelude::*;
+use id3::TagLike;
+
+use id3_json::input::Args;
+use id3_json::json;
+use id3_json::mp4::*;
+
+mod support;
+use support::fixture::Fixture;
+
+const AUDIO: &[u8] = b"pretend these are AAC frames";
+
+fn atom(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    bytes.extend(kind);
+    bytes.extend(payload);
+    bytes
+}
+
+fn data(data_type: u8, value: &[u8]) -> Vec<u8> {
+    let mut payload = vec![0, 0, 0, data_type, 0, 0, 0, 0];
+    payload.extend(value);
+    atom(b"data", &payload)
+}
+
+fn freeform(name: &str, value: &str) -> Vec<u8> {
+    let mut payload = atom(b"mean", &[&[0; 4], b"com.apple.iTunes".as_slice()].concat());
+    payload.extend(atom(b"name", &[&[0; 4], name.as_bytes()].concat()));
+    payload.extend(data(1, value.as_bytes()));
+    atom(b"----", &payload)
+}
+
+// An M4A file with the moov atom before the audio data, so the chunk offset in stco needs to
+// change when the metadata does.
+fn mp4_bytes(ilst: &[u8]) -> Vec<u8> {
+    let ftyp = atom(b"ftyp", b"M4A \x00\x00\x00\x00M4A mp42isom");
+    let hdlr = atom(b"hdlr", &[&[0; 8], b"mdirappl".as_slice(), &[0; 9]].concat());
+    let meta = atom(b"meta", &[&[0; 4], hdlr.as_slice(), &atom(b"ilst", ilst)].concat());
+    let udta = atom(b"udta", &meta);
+
+    let moov_size = |stco: &[u8]| 8 + atom(b"trak", &atom(b"mdia", &atom(b"minf", &atom(b"stbl", stco)))).len() + udta.len();
+    let stco_payload = |offset: u32| [&[0, 0, 0, 0, 0, 0, 0, 1], offset.to_be_bytes().as_slice()].concat();
+
+    // The audio starts after the moov atom, and the mdat header
+    let audio_offset = ftyp.len() + moov_size(&atom(b"stco", &stco_payload(0))) + 8;
+    let stco = atom(b"stco", &stco_payload(audio_offset as u32));
+    let trak = atom(b"trak", &atom(b"mdia", &atom(b"minf", &atom(b"stbl", &stco))));
+    let moov = atom(b"moov", &[trak, udta].concat());
+
+    [ftyp, moov, atom(b"mdat", AUDIO)].concat()
+}
+
+fn mp4_fixture(ilst: &[u8]) -> Fixture {
+    let song = Fixture::blank("song.m4a");
+    fs::write(&*song, mp4_bytes(ilst)).unwrap();
+    song
+}
+
+// Follows the chunk offset in the file to check that it still points to the audio
+fn audio_at_chunk_offset(bytes: &[u8]) -> &[u8] {
+    let stco = bytes.windows(4).position(|w| w == b"stco").unwrap();
+    let offset = u32::from_be_bytes(bytes[(stco + 12)..(stco + 16)].try_into().unwrap()) as usize;
+    &bytes[offset..(offset + AUDIO.len())]
+}
+
+#[test]
+fn test_read_itunes_metadata() {
+    let song = mp4_fixture(&[
+        atom(b"\xA9nam", &data(1, b"Elevator Music Attempt #1")),
+        atom(b"\xA9ART", &data(1, b"Christiaan Bakker")),
+        atom(b"\xA9alb", &data(1, b"Album from A to Z")),
+        atom(b"aART", &data(1, b"Jay-Z")),
+        atom(b"\xA9day", &data(1, b"2011-09-07T07:00:00Z")),
+        atom(b"trkn", &data(0, &[0, 0, 0, 3, 0, 12, 0, 0])),
+        atom(b"disk", &data(0, &[0, 0, 0, 1, 0, 2])),
+        atom(b"gnre", &data(0, &[0, 53])),
+        atom(b"cpil", &data(21, &[1])),
+        atom(b"tmpo", &data(21, &[0, 120])),
+        atom(b"\xA9cmt", &data(1, b"Attribution 3.0")),
+        atom(b"covr", &data(14, b"not really a PNG")),
+        freeform("ISRC", "NLA321100001"),
+        freeform("iTunSMPB", "00000000 00000840"),
+    ].concat());
+
+    assert_eq!(audio_at_chunk_offset(&fs::read(&*song).unwrap()), AUDIO);
+
+    let tag = read_tag(&song).unwrap();
+    let json = json::read_from_tag(&tag, &Args::default());
+    let data = json.get("data").unwrap();
+
+    assert_eq!(data.get("title").unwrap(), "Elevator Music Attempt #1");
+    assert_eq!(data.get("artist").unwrap(), "Christiaan Bakker");
+    assert_eq!(data.get("album").unwrap(), "Album from A to Z");
+    assert_eq!(data.get("album_artist").unwrap(), "Jay-Z");
+    assert_eq!(data.get("date").unwrap(), "2011-09-07T07:00:00");
+    assert_eq!(data.get("track").unwrap(), 3);
+    assert_eq!(data.get("track_total").unwrap(), 12);
+    assert_eq!(data.get("disc").unwrap(), 1);
+    assert_eq!(data.get("disc_total").unwrap(), 2);
+    assert_eq!(data.get("genre").unwrap(), "Electronic");
+    assert_eq!(data.get("compilation").unwrap(), true);
+    assert_eq!(data.get("bpm").unwrap(), 120);
+    assert_eq!(data.get("comment").unwrap(), "Attribution 3.0");
+    assert_eq!(data.get("isrc").unwrap(), "NLA321100001");
+
+    let cover = &data.get("covers").unwrap()[0];
+    assert_eq!(cover.get("mime_type").unwrap(), "image/png");
+    assert_eq!(cover.get("type").unwrap(), "front");
+
+    let extended_text = tag.extended_texts().next().unwrap();
+    assert_eq!(extended_text.description, "iTunSMPB");
+}
+
+#[test]
+fn test_read_nul_in_itunes_metadata() {
+    let song = mp4_fixture(&[
+        atom(b"\xA9nam", &data(1, b"a\x00b")),
+        atom(b"\xA9ART", &data(1, b"Christiaan Bakker\x00")),
+        freeform("CONDUCTOR", "Someone\u{0000}Someone else"),
+    ].concat());
+
+    // A NUL separates values, like it does in ID3v2.4
+    let tag = read_tag(&song).unwrap();
+    let json = json::read_from_tag(&tag, &Args::default());
+    let data = json.get("data").unwrap();
+
+    assert_eq!(data.get("title").unwrap(), &serde_json::json!(["a", "b"]));
+    assert_eq!(data.get("artist").unwrap(), "Christiaan Bakker");
+    assert_eq!(data.get("conductor").unwrap(), &serde_json::json!(["Someone", "Someone else"]));
+}
+
+#[test]
+fn test_write_itunes_metadata() {
+    let song = mp4_fixture(&[
+        atom(b"\xA9nam", &data(1, b"Old title")),
+        atom(b"stik", &data(21, &[1])),
+    ].concat());
+
+    let mut tag = read_tag(&song).unwrap();
+    let input = serde_json::json!({
+        "data": {
+            "title": "New title",
+            "artist": "Christiaan Bakker",
+            "track": 3,
+            "disc_total": 2,
+            "mood": "Calm",
+            "tagging_date": "2024-01-01",
+            "covers": [{
+                "mime_type": "image/jpeg",
+                "type": "front",
+                "description": "",
+                "data": BASE64_STANDARD.encode(b"not really a JPEG"),
+            }],
+        }
+    });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+
+    let skipped = write_tag(&song, &tag).unwrap();
+    assert_eq!(skipped, vec!["TDTG"]);
+
+    // The moov atom grew, so the audio moved, and the chunk offset with it:
+    let bytes = fs::read(&*song).unwrap();
+    assert!(bytes.ends_with(AUDIO));
+    assert_eq!(audio_at_chunk_offset(&bytes), AUDIO);
+
+    // The "stik" item isn't mapped to anything, but it's still there:
+    assert!(bytes.windows(4).any(|w| w == b"stik"));
+
+    let tag = read_tag(&song).unwrap();
+    assert_eq!(tag.title(), Some("New title"));
+    assert_eq!(tag.artist(), Some("Christiaan Bakker"));
+    assert_eq!(tag.track(), Some(3));
+    assert_eq!(tag.get("TPOS").unwrap().content().text(), Some("/2"));
+    assert_eq!(tag.get("TMOO").unwrap().content().text(), Some("Calm"));
+    assert_eq!(tag.pictures().next().unwrap().data, b"not really a JPEG");
+
+    // Removing fields shrinks the moov atom again
+    let mut tag = read_tag(&song).unwrap();
+    let input = serde_json::json!({ "data": { "covers": [], "mood": null } });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+    write_tag(&song, &tag).unwrap();
+
+    let bytes = fs::read(&*song).unwrap();
+    assert_eq!(audio_at_chunk_offset(&bytes), AUDIO);
+    assert_eq!(read_tag(&song).unwrap().pictures().count(), 0);
+}
+
+#[test]
+fn test_write_without_metadata() {
+    let ftyp = atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+    let moov = atom(b"moov", &atom(b"mvhd", &[0; 100]));
+    let song = Fixture::blank("song.m4a");
+    fs::write(&*song, [ftyp, moov, atom(b"mdat", AUDIO)].concat()).unwrap();
+
+    let mut tag = read_tag(&song).unwrap();
+    assert_eq!(tag.frames().count(), 0);
+
+    tag.set_title("Brand new");
+    write_tag(&song, &tag).unwrap();
+    assert_eq!(read_tag(&song).unwrap().title(), Some("Brand new"));
+}
+
+#[test]
+fn test_invalid_mp4() {
+    let song = Fixture::blank("song.m4a");
+    fs::write(&*song, atom(b"ftyp", b"M4A ")).unwrap();
+
+    let error = read_tag(&song).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid MP4 file: no \"moov\" atom");
+
+    // A 64-bit size that goes past the end of memory, not just the end of the file
+    let mut bytes = atom(b"ftyp", b"M4A ");
+    bytes.extend(b"\x00\x00\x00\x01moov");
+    bytes.extend(u64::MAX.to_be_bytes());
+    fs::write(&*song, bytes).unwrap();
+
+    let error = read_tag(&song).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid MP4 file: atom at byte 12 is cut off");
+}
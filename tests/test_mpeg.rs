@@ -0,0 +1,169 @@
+use std::fs;
+
+use id3_json::mpeg::*;
+
+mod support;
+use support::fixture::Fixture;
+
+// MPEG 1 Layer III, 128kbps, 44100Hz, joint stereo, without and with padding
+const HEADER:        [u8; 4] = [0xFF, 0xFB, 0x90, 0x44];
+const PADDED_HEADER: [u8; 4] = [0xFF, 0xFB, 0x92, 0x44];
+
+fn frame(header: [u8; 4]) -> Vec<u8> {
+    let size = FrameHeader::parse(&header).unwrap().frame_size();
+    let mut frame = header.to_vec();
+    frame.resize(size, 0x55);
+    frame
+}
+
+#[test]
+fn test_analyze_xing_header() {
+    let fixture = Fixture::copy("attempt_1.mp3");
+    let bytes = fs::read(&*fixture).unwrap();
+    let info = analyze(&bytes).unwrap();
+
+    assert_eq!(info.first_frame.version, Version::Mpeg1);
+    assert_eq!(info.first_frame.layer, 3);
+    assert_eq!(info.first_frame.sample_rate, 44100);
+    assert_eq!(info.vbr_header, Some("Xing"));
+    assert_eq!(info.frames, 5380);
+    assert_eq!(info.bitrate_mode, "VBR");
+
+    let json = info.to_json();
+    assert_eq!(json.get("channel_mode").unwrap(), "joint stereo");
+    assert_eq!(json.get("duration").unwrap(), 140.539);
+    assert_eq!(json.get("bitrate").unwrap(), 185);
+
+    let encoder = json.get("encoder").unwrap();
+    assert_eq!(encoder.get("version").unwrap(), "LAME3.98r");
+    assert_eq!(encoder.get("vbr_method").unwrap(), "vbr-mtrh");
+    assert_eq!(encoder.get("delay").unwrap(), 576);
+}
+
+#[test]
+fn test_analyze_without_vbr_header() {
+    let mut bytes = Vec::new();
+    id3::Tag::new().write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+    // Some junk before the first frame, that looks like a sync word
+    bytes.extend([0xFF, 0xFB, 0x00]);
+    for i in 0..10 {
+        bytes.extend(frame(if i % 3 == 0 { PADDED_HEADER } else { HEADER }));
+    }
+    bytes.extend(b"TAG");
+    bytes.resize(bytes.len() + 125, 0);
+
+    let info = analyze(&bytes).unwrap();
+    assert_eq!(info.vbr_header, None);
+    assert_eq!(info.frames, 10);
+    assert_eq!(info.bitrate_mode, "CBR");
+    assert_eq!(info.bitrate(), 128);
+
+    let json = info.to_json();
+    assert_eq!(json.get("duration").unwrap(), 0.261);
+    assert_eq!(json.get("encoder").unwrap(), &serde_json::Value::Null);
+}
+
+#[test]
+fn test_audio_range() {
+    let mut bytes = Vec::new();
+    let mut tag = id3::Tag::new();
+    id3::TagLike::set_title(&mut tag, "Title");
+    tag.write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+    let start = bytes.len();
+
+    bytes.extend(frame(HEADER));
+    let end = bytes.len();
+
+    bytes.extend(b"TAG");
+    bytes.resize(bytes.len() + 125, 0);
+
+    assert_eq!(audio_range(&bytes), start..end);
+    assert_eq!(audio_range(&frame(HEADER)), 0..417);
+    assert!(analyze(b"no audio here").is_none());
+}
+
+#[test]
+fn test_verify_fixture() {
+    let fixture = Fixture::copy("attempt_1.mp3");
+    let bytes = fs::read(&*fixture).unwrap();
+    let verification = verify(&bytes);
+
+    assert_eq!(verification.errors, vec![]);
+    assert_eq!(verification.frames, 5381);
+    assert!(verification.is_valid());
+}
+
+#[test]
+fn test_verify_errors() {
+    let stream = |parts: &[Vec<u8>]| parts.concat();
+
+    let valid = stream(&[frame(HEADER), frame(HEADER), frame(HEADER)]);
+    assert_eq!(verify(&valid), Verification { frames: 3, errors: vec![] });
+
+    let with_garbage = stream(&[frame(HEADER), b"junk".to_vec(), frame(HEADER), frame(HEADER)]);
+    assert_eq!(verify(&with_garbage).errors, vec![
+        StreamError::Garbage { offset: 417, size: 4 },
+    ]);
+
+    let cut_short = stream(&[frame(HEADER), frame(HEADER)[..100].to_vec(), frame(HEADER), frame(HEADER)]);
+    assert_eq!(verify(&cut_short).errors, vec![
+        StreamError::SyncError { offset: 417, expected_size: 417, actual_size: 100 },
+    ]);
+
+    let truncated = stream(&[frame(HEADER), frame(HEADER), frame(HEADER)[..200].to_vec()]);
+    assert_eq!(verify(&truncated), Verification {
+        frames: 3,
+        errors: vec![StreamError::TruncatedFrame { offset: 834, expected_size: 417, actual_size: 200 }],
+    });
+
+    assert_eq!(verify(b"no audio here").errors, vec![StreamError::NoFrames]);
+}
+
+#[test]
+fn test_verify_frame_count() {
+    let fixture = Fixture::copy("attempt_1.mp3");
+    let bytes = fs::read(&*fixture).unwrap();
+
+    // Cut off the second half of the file, along with the ID3v1 tag, at a frame boundary
+    let range = audio_range(&bytes);
+    let mut position = range.start;
+    for _ in 0..3000 {
+        position += FrameHeader::parse(&bytes[position..]).unwrap().frame_size();
+    }
+
+    let verification = verify(&bytes[..position]);
+    assert_eq!(verification.frames, 3000);
+    assert_eq!(verification.errors, vec![
+        StreamError::FrameCountMismatch { header: "Xing", expected: 5380, actual: 2999 },
+    ]);
+
+    let json = verification.to_json();
+    assert_eq!(json.get("valid").unwrap(), false);
+    assert_eq!(json.get("errors").unwrap()[0].get("type").unwrap(), "frame_count_mismatch");
+}
+
+#[test]
+fn test_audio_hash_ignores_tags() {
+    let fixture = Fixture::copy("attempt_1.mp3");
+    let original_hash = audio_hash(&fs::read(&*fixture).unwrap()).unwrap();
+    assert_eq!(original_hash.len(), 64);
+
+    let mut tag = id3::Tag::read_from_path(&*fixture).unwrap();
+    id3::TagLike::set_title(&mut tag, "A much longer title than the one the file had before");
+    id3::TagLike::remove_all_pictures(&mut tag);
+    tag.write_to_path(&*fixture, id3::Version::Id3v23).unwrap();
+
+    let mut bytes = fs::read(&*fixture).unwrap();
+    assert_eq!(audio_hash(&bytes).unwrap(), original_hash);
+
+    // Without the ID3v1 tag at the end
+    bytes.truncate(bytes.len() - 128);
+    assert_eq!(audio_hash(&bytes).unwrap(), original_hash);
+
+    // Changing a single byte of audio changes the hash
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 1;
+    assert_ne!(audio_hash(&bytes).unwrap(), original_hash);
+
+    assert_eq!(audio_hash(b"no audio here"), None);
+}
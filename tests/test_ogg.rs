@@ -0,0 +1,202 @@
+use std::fs;
+
+use base64::prelude::*;
+use id3::TagLike;
+
+use id3_json::input::Args;
+use id3_json::json;
+use id3_json::ogg::*;
+use id3_json::vorbis;
+
+mod support;
+use support::fixture::Fixture;
+
+const SERIAL: u32 = 0x1234_5678;
+
+// Bit by bit, to check the table-based version in the crate
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0_u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// A page with a single packet that fits in it
+fn page(header_type: u8, granule_position: u64, sequence: u32, packet: &[u8]) -> Vec<u8> {
+    let mut segments = vec![255; packet.len() / 255];
+    segments.push((packet.len() % 255) as u8);
+
+    let mut bytes = b"OggS\x00".to_vec();
+    bytes.push(header_type);
+    bytes.extend(granule_position.to_le_bytes());
+    bytes.extend(SERIAL.to_le_bytes());
+    bytes.extend(sequence.to_le_bytes());
+    bytes.extend([0; 4]);
+    bytes.push(segments.len() as u8);
+    bytes.extend(segments);
+    bytes.extend(packet);
+
+    let checksum = crc32(&bytes);
+    bytes[22..26].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+fn comment_packet(prefix: &[u8], comments: &[(&str, &str)], framing_bit: bool) -> Vec<u8> {
+    let comments = comments.iter().
+        map(|(k, v)| (k.to_string(), v.to_string())).
+        collect::<Vec<_>>();
+
+    let mut packet = prefix.to_vec();
+    packet.extend(vorbis::encode_comments("Xiph.Org libVorbis I 20200704", &comments));
+    if framing_bit {
+        packet.push(1);
+    }
+    packet
+}
+
+fn vorbis_bytes(comments: &[(&str, &str)]) -> Vec<u8> {
+    let mut bytes = page(0x02, 0, 0, b"\x01vorbis pretend identification header");
+
+    // The comment and setup headers share a page
+    let comments = comment_packet(b"\x03vorbis", comments, true);
+    let setup = b"\x05vorbis pretend setup header";
+    let mut segments = vec![255; comments.len() / 255];
+    segments.push((comments.len() % 255) as u8);
+    segments.push(setup.len() as u8);
+
+    let mut second_page = b"OggS\x00\x00".to_vec();
+    second_page.extend(0_u64.to_le_bytes());
+    second_page.extend(SERIAL.to_le_bytes());
+    second_page.extend(1_u32.to_le_bytes());
+    second_page.extend([0; 4]);
+    second_page.push(segments.len() as u8);
+    second_page.extend(segments);
+    second_page.extend(comments);
+    second_page.extend(setup);
+    let checksum = crc32(&second_page);
+    second_page[22..26].copy_from_slice(&checksum.to_le_bytes());
+    bytes.extend(second_page);
+
+    bytes.extend(page(0x00, 1024, 2, b"pretend audio"));
+    bytes.extend(page(0x04, 2048, 3, b"more pretend audio"));
+    bytes
+}
+
+fn opus_bytes(comments: &[(&str, &str)]) -> Vec<u8> {
+    let mut bytes = page(0x02, 0, 0, b"OpusHead pretend identification header");
+    bytes.extend(page(0x00, 0, 1, &comment_packet(b"OpusTags", comments, false)));
+    bytes.extend(page(0x04, 960, 2, b"pretend audio"));
+    bytes
+}
+
+// Checks the checksums, and returns the sequence numbers and packet data of each page
+fn read_pages(bytes: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut pages = Vec::new();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        assert_eq!(&bytes[position..(position + 4)], b"OggS");
+
+        let segment_count = bytes[position + 26] as usize;
+        let segments = &bytes[(position + 27)..(position + 27 + segment_count)];
+        let end = position + 27 + segment_count + segments.iter().map(|s| *s as usize).sum::<usize>();
+
+        let mut page = bytes[position..end].to_vec();
+        let checksum = u32::from_le_bytes(page[22..26].try_into().unwrap());
+        page[22..26].copy_from_slice(&[0; 4]);
+        assert_eq!(crc32(&page), checksum, "Checksum of page at byte {}", position);
+
+        let sequence = u32::from_le_bytes(page[18..22].try_into().unwrap());
+        pages.push((sequence, page[(27 + segment_count)..].to_vec()));
+        position = end;
+    }
+
+    pages
+}
+
+#[test]
+fn test_read_vorbis_comments() {
+    let song = Fixture::blank("song.ogg");
+    fs::write(&*song, vorbis_bytes(&[
+        ("TITLE", "Elevator Music Attempt #1"),
+        ("ARTIST", "Christiaan Bakker"),
+        ("TRACKNUMBER", "3/12"),
+    ])).unwrap();
+
+    let tag = read_tag(&song).unwrap();
+    let json = json::read_from_tag(&tag, &Args::default());
+    let data = json.get("data").unwrap();
+
+    assert_eq!(data.get("title").unwrap(), "Elevator Music Attempt #1");
+    assert_eq!(data.get("artist").unwrap(), "Christiaan Bakker");
+    assert_eq!(data.get("track").unwrap(), 3);
+    assert_eq!(data.get("track_total").unwrap(), 12);
+}
+
+#[test]
+fn test_write_vorbis_comments() {
+    let song = Fixture::blank("song.ogg");
+    fs::write(&*song, vorbis_bytes(&[("TITLE", "Old title")])).unwrap();
+
+    let mut tag = read_tag(&song).unwrap();
+    let input = serde_json::json!({ "data": { "title": "New title", "album": "Echoes From The Past" } });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+    assert_eq!(write_tag(&song, &tag).unwrap(), Vec::<String>::new());
+
+    let pages = read_pages(&fs::read(&*song).unwrap());
+    assert_eq!(pages.iter().map(|(s, _)| *s).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    assert!(pages[1].1.ends_with(b"\x05vorbis pretend setup header"));
+    assert_eq!(pages[3].1, b"more pretend audio");
+
+    let tag = read_tag(&song).unwrap();
+    assert_eq!(tag.title(), Some("New title"));
+    assert_eq!(tag.album(), Some("Echoes From The Past"));
+}
+
+#[test]
+fn test_write_opus_cover() {
+    let song = Fixture::blank("song.opus");
+    fs::write(&*song, opus_bytes(&[("TITLE", "Short")])).unwrap();
+    // More than a page's worth of data
+    let image = vec![0xAB; 70_000];
+
+    let mut tag = read_tag(&song).unwrap();
+    let input = serde_json::json!({
+        "data": {
+            "covers": [{ "mime_type": "image/jpeg", "data": BASE64_STANDARD.encode(&image) }],
+        }
+    });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+    write_tag(&song, &tag).unwrap();
+
+    // The cover doesn't fit in a single page, so the audio page is renumbered
+    let pages = read_pages(&fs::read(&*song).unwrap());
+    assert!(pages.len() > 3);
+    assert_eq!(pages.iter().map(|(s, _)| *s).collect::<Vec<_>>(), (0..(pages.len() as u32)).collect::<Vec<_>>());
+    assert_eq!(pages.last().unwrap().1, b"pretend audio");
+
+    let tag = read_tag(&song).unwrap();
+    assert_eq!(tag.title(), Some("Short"));
+    assert_eq!(tag.pictures().next().unwrap().data, image);
+
+    // And back down to a single page
+    let mut tag = read_tag(&song).unwrap();
+    let input = serde_json::json!({ "data": { "covers": [] } });
+    json::write_to_tag(input.as_object().unwrap(), &mut tag, &Args::default()).unwrap();
+    write_tag(&song, &tag).unwrap();
+
+    let pages = read_pages(&fs::read(&*song).unwrap());
+    assert_eq!(pages.iter().map(|(s, _)| *s).collect::<Vec<_>>(), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_unsupported_codec() {
+    let song = Fixture::blank("song.ogg");
+    fs::write(&*song, page(0x02, 0, 0, b"\x7fFLAC pretend header")).unwrap();
+
+    assert_eq!(read_tag(&song).unwrap_err().to_string(), "Unsupported container: Ogg (FLAC)");
+}
@@ -0,0 +1,83 @@
+use id3::TagLike;
+
+use id3_json::replaygain::*;
+
+mod support;
+use support::fixture::Fixture;
+
+#[test]
+fn test_analyze_path() {
+    let fixture = Fixture::copy("attempt_1.mp3");
+    let loudness = analyze_path(&fixture).unwrap();
+    let gain = Gain::from_loudness(&loudness).unwrap();
+
+    assert!((gain.loudness - -16.7).abs() < 0.05, "{}", gain.loudness);
+    assert!((gain.gain - -1.3).abs() < 0.05);
+    assert!((gain.peak - 0.8919).abs() < 0.001);
+
+    // An album of two copies of the same track is just as loud
+    let album = Gain::from_loudness(&Loudness::combine(&[loudness.clone(), loudness])).unwrap();
+    assert!((album.gain - gain.gain).abs() < 0.001);
+}
+
+#[test]
+fn test_write_to_tag() {
+    let track = Gain { loudness: -14.0, gain: -4.0, peak: 0.5 };
+    let album = Gain { loudness: -15.0, gain: -3.0, peak: 0.75 };
+
+    let mut tag = id3::Tag::new();
+    assert!(write_to_tag(&mut tag, &track, Some(&album), id3::Version::Id3v24));
+    // Writing again replaces the frames
+    assert!(write_to_tag(&mut tag, &track, Some(&album), id3::Version::Id3v24));
+
+    let texts = tag.extended_texts().
+        map(|t| (t.description.as_str(), t.value.as_str())).
+        collect::<Vec<_>>();
+    assert_eq!(texts, vec![
+        ("REPLAYGAIN_TRACK_GAIN", "-4.00 dB"),
+        ("REPLAYGAIN_TRACK_PEAK", "0.500000"),
+        ("REPLAYGAIN_ALBUM_GAIN", "-3.00 dB"),
+        ("REPLAYGAIN_ALBUM_PEAK", "0.750000"),
+    ]);
+    assert_eq!(tag.frames().filter(|f| f.id() == "RVA2").count(), 2);
+
+    let json = read_from_tag(&tag);
+    assert_eq!(json.get("track_gain").unwrap(), -4.0);
+    assert_eq!(json.get("album_peak").unwrap(), 0.75);
+
+    // Track mode keeps the album values
+    let new_track = Gain { loudness: -20.0, gain: 2.0, peak: 0.25 };
+    assert!(write_to_tag(&mut tag, &new_track, None, id3::Version::Id3v24));
+
+    let json = read_from_tag(&tag);
+    assert_eq!(json.get("track_gain").unwrap(), 2.0);
+    assert_eq!(json.get("album_gain").unwrap(), -3.0);
+    assert_eq!(tag.frames().filter(|f| f.id() == "RVA2").count(), 2);
+}
+
+#[test]
+fn test_write_to_id3v23_tag() {
+    let track = Gain { loudness: -14.0, gain: -4.0, peak: 0.5 };
+
+    let mut tag = id3::Tag::with_version(id3::Version::Id3v23);
+    assert!(!write_to_tag(&mut tag, &track, None, id3::Version::Id3v23));
+
+    assert_eq!(tag.frames().filter(|f| f.id() == "RVA2").count(), 0);
+    assert_eq!(tag.extended_texts().count(), 2);
+}
+
+#[test]
+fn test_read_from_tag() {
+    let mut tag = id3::Tag::new();
+    assert_eq!(read_from_tag(&tag), serde_json::Value::Null);
+
+    // Other programs write the description in lowercase, and might skip the unit
+    tag.add_frame(id3::frame::ExtendedText {
+        description: String::from("replaygain_track_gain"),
+        value:       String::from("+1.5"),
+    });
+
+    let json = read_from_tag(&tag);
+    assert_eq!(json.get("track_gain").unwrap(), 1.5);
+    assert_eq!(json.get("track_peak").unwrap(), &serde_json::Value::Null);
+}